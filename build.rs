@@ -1,7 +1,66 @@
-use std::error::Error;
+use std::{env, error::Error, path::PathBuf, process::Command};
+
+// Messages are also rendered as JSON for tooling, in serde's default representation of
+// the generated types with camelCase field names, oneofs flattened into their message and
+// enums by name. This is not the proto3 JSON mapping: 64-bit integers are numbers rather
+// than strings, and the fields holding their default value are rendered.
+const SERDE_DERIVE: &str = "#[derive(serde::Serialize, serde::Deserialize)]";
+const SKIP_EMPTY_MAP: &str = "#[serde(skip_serializing_if = \"::std::collections::HashMap::is_empty\")]";
+const SKIP_NONE: &str = "#[serde(skip_serializing_if = \"Option::is_none\")]";
+const ONEOF: &str = "#[serde(flatten, deserialize_with = \"crate::json::oneof::deserialize\")]";
 
 fn main() -> Result<(), Box<dyn Error>> {
-    prost_build::compile_protos(&["proto/messages.proto"], &["proto/"])?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    prost_build::Config::new()
+        // Embedded in the crate and served to tooling that decodes the traffic generically.
+        .file_descriptor_set_path(out_dir.join("messages_descriptor.bin"))
+        .message_attribute(".", SERDE_DERIVE)
+        .message_attribute(".", "#[serde(default, rename_all = \"camelCase\")]")
+        .enum_attribute(".messages.ClientMessage.message", SERDE_DERIVE)
+        .enum_attribute(".messages.ClientMessage.message", "#[serde(rename_all = \"camelCase\")]")
+        .enum_attribute(".messages.ServerMessage.message", SERDE_DERIVE)
+        .enum_attribute(".messages.ServerMessage.message", "#[serde(rename_all = \"camelCase\")]")
+        .enum_attribute(".messages.AddResponse.outcome", SERDE_DERIVE)
+        .enum_attribute(".messages.AddResponse.outcome", "#[serde(rename_all = \"camelCase\")]")
+        .enum_attribute(".messages.EvalResponse.outcome", SERDE_DERIVE)
+        .enum_attribute(".messages.EvalResponse.outcome", "#[serde(rename_all = \"camelCase\")]")
+        .field_attribute("ClientMessage.message", ONEOF)
+        .field_attribute("ServerMessage.message", ONEOF)
+        .field_attribute("AddResponse.outcome", ONEOF)
+        .field_attribute("EvalResponse.outcome", ONEOF)
+        .field_attribute(".messages.ClientMessage.metadata", SKIP_EMPTY_MAP)
+        .field_attribute(".messages.ServerMessage.metadata", SKIP_EMPTY_MAP)
+        .field_attribute(".messages.ClientMessage.request_id", SKIP_NONE)
+        .field_attribute(".messages.ServerMessage.request_id", SKIP_NONE)
+        .field_attribute(".messages.ErrorMessage.session_summary", SKIP_NONE)
+        .field_attribute(".messages.ByeMessage.session_summary", SKIP_NONE)
+        .field_attribute(
+            ".messages.ErrorMessage.shutdown_reason",
+            "#[serde(with = \"crate::json::shutdown_reason\")]",
+        )
+        .field_attribute(".messages.ErrorMessage.code", "#[serde(with = \"crate::json::error_code\")]")
+        .field_attribute(".messages.AddError.code", "#[serde(with = \"crate::json::add_error_code\")]")
+        .field_attribute(".messages.EvalError.code", "#[serde(with = \"crate::json::eval_error_code\")]")
+        .field_attribute(".messages.UpgradeRequest.transport", "#[serde(with = \"crate::json::transport\")]")
+        .field_attribute(".messages.UpgradeResponse.transport", "#[serde(with = \"crate::json::transport\")]")
+        .compile_protos(&["proto/messages.proto"], &["proto/"])?;
+
+    // Reported by the server on start, see `startup::GIT_HASH`.
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+    // Any directive disables the default of rerunning on every change of the package.
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto/messages.proto");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
 
     Ok(())
 }
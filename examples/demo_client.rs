@@ -0,0 +1,52 @@
+//! Makes a round trip of each kind of request to a server, by default a demo server
+//! started in the same process.
+//!
+//! ```sh
+//! cargo run --example demo_client [-- <address>]
+//! ```
+
+use embedded_recruitment_task::{
+    client::Client,
+    message::{client_message, AddRequest, ByeMessage, EchoMessage, EvalRequest},
+    server::Server,
+};
+use std::{env, io, net::SocketAddr, sync::Arc, thread};
+
+fn main() -> io::Result<()> {
+    // Without an address, serve the requests from a demo server in the background.
+    let (addr, server) = match env::args().nth(1) {
+        Some(addr) => {
+            let addr: SocketAddr = addr.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            (addr, None)
+        }
+        None => {
+            let server = Arc::new(Server::demo()?);
+            let addr = server.local_addr()?;
+            let background = server.clone();
+            thread::spawn(move || background.run());
+            (addr, Some(server))
+        }
+    };
+
+    let mut client = Client::demo(addr)?;
+    let requests = [
+        client_message::Message::EchoMessage(EchoMessage {
+            content: "Hello, World!".to_string(),
+        }),
+        client_message::Message::AddRequest(AddRequest { a: 2, b: 40 }),
+        client_message::Message::EvalRequest(EvalRequest {
+            expression: "3 * (a + b)".to_string(),
+            vars: [("a".to_string(), 4), ("b".to_string(), 10)].into(),
+        }),
+        client_message::Message::ByeMessage(ByeMessage::default()),
+    ];
+    for request in requests {
+        println!("> {:?}", request);
+        println!("< {:?}", client.call_message(request)?.message);
+    }
+
+    if let Some(server) = server {
+        server.stop();
+    }
+    Ok(())
+}
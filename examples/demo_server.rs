@@ -0,0 +1,16 @@
+//! Runs a demo server until it is interrupted.
+//!
+//! ```sh
+//! cargo run --example demo_server
+//! ```
+//!
+//! Then run `cargo run --example demo_client -- <address>` with the address it logged.
+
+use embedded_recruitment_task::server::Server;
+use std::io;
+
+fn main() -> io::Result<()> {
+    let server = Server::demo()?;
+    println!("Listening on {}", server.local_addr()?);
+    server.run()
+}
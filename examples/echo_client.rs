@@ -0,0 +1,27 @@
+//! Echoes each line read from the standard input through a server.
+//!
+//! ```sh
+//! cargo run --example echo_client -- <address>
+//! ```
+
+use embedded_recruitment_task::client::Client;
+use std::{
+    env,
+    io::{self, BufRead},
+    net::ToSocketAddrs,
+};
+
+fn main() -> io::Result<()> {
+    let addr = env::args()
+        .nth(1)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Usage: echo_client <address>"))?
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Address resolved to nothing"))?;
+    let mut client = Client::demo(addr)?;
+
+    for line in io::stdin().lock().lines() {
+        println!("{}", client.echo(&line?)?);
+    }
+    client.disconnect()
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+/// Controls what the accept loop does when accepting a connection fails, e.g. with
+/// `EMFILE` once the process ran out of file descriptors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptErrorPolicy {
+    /// Wait before accepting again, doubling the delay after each consecutive failure.
+    Backoff { initial: Duration, max: Duration },
+    /// Stop accepting for `duration` once `after` consecutive accepts failed.
+    Pause { after: u32, duration: Duration },
+    /// Shut the server down once `after` consecutive accepts failed.
+    Shutdown { after: u32 },
+}
+
+impl Default for AcceptErrorPolicy {
+    /// Back off from 10 ms up to 1 s, so that a persistent error does not spin the loop.
+    fn default() -> Self {
+        AcceptErrorPolicy::Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(1),
+        }
+    }
+}
+
+/// What the accept loop must do after a failed accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptErrorAction {
+    /// Accept again right away.
+    Retry,
+    /// Accept again once the delay elapsed.
+    Wait(Duration),
+    /// Shut the server down.
+    Shutdown,
+}
+
+/// Counters of the failed accepts since the server was created.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AcceptErrorStats {
+    pub errors: u64,
+    pub backoffs: u64,
+    pub pauses: u64,
+    pub shutdowns: u64,
+    /// Failures since the last successful accept.
+    pub consecutive: u32,
+}
+
+/// Applies an `AcceptErrorPolicy` to the outcome of each accept.
+#[derive(Debug, Default)]
+pub struct AcceptErrors {
+    policy: AcceptErrorPolicy,
+    stats: AcceptErrorStats,
+}
+
+impl AcceptErrors {
+    /// Creates a tracker applying `policy`.
+    pub fn new(policy: AcceptErrorPolicy) -> Self {
+        AcceptErrors {
+            policy,
+            stats: AcceptErrorStats::default(),
+        }
+    }
+
+    /// Records a successful accept, which ends a run of failures.
+    pub fn on_success(&mut self) {
+        self.stats.consecutive = 0;
+    }
+
+    /// Records a failed accept.
+    ///
+    /// # Returns
+    /// - What the accept loop must do before accepting again.
+    pub fn on_error(&mut self) -> AcceptErrorAction {
+        self.stats.errors += 1;
+        self.stats.consecutive = self.stats.consecutive.saturating_add(1);
+        let consecutive = self.stats.consecutive;
+
+        match self.policy {
+            AcceptErrorPolicy::Backoff { initial, max } => {
+                self.stats.backoffs += 1;
+                let factor = 2u32.saturating_pow(consecutive - 1);
+                AcceptErrorAction::Wait(initial.saturating_mul(factor).min(max))
+            }
+            AcceptErrorPolicy::Pause { after, duration } if consecutive >= after => {
+                self.stats.pauses += 1;
+                // The pause gives the next accepts a fresh budget of failures.
+                self.stats.consecutive = 0;
+                AcceptErrorAction::Wait(duration)
+            }
+            AcceptErrorPolicy::Shutdown { after } if consecutive >= after => {
+                self.stats.shutdowns += 1;
+                AcceptErrorAction::Shutdown
+            }
+            AcceptErrorPolicy::Pause { .. } | AcceptErrorPolicy::Shutdown { .. } => AcceptErrorAction::Retry,
+        }
+    }
+
+    /// The counters of the failed accepts.
+    pub fn stats(&self) -> AcceptErrorStats {
+        self.stats
+    }
+}
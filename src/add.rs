@@ -0,0 +1,39 @@
+use crate::message::{add_response, AddErrorCode, AddResponse};
+use std::{error::Error, fmt};
+
+/// An addition the server could not perform, as reported in an `AddResponse`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerError {
+    pub code: AddErrorCode,
+    pub detail: String,
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.as_str_name(), self.detail)
+    }
+}
+
+impl Error for ServerError {}
+
+impl AddResponse {
+    /// The sum, or the reason why the server could not compute it.
+    ///
+    /// # Returns
+    /// - Ok    with the sum.
+    /// - Err   with the error reported by the server, or an unspecified one when the
+    ///   response holds neither.
+    pub fn to_result(&self) -> Result<i32, ServerError> {
+        match &self.outcome {
+            Some(add_response::Outcome::Result(result)) => Ok(*result),
+            Some(add_response::Outcome::Error(error)) => Err(ServerError {
+                code: error.code(),
+                detail: error.detail.clone(),
+            }),
+            None => Err(ServerError {
+                code: AddErrorCode::Unspecified,
+                detail: "Response holds no result".to_string(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::add::ServerError;
+use crate::client::is_upgrade_response;
+use crate::framing::{FrameDecoder, FrameFormat, DEFAULT_MAX_FRAME_LEN};
+use crate::message::{
+    client_message, server_message, AddRequest, ByeMessage, ClientMessage, EchoMessage, MyStatsRequest,
+    MyStatsResponse, ServerMessage, Transport, UpgradeRequest,
+};
+use prost::Message;
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+/// The size of the buffer the responses are read into.
+const READ_BUFFER_SIZE: usize = 1024;
+
+/// A client of the server for async applications, reading and writing on a Tokio
+/// `TcpStream` rather than blocking a thread.
+///
+/// The requests are answered in order, except for the delayed echoes, whose responses
+/// come once their delay elapsed: `call()` is meant for the others, `send()` and
+/// `receive()` give full control. Bound the calls with `tokio::time::timeout` as needed.
+pub struct AsyncClient {
+    stream: TcpStream,
+    // How the requests and responses are framed, which may have been upgraded since connecting.
+    frame_format: FrameFormat,
+    // Set while waiting for the response to an upgrade request.
+    pending_upgrade: Option<FrameFormat>,
+    // Reassembles the responses from whatever the reads return.
+    decoder: FrameDecoder,
+    // Responses read from the connection but not returned yet.
+    frames: VecDeque<Vec<u8>>,
+    read_buffer: Vec<u8>,
+    // Attached to every request.
+    metadata: HashMap<String, String>,
+}
+
+impl AsyncClient {
+    /// Connects to the server at `addr`.
+    ///
+    /// # Arguments
+    /// - `addr` The address of the server, e.g. `localhost:8080`.
+    ///
+    /// # Returns
+    /// - Ok    with the connected client.
+    /// - Err   when the server could not be reached.
+    pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::connect_with_format(addr, FrameFormat::default()).await
+    }
+
+    /// Connects to the server at `addr`, framing the requests and responses as
+    /// `frame_format` tells, which must match the server, e.g. `FrameFormat::SYNCED` for a
+    /// server built with `ServerBuilder::with_sync_marker()`.
+    ///
+    /// # Arguments
+    /// - `addr` The address of the server, e.g. `localhost:8080`.
+    /// - `frame_format` How the messages are framed until an upgrade.
+    ///
+    /// # Returns
+    /// - Ok    with the connected client.
+    /// - Err   when the server could not be reached.
+    pub async fn connect_with_format(addr: impl ToSocketAddrs, frame_format: FrameFormat) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(AsyncClient {
+            stream,
+            frame_format,
+            pending_upgrade: None,
+            decoder: frame_format.decoder(DEFAULT_MAX_FRAME_LEN),
+            frames: VecDeque::new(),
+            read_buffer: vec![0; READ_BUFFER_SIZE],
+            metadata: HashMap::new(),
+        })
+    }
+
+    /// The address of the server.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Attach `metadata` to every request from now on, e.g. a trace context.
+    pub fn set_metadata(&mut self, metadata: HashMap<String, String>) {
+        self.metadata = metadata;
+    }
+
+    /// Split the requests longer than `fragment_len` bytes into fragments, see
+    /// `Client::set_fragment_len()`.
+    pub fn set_fragment_len(&mut self, fragment_len: Option<usize>) {
+        self.frame_format.fragment_len = fragment_len;
+    }
+
+    /// Switches the connection to `transport` without reconnecting, see `Client::upgrade()`,
+    /// discarding any response that was still in flight.
+    ///
+    /// # Returns
+    /// - Ok    once the server switched.
+    /// - Err   when the server refused the upgrade, or the connection failed.
+    pub async fn upgrade(&mut self, transport: Transport) -> io::Result<()> {
+        let upgrade_request = UpgradeRequest {
+            transport: transport.into(),
+        };
+        self.send(client_message::Message::UpgradeRequest(upgrade_request)).await?;
+        self.pending_upgrade = Some(self.frame_format.upgraded_to(transport.into()));
+        let result = loop {
+            match self.receive().await.map(|response| response.message) {
+                Ok(Some(server_message::Message::UpgradeResponse(_))) => break Ok(()),
+                Ok(Some(server_message::Message::ErrorMessage(error))) => break Err(io::Error::other(error.content)),
+                Ok(_) => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.pending_upgrade = None;
+        result
+    }
+
+    /// Sends a request, without waiting for its response.
+    ///
+    /// # Returns
+    /// - Ok    once the request was written.
+    /// - Err   when the connection failed.
+    pub async fn send(&mut self, message: client_message::Message) -> io::Result<()> {
+        let request = ClientMessage {
+            message: Some(message),
+            request_id: None,
+            metadata: self.metadata.clone(),
+        };
+        self.stream.write_all(&self.frame_format.encode(&request.encode_to_vec())).await?;
+        self.stream.flush().await
+    }
+
+    /// Receives the next response, skipping the keepalives.
+    ///
+    /// # Returns
+    /// - Ok    with the response.
+    /// - Err   when the server closed the connection, the connection failed, or the
+    ///   response could not be decoded.
+    pub async fn receive(&mut self) -> io::Result<ServerMessage> {
+        let frame = loop {
+            if let Some(frame) = self.frames.pop_front() {
+                break frame;
+            }
+            let bytes_read = self.stream.read(&mut self.read_buffer).await?;
+            if bytes_read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Server disconnected"));
+            }
+            self.take_frames(bytes_read)?;
+        };
+
+        ServerMessage::decode(frame.as_slice()).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to decode ServerMessage: {}", e),
+            )
+        })
+    }
+
+    /// Queues the responses completed by the first `bytes_read` bytes of the read buffer.
+    fn take_frames(&mut self, bytes_read: usize) -> io::Result<()> {
+        let mut bytes = &self.read_buffer[..bytes_read];
+        while !bytes.is_empty() {
+            let (frame, consumed) = self.decoder.feed_frame(bytes)?;
+            bytes = &bytes[consumed..];
+            let Some(frame) = frame else {
+                continue;
+            };
+            // The bytes after the response to an upgrade are of the new transport.
+            if let Some(frame_format) = self.pending_upgrade {
+                if is_upgrade_response(&frame) {
+                    self.frame_format = frame_format;
+                    self.decoder = frame_format.decoder(self.decoder.max_frame_len());
+                    self.pending_upgrade = None;
+                }
+            }
+            self.frames.push_back(frame);
+        }
+        Ok(())
+    }
+
+    /// Sends a request and waits for its response.
+    ///
+    /// # Returns
+    /// - Ok    with the response, which may be an error message of the server.
+    /// - Err   when the connection failed.
+    pub async fn call(&mut self, message: client_message::Message) -> io::Result<ServerMessage> {
+        self.send(message).await?;
+        self.receive().await
+    }
+
+    /// Has the server echo `content` back.
+    ///
+    /// # Returns
+    /// - Ok    with the echoed content.
+    /// - Err   when the call failed, or the server answered with anything else.
+    pub async fn echo(&mut self, content: &str) -> io::Result<String> {
+        let echo_message = EchoMessage {
+            content: content.to_string(),
+        };
+        match self.call(client_message::Message::EchoMessage(echo_message)).await?.message {
+            Some(server_message::Message::EchoMessage(echo)) => Ok(echo.content),
+            response => Err(unexpected_response("EchoMessage", response)),
+        }
+    }
+
+    /// Adds two integers on the server.
+    ///
+    /// # Returns
+    /// - Ok    with the sum, or the reason why the server could not compute it.
+    /// - Err   when the call failed, or the server answered with anything else.
+    pub async fn add(&mut self, a: i32, b: i32) -> io::Result<Result<i32, ServerError>> {
+        match self.call(client_message::Message::AddRequest(AddRequest { a, b })).await?.message {
+            Some(server_message::Message::AddResponse(add_response)) => Ok(add_response.to_result()),
+            response => Err(unexpected_response("AddResponse", response)),
+        }
+    }
+
+    /// Asks the server for the counters of this connection.
+    ///
+    /// # Returns
+    /// - Ok    with the counters.
+    /// - Err   when the call failed, or the server answered with anything else.
+    pub async fn my_stats(&mut self) -> io::Result<MyStatsResponse> {
+        match self.call(client_message::Message::MyStatsRequest(MyStatsRequest {})).await?.message {
+            Some(server_message::Message::MyStatsResponse(my_stats)) => Ok(my_stats),
+            response => Err(unexpected_response("MyStatsResponse", response)),
+        }
+    }
+
+    /// Says goodbye and waits for the acknowledgement, skipping the responses still in
+    /// flight.
+    ///
+    /// # Returns
+    /// - Ok    once the server acknowledged the goodbye.
+    /// - Err   when the connection failed before.
+    pub async fn bye(mut self) -> io::Result<()> {
+        self.send(client_message::Message::ByeMessage(ByeMessage::default())).await?;
+        loop {
+            if let Some(server_message::Message::ByeMessage(_)) = self.receive().await?.message {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// The error of a call answered with something else than `expected`, e.g. an error message.
+fn unexpected_response(expected: &str, response: Option<server_message::Message>) -> io::Error {
+    let detail = match response {
+        Some(server_message::Message::ErrorMessage(error)) => format!("the error {:?}", error.content),
+        response => format!("{:?}", response),
+    };
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Expected {}, but received {}", expected, detail),
+    )
+}
@@ -0,0 +1,616 @@
+use crate::clock::{self, SharedClock};
+use crate::framing::{self, FrameFormat};
+use crate::id::{SharedIdGenerator, SnowflakeIdGenerator};
+use crate::message::{client_message, ClientMessage, ShutdownReason};
+use crate::server::{
+    busy_message, decode_request, shutdown_message, ConnectionOptions, HeldReplies, ResponsePostProcessor, UnknownMessageHandler, WriteCoalescing,
+    DEFAULT_MAX_ECHO_DELAY, NOTIFICATION_WRITE_TIMEOUT,
+};
+use crate::session::{DeprecatedRequests, Reply, Session};
+use crate::slab::Slab;
+use crate::time_scale;
+use crate::usage::{UsageCounters, UsageTotals};
+use log::{debug, error, info, warn};
+use prost::Message;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io,
+    net::SocketAddr,
+    sync::{self, Arc},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::{watch, Mutex},
+    task::JoinSet,
+    time::Instant,
+};
+
+// How long the accept loop backs off after a failed accept, e.g. when out of file
+// descriptors, rather than spinning.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A server handling the connections as tasks of the Tokio runtime it runs on, rather
+/// than on a pool of threads, for applications that are async already.
+///
+/// The requests are answered exactly as by `Server`, whose framing and handlers it shares,
+/// and the connections are limited and accounted for in the same way. As the connections
+/// share the threads of the runtime, the evaluations, and the batches holding one, run on
+/// its blocking pool so that they do not hold back the other connections. The rest of the
+/// operation of `Server` is not available yet:
+/// - no PROXY protocol header is read, see `Server::with_proxy_protocol()`;
+/// - the connections are not refused when file descriptors run low, only beyond
+///   `with_max_connections()`;
+/// - there is no graceful shut down, see `Server::begin_drain()`, nor any client to drain:
+///   `stop_with_reason()` notifies and closes every connection right away;
+/// - no usage report is scheduled, only `usage_totals()` is kept;
+/// - the memory the connections hold is not bounded, see `Server::with_memory_budget()`;
+/// - no startup report is logged, nor served with the descriptors, see
+///   `Server::startup_report()`.
+pub struct AsyncServer {
+    listener: TcpListener,
+    // Set by `stop_with_reason()`, observed by the accept loop and every connection.
+    stopped: watch::Sender<Option<ShutdownReason>>,
+    // Connections are refused beyond this number, when set.
+    max_connections: Option<usize>,
+    // The counters of the connections being served.
+    connections: Arc<sync::Mutex<Slab<Arc<UsageCounters>>>>,
+    // The usage of every connection that closed, for the totals.
+    retired_usage: Arc<UsageCounters>,
+    connection_options: ConnectionOptions,
+    unknown_message_handler: Option<UnknownMessageHandler>,
+    response_post_processors: Vec<ResponsePostProcessor>,
+    redacted_fields: Vec<String>,
+    max_echo_delay: Duration,
+    deprecated_requests: DeprecatedRequests,
+    clock: SharedClock,
+    id_generator: SharedIdGenerator,
+}
+
+impl AsyncServer {
+    /// Binds a new server to `addr`.
+    ///
+    /// # Arguments
+    /// - `addr` The address to listen on, e.g. `localhost:8080`, or port 0 for any free one.
+    ///
+    /// # Returns
+    /// - Ok    with the server, ready to `run()`.
+    /// - Err   when the address could not be bound.
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(AsyncServer {
+            listener,
+            stopped: watch::channel(None).0,
+            max_connections: None,
+            connections: Arc::new(sync::Mutex::new(Slab::new())),
+            retired_usage: Arc::new(UsageCounters::default()),
+            connection_options: ConnectionOptions::default(),
+            unknown_message_handler: None,
+            response_post_processors: Vec::new(),
+            redacted_fields: Vec::new(),
+            max_echo_delay: DEFAULT_MAX_ECHO_DELAY,
+            deprecated_requests: DeprecatedRequests::default(),
+            clock: clock::system(),
+            id_generator: Arc::new(SnowflakeIdGenerator::default()),
+        })
+    }
+
+    /// The address the server listens on, e.g. to find the port picked for port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Refuse the connections beyond `max_connections`, telling the clients that the
+    /// server is busy, see `ServerBuilder::with_max_connections()`.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Read each connection `read_buffer_size` bytes at most at a time,
+    /// `DEFAULT_READ_BUFFER_SIZE` by default. Larger requests take several reads.
+    pub fn with_read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.connection_options.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    /// Drop the connections sending a request larger than `max_frame_len` bytes,
+    /// `DEFAULT_MAX_FRAME_LEN` by default.
+    pub fn with_max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.connection_options.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// Close the connections that sent nothing for `idle_timeout`. They are kept open
+    /// until the client leaves by default.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.connection_options.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Frame the requests and replies with sync markers, see `ServerBuilder::with_sync_marker()`.
+    pub fn with_sync_marker(mut self) -> Self {
+        self.connection_options.frame_format.sync_marker = true;
+        self
+    }
+
+    /// Split the long replies into fragments, see `ServerBuilder::with_fragment_len()`.
+    pub fn with_fragment_len(mut self, fragment_len: usize) -> Self {
+        self.connection_options.frame_format.fragment_len = Some(fragment_len);
+        self
+    }
+
+    /// Serve the clients predating the framing, see `ServerBuilder::with_unframed_clients()`.
+    pub fn with_unframed_clients(mut self, enabled: bool) -> Self {
+        self.connection_options.unframed_clients = enabled;
+        self
+    }
+
+    /// Write the replies of each connection together, see `ServerBuilder::with_write_coalescing()`.
+    pub fn with_write_coalescing(mut self, window: Duration, max_replies: usize) -> Self {
+        self.connection_options.write_coalescing = Some(WriteCoalescing { window, max_replies });
+        self
+    }
+
+    /// Let `handler` answer the requests that the server does not understand, see
+    /// `Server::with_unknown_message_handler()`.
+    pub fn with_unknown_message_handler(mut self, handler: UnknownMessageHandler) -> Self {
+        self.unknown_message_handler = Some(handler);
+        self
+    }
+
+    /// Let `post_processor` annotate or transform every response before it is sent, see
+    /// `Server::with_response_post_processor()`.
+    pub fn with_response_post_processor(mut self, post_processor: ResponsePostProcessor) -> Self {
+        self.response_post_processors.push(post_processor);
+        self
+    }
+
+    /// Mask sensitive fields in the requests logged at debug level, see
+    /// `Server::with_redacted_fields()`.
+    pub fn with_redacted_fields(mut self, redacted_fields: Vec<String>) -> Self {
+        self.redacted_fields = redacted_fields;
+        self
+    }
+
+    /// Bound the delay that a `DelayedEchoRequest` may ask for, longer delays are rejected
+    /// with an error message.
+    pub fn with_max_echo_delay(mut self, max_echo_delay: Duration) -> Self {
+        self.max_echo_delay = max_echo_delay;
+        self
+    }
+
+    /// Mark a request type as deprecated, see `Server::with_deprecated_request()`.
+    pub fn with_deprecated_request(mut self, request: &str, warning: &str) -> Self {
+        Arc::make_mut(&mut self.deprecated_requests).insert(request.to_string(), warning.to_string());
+        self
+    }
+
+    /// Measure the session ages with `clock`. The delayed echoes always wait on the timer
+    /// of the runtime, which has its own means of controlling time in tests.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Generate the ids of the trace spans with `id_generator`.
+    pub fn with_id_generator(mut self, id_generator: SharedIdGenerator) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    /// Accepts and serves connections until `stop()` is called, each connection in a task
+    /// of its own.
+    ///
+    /// # Returns
+    /// - Ok    once stopped, after every connection was closed.
+    /// - Err   never for now, accept errors are logged and retried.
+    pub async fn run(&self) -> io::Result<()> {
+        info!("Async server is running on {}", self.listener.local_addr()?);
+
+        let mut stopped = self.stopped.subscribe();
+        let mut connections = JoinSet::new();
+        loop {
+            // Forget the connections that closed, so that the set does not grow forever.
+            while connections.try_join_next().is_some() {}
+
+            let accepted = tokio::select! {
+                _ = wait_stopped(&mut stopped) => break,
+                accepted = self.listener.accept() => accepted,
+            };
+            match accepted {
+                Ok((stream, addr)) => {
+                    info!("New client connected: {}", addr);
+                    let Some((id, usage)) = self.register_client() else {
+                        warn!("Refusing client {}: too many connections", addr);
+                        let payload = self.new_session().encode_response(busy_message());
+                        let frame = self.connection_options.frame_format.encode(&payload);
+                        connections.spawn(async move {
+                            let mut stream = stream;
+                            let notified = tokio::time::timeout(
+                                time_scale::scale(NOTIFICATION_WRITE_TIMEOUT),
+                                stream.write_all(&frame),
+                            )
+                            .await;
+                            if let Err(e) = notified.unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())) {
+                                warn!("Failed to notify client {}: {}", addr, e);
+                            }
+                        });
+                        continue;
+                    };
+                    let session = self.new_session().with_usage(usage);
+                    let options = self.connection_options;
+                    let stopped = self.stopped.subscribe();
+                    let registered = self.connections.clone();
+                    let retired_usage = self.retired_usage.clone();
+                    connections.spawn(async move {
+                        if let Err(e) = serve_connection(stream, session, options, stopped).await {
+                            error!("Error handling client: {}", e);
+                        }
+                        // Under the lock, so that the totals never miss the connection.
+                        let mut registered = registered.lock().unwrap();
+                        if let Some(usage) = registered.remove(id) {
+                            retired_usage.absorb(&usage);
+                        }
+                        info!("Client {} released.", addr);
+                    });
+                }
+                Err(e) => {
+                    error!("Error accepting connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                }
+            }
+        }
+
+        // The connections observe the stop on their own.
+        while connections.join_next().await.is_some() {}
+        info!("Async server stopped.");
+        Ok(())
+    }
+
+    /// Stops the server: `run()` stops accepting, notifies every client of the shut down
+    /// before closing its connection, and returns.
+    /// Stopping before `run()` makes it return right away.
+    pub fn stop(&self) {
+        self.stop_with_reason(ShutdownReason::Requested);
+    }
+
+    /// Stops the server like `stop()`, telling the clients why. Only the first stop counts.
+    ///
+    /// # Arguments
+    /// - `reason` The cause of the shut down, see `Server::stop_with_reason()`.
+    pub fn stop_with_reason(&self, reason: ShutdownReason) {
+        let stopped = self.stopped.send_if_modified(|stopped| match stopped {
+            Some(_) => false,
+            None => {
+                *stopped = Some(reason);
+                true
+            }
+        });
+        if stopped {
+            info!("Async server stopping ({})...", reason.as_str_name());
+        } else {
+            warn!("Async server was already stopped.");
+        }
+    }
+
+    /// The number of connections being served.
+    pub fn active_client_count(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    /// The usage of every client since the server was created, connected or not, see
+    /// `Server::usage_totals()`.
+    pub fn usage_totals(&self) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        let connections = self.connections.lock().unwrap();
+        totals.add(&self.retired_usage);
+        for (_, usage) in connections.iter() {
+            totals.add(usage);
+        }
+        totals
+    }
+
+    /// Registers a new connection, unless there are too many already.
+    ///
+    /// # Returns
+    /// - Some  with the id of the connection and its counters.
+    /// - None  when the connection must be refused.
+    fn register_client(&self) -> Option<(usize, Arc<UsageCounters>)> {
+        let mut connections = self.connections.lock().unwrap();
+        if self.max_connections.is_some_and(|max| connections.len() >= max) {
+            return None;
+        }
+        let usage = Arc::new(UsageCounters::default());
+        Some((connections.insert(usage.clone()), usage))
+    }
+
+    /// A session answering the requests of a new connection as configured.
+    fn new_session(&self) -> Session {
+        Session::new()
+            .with_unknown_message_handler(self.unknown_message_handler.clone())
+            .with_response_post_processors(self.response_post_processors.clone())
+            .with_redacted_fields(self.redacted_fields.clone())
+            .with_max_echo_delay(self.max_echo_delay)
+            .with_deprecated_requests(self.deprecated_requests.clone())
+            .with_clock(self.clock.clone())
+            .with_id_generator(self.id_generator.clone())
+    }
+}
+
+/// Reads the requests of a connection and writes their replies, until the client leaves,
+/// goes idle, or the server stops.
+///
+/// # Returns
+/// - Ok    once the connection is done.
+/// - Err   when the framing is broken, or the transport failed.
+async fn serve_connection(
+    stream: TcpStream,
+    mut session: Session,
+    options: ConnectionOptions,
+    mut stopped: watch::Receiver<Option<ShutdownReason>>,
+) -> io::Result<()> {
+    let (mut reader, writer) = stream.into_split();
+    let writer = Mutex::new(FrameWriter {
+        half: writer,
+        format: options.frame_format,
+        write_coalescing: options.write_coalescing,
+        held_replies: HeldReplies::default(),
+        usage: session.usage().clone(),
+    });
+    let mut read_buffer = vec![0; options.read_buffer_size];
+    let mut decoder = options.frame_format.decoder(options.max_frame_len);
+    // Set until the first byte of the client tells whether it frames its requests.
+    let mut detect_unframed = options.detects_unframed_clients();
+    // The delayed echoes not sent yet, by deadline, dropped with the connection.
+    let mut delayed_echoes: BinaryHeap<Reverse<(Instant, u64, Vec<u8>)>> = BinaryHeap::new();
+    let mut sequence = 0u64;
+    let mut idle_deadline = options.idle_timeout.map(|idle_timeout| Instant::now() + time_scale::scale(idle_timeout));
+
+    while !session.is_closed() {
+        let next_echo = delayed_echoes.peek().map(|Reverse((deadline, _, _))| *deadline);
+        let replies_due = writer.lock().await.held_replies.due().map(Instant::from_std);
+        let bytes_read = tokio::select! {
+            reason = wait_stopped(&mut stopped) => {
+                // Like the shut down notification of `Server::stop()`.
+                let notification = session.encode_response(shutdown_message(reason, Some(session.summary())));
+                let notified = tokio::time::timeout(time_scale::scale(NOTIFICATION_WRITE_TIMEOUT), write_frame(&writer, &notification)).await;
+                if let Err(e) = notified.unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())) {
+                    warn!("Failed to notify client: {}", e);
+                }
+                if !delayed_echoes.is_empty() {
+                    debug!("Dropped {} delayed echo(es) on shut down", delayed_echoes.len());
+                }
+                break;
+            }
+            _ = tokio::time::sleep_until(next_echo.unwrap_or_else(Instant::now)), if next_echo.is_some() => {
+                let Reverse((_, _, payload)) = delayed_echoes.pop().unwrap();
+                if let Err(e) = write_frame(&writer, &payload).await {
+                    warn!("Failed to send delayed echo: {}", e);
+                }
+                continue;
+            }
+            // No request followed the held replies.
+            _ = tokio::time::sleep_until(replies_due.unwrap_or_else(Instant::now)), if replies_due.is_some() => {
+                writer.lock().await.write_held_replies().await?;
+                continue;
+            }
+            bytes_read = read(&mut reader, &mut read_buffer, idle_deadline) => bytes_read?,
+        };
+        let Some(bytes_read) = bytes_read else {
+            info!("Closing idle client.");
+            let payload = session.encode_response(session.goodbye(None));
+            if let Err(e) = write_frame(&writer, &payload).await {
+                warn!("Failed to say goodbye to an idle client: {}", e);
+            }
+            break;
+        };
+        if bytes_read == 0 {
+            if !decoder.is_idle() {
+                warn!("Client disconnected in the middle of a request.");
+            }
+            info!("Client disconnected.");
+            break;
+        }
+        if std::mem::take(&mut detect_unframed) && framing::is_unframed_start(read_buffer[0]) {
+            info!("Client does not frame its requests, taking each read for a request.");
+            writer.lock().await.format = FrameFormat::UNFRAMED;
+            decoder = FrameFormat::UNFRAMED.decoder(decoder.max_frame_len());
+        }
+        idle_deadline = options.idle_timeout.map(|idle_timeout| Instant::now() + time_scale::scale(idle_timeout));
+
+        // One request at a time, as the bytes after an upgrade request are of another transport.
+        let mut offset = 0;
+        while offset < bytes_read {
+            let keepalives = decoder.keepalives();
+            let (request, consumed) = decode_request(&mut decoder, &mut session, &read_buffer[offset..bytes_read])?;
+            offset += consumed;
+            let keepalive = decoder.keepalives() > keepalives;
+            if request.is_none() && !keepalive {
+                continue;
+            }
+            // Requests sent after a goodbye are not served.
+            if session.is_closed() {
+                break;
+            }
+            let Some(request) = request else {
+                writer.lock().await.reply_keepalive().await?;
+                continue;
+            };
+            let reply = match handler_class(&request) {
+                HandlerClass::Io => session.handle_frame(&request),
+                // On the blocking pool of the runtime, so that the other connections are
+                // served meanwhile. The next request of this one waits for the reply.
+                HandlerClass::Compute => {
+                    let (handled_by, reply) = tokio::task::spawn_blocking(move || {
+                        let reply = session.handle_frame(&request);
+                        (session, reply)
+                    })
+                    .await
+                    .map_err(io::Error::other)?;
+                    session = handled_by;
+                    reply
+                }
+            };
+            match reply {
+                Reply::Now(payload) => {
+                    // Nothing may follow the acknowledgement of a goodbye.
+                    if session.is_closed() && !delayed_echoes.is_empty() {
+                        info!("Dropped {} delayed echo(es) on goodbye.", delayed_echoes.len());
+                        delayed_echoes.clear();
+                    }
+                    writer.lock().await.reply(&payload).await?
+                }
+                Reply::Later { delay, payload, .. } => {
+                    sequence += 1;
+                    delayed_echoes.push(Reverse((Instant::now() + time_scale::scale(delay), sequence, payload)));
+                }
+                Reply::Upgrade { frame_format, payload } => {
+                    let mut writer = writer.lock().await;
+                    writer.write(&payload).await?;
+                    writer.format = writer.format.upgraded_to(frame_format);
+                    decoder = frame_format.decoder(decoder.max_frame_len());
+                }
+            }
+        }
+    }
+    // Counts what was read after the last request.
+    session.close();
+    if let Err(e) = writer.lock().await.write_held_replies().await {
+        warn!("Failed to send the held replies: {}", e);
+    }
+    Ok(())
+}
+
+/// The kind of work the handler of a request does, telling where it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum HandlerClass {
+    /// Answers in constant time, or schedules a timer, on the task serving the connection.
+    Io,
+    /// Keeps a CPU busy for as long as its input is long, which would hold back the other
+    /// tasks of the runtime thread.
+    Compute,
+}
+
+/// The class of the handler of an encoded request, `Io` when it does not decode, as it is
+/// then answered with an error right away.
+fn handler_class(request: &[u8]) -> HandlerClass {
+    match ClientMessage::decode(request).ok().and_then(|request| request.message) {
+        Some(message) => message_class(&message),
+        None => HandlerClass::Io,
+    }
+}
+
+/// The class of the handler of `message`, a batch taking the heaviest of its requests.
+fn message_class(message: &client_message::Message) -> HandlerClass {
+    match message {
+        client_message::Message::EvalRequest(_) => HandlerClass::Compute,
+        client_message::Message::BatchRequest(batch_request) => batch_request
+            .requests
+            .iter()
+            .filter_map(|request| request.message.as_ref())
+            .map(message_class)
+            .max()
+            .unwrap_or(HandlerClass::Io),
+        _ => HandlerClass::Io,
+    }
+}
+
+/// Reads what the client sent, waiting until `idle_deadline` at most.
+///
+/// # Returns
+/// - Ok    with the number of bytes read, 0 at the end of the stream, or `None` when the
+///   client was idle for too long.
+/// - Err   when the transport failed.
+async fn read(reader: &mut OwnedReadHalf, buffer: &mut [u8], idle_deadline: Option<Instant>) -> io::Result<Option<usize>> {
+    match idle_deadline {
+        Some(idle_deadline) => match tokio::time::timeout_at(idle_deadline, reader.read(buffer)).await {
+            Ok(bytes_read) => bytes_read.map(Some),
+            Err(_) => Ok(None),
+        },
+        None => reader.read(buffer).await.map(Some),
+    }
+}
+
+/// The writing half of a connection, with the transport its frames are written with.
+struct FrameWriter {
+    half: OwnedWriteHalf,
+    format: FrameFormat,
+    // The replies are written as they come without one.
+    write_coalescing: Option<WriteCoalescing>,
+    held_replies: HeldReplies,
+    // The counters of the connection, which the frames written are counted in.
+    usage: Arc<UsageCounters>,
+}
+
+impl FrameWriter {
+    /// Writes a payload as a single frame of the current transport, after the held replies.
+    async fn write(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.write_held_replies().await?;
+        let frame = self.format.encode(payload);
+        self.usage.record_sent(frame.len());
+        self.half.write_all(&frame).await?;
+        self.half.flush().await
+    }
+
+    /// Replies with a payload, held to be written with the next replies when coalescing them.
+    async fn reply(&mut self, payload: &[u8]) -> io::Result<()> {
+        let frame = self.format.encode(payload);
+        self.usage.record_sent(frame.len());
+        self.reply_frame(frame).await
+    }
+
+    /// Answers a keepalive in kind, like a reply.
+    async fn reply_keepalive(&mut self) -> io::Result<()> {
+        self.reply_frame(self.format.encode_keepalive()).await
+    }
+
+    /// Replies with a frame, encoded already, see `reply()`.
+    async fn reply_frame(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        match self.write_coalescing {
+            Some(write_coalescing) => {
+                if self.held_replies.hold(frame, write_coalescing) {
+                    self.write_held_replies().await?;
+                }
+                Ok(())
+            }
+            None => {
+                self.write_held_replies().await?;
+                self.half.write_all(&frame).await?;
+                self.half.flush().await
+            }
+        }
+    }
+
+    /// Writes the held replies, if any, at once.
+    async fn write_held_replies(&mut self) -> io::Result<()> {
+        let bytes = self.held_replies.take();
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.half.write_all(&bytes).await?;
+        self.half.flush().await
+    }
+}
+
+/// Writes a payload as a single frame of the current transport.
+async fn write_frame(writer: &Mutex<FrameWriter>, payload: &[u8]) -> io::Result<()> {
+    writer.lock().await.write(payload).await
+}
+
+/// Returns once the server was stopped, or dropped.
+///
+/// # Returns
+/// - Why the server was stopped, `Requested` when it was dropped.
+async fn wait_stopped(stopped: &mut watch::Receiver<Option<ShutdownReason>>) -> ShutdownReason {
+    // The guard of the value is not held across awaits, so that the futures stay `Send`.
+    match stopped.wait_for(Option::is_some).await {
+        Ok(reason) => reason.unwrap_or(ShutdownReason::Requested),
+        Err(_) => ShutdownReason::Requested,
+    }
+}
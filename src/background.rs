@@ -0,0 +1,115 @@
+use crate::shutdown::ShutdownToken;
+use log::{error, info};
+use std::{
+    io,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+/// A task run on its own thread for as long as the server runs.
+pub type BackgroundTask = Box<dyn FnOnce(ShutdownToken) + Send>;
+
+/// A task run again on every run of the server, see `Server::spawn_background_on_each_run()`.
+pub type RecurringTask = Arc<dyn Fn(ShutdownToken) + Send + Sync>;
+
+/// The background tasks of a server: registered ones wait for the server to start, then
+/// run until they observe the cancellation of the token and are joined on stop.
+#[derive(Default)]
+pub(crate) struct BackgroundTasks {
+    started: bool,
+    stopped: bool,
+    pending: Vec<(String, BackgroundTask)>,
+    running: Vec<(String, JoinHandle<()>)>,
+    // Registered again on every run.
+    recurring: Vec<(String, RecurringTask)>,
+}
+
+impl BackgroundTasks {
+    /// Registers a task, starting it right away when the server already runs.
+    pub(crate) fn spawn(&mut self, name: String, task: BackgroundTask, token: &ShutdownToken) -> io::Result<()> {
+        if self.stopped {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("Cannot start background task {}: the server was stopped", name),
+            ));
+        }
+        if self.started {
+            self.start(name, task, token)
+        } else {
+            self.pending.push((name, task));
+            Ok(())
+        }
+    }
+
+    /// Registers a task run on this run, see `spawn()`, and again on every run from now on.
+    pub(crate) fn spawn_recurring(&mut self, name: String, task: RecurringTask, token: &ShutdownToken) -> io::Result<()> {
+        self.spawn(name.clone(), once(task.clone()), token)?;
+        self.recurring.push((name, task));
+        Ok(())
+    }
+
+    /// Forgets the previous run, once stopped, registering the recurring tasks again for the
+    /// next one.
+    pub(crate) fn restart(&mut self) {
+        let recurring = std::mem::take(&mut self.recurring);
+        *self = BackgroundTasks::default();
+        self.pending = recurring.iter().map(|(name, task)| (name.clone(), once(task.clone()))).collect();
+        self.recurring = recurring;
+    }
+
+    /// Starts the registered tasks, and the ones registered from now on.
+    pub(crate) fn start_all(&mut self, token: &ShutdownToken) -> io::Result<()> {
+        self.started = true;
+        for (name, task) in std::mem::take(&mut self.pending) {
+            self.start(name, task, token)?;
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, name: String, task: BackgroundTask, token: &ShutdownToken) -> io::Result<()> {
+        let token = token.clone();
+        let handle = thread::Builder::new().name(name.clone()).spawn(move || task(token))?;
+        info!("Background task {} started.", name);
+        self.running.push((name, handle));
+        Ok(())
+    }
+
+    /// Takes the running tasks so that they can be joined, dropping the ones that never
+    /// started. Tasks cannot be registered anymore afterwards.
+    pub(crate) fn stop(&mut self) -> Vec<(String, JoinHandle<()>)> {
+        self.stopped = true;
+        self.pending.clear();
+        std::mem::take(&mut self.running)
+    }
+
+    /// The names of the tasks that were started and did not return yet.
+    pub(crate) fn unfinished(&self) -> Vec<&str> {
+        self.running
+            .iter()
+            .filter(|(_, handle)| !handle.is_finished())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+/// A single run of a recurring task.
+fn once(task: RecurringTask) -> BackgroundTask {
+    Box::new(move |token| task(token))
+}
+
+/// Waits for the stopped tasks to return.
+///
+/// A task stopping the server from its own thread is not waited for, as it would wait
+/// for itself.
+pub(crate) fn join(tasks: Vec<(String, JoinHandle<()>)>) {
+    let current = thread::current().id();
+    for (name, handle) in tasks {
+        if handle.thread().id() == current {
+            continue;
+        }
+        match handle.join() {
+            Ok(()) => info!("Background task {} stopped.", name),
+            Err(_) => error!("Background task {} panicked.", name),
+        }
+    }
+}
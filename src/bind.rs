@@ -0,0 +1,74 @@
+use crate::time_scale;
+use log::{info, warn};
+use std::{
+    io::{self, ErrorKind},
+    net::TcpListener,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Controls what the server does when its address is already in use.
+#[derive(Debug, Clone)]
+pub struct BindPolicy {
+    /// How long to keep retrying the primary address, covering `TIME_WAIT` after a crash.
+    pub retry_for: Duration,
+    /// Delay before the first retry, doubled after each failed attempt.
+    pub backoff: Duration,
+    /// Addresses tried in order once the primary address could not be bound.
+    pub fallback_addrs: Vec<String>,
+}
+
+impl Default for BindPolicy {
+    /// Fail right away, without retrying or falling back, like a plain `TcpListener::bind`.
+    fn default() -> Self {
+        BindPolicy {
+            retry_for: Duration::ZERO,
+            backoff: Duration::from_millis(100),
+            fallback_addrs: Vec::new(),
+        }
+    }
+}
+
+/// Binds a listener according to the given policy.
+///
+/// # Arguments
+/// - `addr` The preferred address for the server.
+/// - `policy` The retry and fallback policy to apply when the address is busy.
+///
+/// # Returns
+/// - Ok    with the listener bound to the first address that was free.
+/// - Err   with the error of the last attempt when no address could be bound.
+pub fn bind(addr: &str, policy: &BindPolicy) -> io::Result<TcpListener> {
+    let deadline = Instant::now() + policy.retry_for;
+    let mut backoff = policy.backoff;
+
+    // Keep retrying the primary address while it is busy and the deadline was not reached.
+    let mut result = TcpListener::bind(addr);
+    while let Err(ref e) = result {
+        let now = Instant::now();
+        if e.kind() != ErrorKind::AddrInUse || now >= deadline {
+            break;
+        }
+
+        warn!("Address {} is in use, retrying in {:?}", addr, backoff);
+        thread::sleep(time_scale::scale(backoff.min(deadline - now)));
+        backoff *= 2;
+        result = TcpListener::bind(addr);
+    }
+
+    // Try the alternate addresses in order.
+    for fallback in &policy.fallback_addrs {
+        match result {
+            Err(ref e) if e.kind() == ErrorKind::AddrInUse => {
+                warn!("Address in use, falling back to {}", fallback);
+                result = TcpListener::bind(fallback.as_str());
+            }
+            _ => break,
+        }
+    }
+
+    if let Ok(ref listener) = result {
+        info!("Server bound to {}", listener.local_addr()?);
+    }
+    result
+}
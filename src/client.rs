@@ -0,0 +1,1433 @@
+use crate::add::ServerError;
+use crate::demo;
+use crate::framing::{self, FrameDecoder, FrameFormat};
+use crate::time_scale;
+use crate::message::{
+    client_message, server_message, AddRequest, BatchRequest, ByeMessage, ClientMessage, EchoMessage, ErrorCode,
+    ReconnectHint, ServerMessage, Transport, UpgradeRequest,
+};
+use log::{debug, error, info, warn, Level};
+use prost::Message;
+use std::io::Read;
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+    io,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Counters describing the client's activity since it was created.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClientStats {
+    pub requests: u64,
+    pub retries: u64,
+    pub reconnects: u64,
+    pub timeouts: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Times corrupted bytes were skipped up to the next response, with sync markers.
+    pub resyncs: u64,
+}
+
+/// Details of a single completed call, handed to the call hook.
+#[derive(Debug, Clone, Copy)]
+pub struct CallRecord {
+    pub latency: Duration,
+    pub attempts: u32,
+    pub succeeded: bool,
+}
+
+/// Callback invoked after each completed call.
+pub type CallHook = Box<dyn FnMut(&CallRecord) + Send>;
+
+/// Check run on every response of a call, returning the reason of a rejection.
+pub type Validator = Box<dyn Fn(&client_message::Message, &ServerMessage) -> Result<(), String> + Send>;
+
+/// Raised, wrapped in an `InvalidData` io::Error, when a validator rejected a response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub response: ServerMessage,
+    pub reason: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Response rejected: {}", self.reason)
+    }
+}
+
+impl Error for ValidationError {}
+
+/// A server of the failover list, and until when it is only tried as a last resort.
+struct FailoverServer {
+    ip: String,
+    port: u32,
+    down_until: Option<Instant>,
+}
+
+/// How long a demo client waits for the connection and for each response.
+const DEMO_TIMEOUT_MS: u64 = 5000;
+
+/// How long a failed server is avoided, and how long the client stays on an alternate
+/// before trying the primary again, unless configured otherwise.
+const DEFAULT_FAILBACK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The longest retry-after delay of a reconnect hint the client waits out, unless
+/// configured otherwise, so that a misbehaving server cannot park it for days.
+const DEFAULT_MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// A response to a call, without the protobuf wrapping.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerReply {
+    Echo(String),
+    Add(Result<i32, ServerError>),
+    Error { code: ErrorCode, content: String },
+    /// Any other response, including the ones of a newer server, kept as received.
+    Unknown(ServerMessage),
+}
+
+impl From<ServerMessage> for ServerReply {
+    fn from(response: ServerMessage) -> Self {
+        match response.message {
+            Some(server_message::Message::EchoMessage(echo)) => ServerReply::Echo(echo.content),
+            Some(server_message::Message::AddResponse(ref add_response)) => ServerReply::Add(add_response.to_result()),
+            Some(server_message::Message::ErrorMessage(ref error)) => ServerReply::Error {
+                // Codes unknown to this client read as unspecified
+                code: error.code(),
+                content: error.content.clone(),
+            },
+            _ => ServerReply::Unknown(response),
+        }
+    }
+}
+
+/// A blocking client of the server, sending one request at a time, with optional retries
+/// and failover to other servers.
+pub struct Client {
+    ip: String,
+    port: u32,
+    timeout: Duration,
+    stream: Option<TcpStream>,
+    // Reassembles the responses from whatever the reads return
+    decoder: FrameDecoder,
+    // How the requests and responses are framed on connect, which must match the server
+    frame_format: FrameFormat,
+    // How they are framed on the current connection, which may have been upgraded since
+    connection_format: FrameFormat,
+    // Set while waiting for the response to an upgrade request
+    pending_upgrade: Option<FrameFormat>,
+    // Responses read from the connection but not returned yet
+    frames: VecDeque<Vec<u8>>,
+    // Number of times a call is re-sent after timing out
+    max_retries: u32,
+    stats: ClientStats,
+    has_connected: bool,
+    // Set once a read observed the server closing the connection
+    server_disconnected: bool,
+    call_hook: Option<CallHook>,
+    validators: Vec<Validator>,
+    // Attached to every request
+    metadata: HashMap<String, String>,
+    // Tells the response to a call from those to earlier calls that timed out
+    next_request_id: u64,
+    // Left by the server when it closed the connection, and when, followed on the next connect
+    reconnect_hint: Option<(ReconnectHint, Instant)>,
+    // The servers to fail over to by priority, the primary first, empty unless configured
+    failover: Vec<FailoverServer>,
+    failback_interval: Duration,
+    // The longest retry-after delay of a reconnect hint that is waited out
+    max_reconnect_delay: Duration,
+    // When the client last connected to a server other than the primary
+    failed_over_at: Option<Instant>,
+    // When a message was last sent or received
+    last_activity: Instant,
+}
+
+impl Client {
+    /// Creates a client of the server at `ip` and `port`, not connected yet.
+    ///
+    /// # Arguments
+    /// - `ip` The address or host name of the server.
+    /// - `port` The port of the server.
+    /// - `timeout_ms` How long to wait for the connection and for each response.
+    pub fn new(ip: &str, port: u32, timeout_ms: u64) -> Self {
+        Client {
+            ip: ip.to_string(),
+            port,
+            timeout: Duration::from_millis(timeout_ms),
+            stream: None,
+            decoder: FrameDecoder::new(),
+            frame_format: FrameFormat::default(),
+            connection_format: FrameFormat::default(),
+            pending_upgrade: None,
+            frames: VecDeque::new(),
+            max_retries: 0,
+            stats: ClientStats::default(),
+            has_connected: false,
+            server_disconnected: false,
+            call_hook: None,
+            validators: Vec::new(),
+            metadata: HashMap::new(),
+            next_request_id: 1,
+            reconnect_hint: None,
+            failover: Vec::new(),
+            failback_interval: DEFAULT_FAILBACK_INTERVAL,
+            max_reconnect_delay: DEFAULT_MAX_RECONNECT_DELAY,
+            failed_over_at: None,
+            last_activity: Instant::now(),
+        }
+    }
+
+    /// A client of the server listening on addr, e.g. on the port it picked when bound to port 0.
+    pub fn with_addr(addr: SocketAddr, timeout_ms: u64) -> Self {
+        Self::new(&addr.ip().to_string(), addr.port() as u32, timeout_ms)
+    }
+
+    /// Creates a client for trying the system out, e.g. with `Server::demo()`: it is
+    /// connected to the server at `addr`, and logs to the console. The calls are made with
+    /// the default settings.
+    ///
+    /// # Returns
+    /// - Ok    upon connecting.
+    /// - Err   when the server could not be reached.
+    pub fn demo(addr: SocketAddr) -> io::Result<Self> {
+        demo::init_console_logging(Level::Info);
+        let mut client = Self::with_addr(addr, DEMO_TIMEOUT_MS);
+        client.connect()?;
+        Ok(client)
+    }
+
+    /// The local address of the connection, as seen by the server.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self.stream {
+            Some(ref stream) => stream.local_addr(),
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "No active connection")),
+        }
+    }
+
+    /// Set how many times a call is re-sent when the response times out.
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = max_retries;
+    }
+
+    /// Attach `metadata` to every request from now on, e.g. a tenant or trace context.
+    pub fn set_metadata(&mut self, metadata: HashMap<String, String>) {
+        self.metadata = metadata;
+    }
+
+    /// Frame the requests with sync markers, and expect them on the responses, to talk to a
+    /// server built with `ServerBuilder::with_sync_marker()`. Applies from the next connect.
+    pub fn set_sync_marker(&mut self, sync_marker: bool) {
+        self.frame_format.sync_marker = sync_marker;
+    }
+
+    /// Split the requests longer than `fragment_len` bytes into fragments, for a server that
+    /// limits the size of a frame, see `FrameFormat::fragment_len`. Applies right away, as
+    /// the fragmented responses are reassembled either way.
+    pub fn set_fragment_len(&mut self, fragment_len: Option<usize>) {
+        self.frame_format.fragment_len = fragment_len;
+        self.connection_format.fragment_len = fragment_len;
+    }
+
+    /// Fail over to `alternates`, by priority, when the server given to `new` cannot be
+    /// reached or redirects the client without an address.
+    pub fn set_failover_servers(&mut self, alternates: &[(&str, u32)]) {
+        let primary = (self.ip.as_str(), self.port);
+        self.failover = std::iter::once(primary)
+            .chain(alternates.iter().copied())
+            .map(|(ip, port)| FailoverServer {
+                ip: ip.to_string(),
+                port,
+                down_until: None,
+            })
+            .collect();
+    }
+
+    /// Set how long a failed server is avoided, and how often the client tries to fall
+    /// back to the primary while connected to an alternate.
+    pub fn set_failback_interval(&mut self, interval: Duration) {
+        self.failback_interval = interval;
+    }
+
+    /// Set the longest retry-after delay of a reconnect hint the client waits out; longer
+    /// delays asked by the server are capped to it.
+    pub fn set_max_reconnect_delay(&mut self, max_reconnect_delay: Duration) {
+        self.max_reconnect_delay = max_reconnect_delay;
+    }
+
+    /// Register a callback that is invoked after each completed call.
+    pub fn set_call_hook(&mut self, hook: CallHook) {
+        self.call_hook = Some(hook);
+    }
+
+    /// Register a check that every response of a call must pass.
+    pub fn add_validator(&mut self, validator: Validator) {
+        self.validators.push(validator);
+    }
+
+    /// Snapshot of the client counters.
+    pub fn stats(&self) -> ClientStats {
+        self.stats
+    }
+
+    /// Whether the connection is open, as far as the client knows.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some() && !self.server_disconnected
+    }
+
+    /// How long since a message was last sent or received.
+    pub fn idle_time(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Connect ahead of the first request, or reconnect if the server closed the
+    /// connection meanwhile, so that the request does not pay for it; does nothing on a
+    /// connection that is still open.
+    pub fn preconnect(&mut self) -> io::Result<()> {
+        self.poll_connection()?;
+        if self.reconnect_hint.is_some() || !self.is_connected() {
+            self.connect()?;
+        }
+        Ok(())
+    }
+
+    /// Make a round trip on the connection, (re)connecting first if needed, so that the
+    /// connection and the network path stay warm during idle periods.
+    pub fn ping(&mut self) -> io::Result<()> {
+        self.preconnect()?;
+        self.send(ping_message())?;
+        self.receive().map(|_| ())
+    }
+
+    /// Connect the client to the server, or to the one the server redirected it to, or to
+    /// the first healthy one of the failover list.
+    pub fn connect(&mut self) -> io::Result<()> {
+        let redirected = match self.reconnect_hint.take() {
+            Some((hint, received_at)) => self.follow_reconnect_hint(&hint, received_at),
+            None => false,
+        };
+        if redirected || self.failover.is_empty() {
+            return self.connect_current();
+        }
+
+        // Healthy servers by priority, then the failed ones in case they are back
+        let now = Instant::now();
+        let mut order: Vec<usize> = (0..self.failover.len()).collect();
+        order.sort_by_key(|&i| self.failover[i].down_until.is_some_and(|until| until > now));
+
+        let mut last_error = None;
+        for i in order {
+            self.ip = self.failover[i].ip.clone();
+            self.port = self.failover[i].port;
+            match self.connect_current() {
+                Ok(()) => {
+                    self.failover[i].down_until = None;
+                    return Ok(());
+                }
+                Err(e) => {
+                    error!("Failed to connect to {}:{}: {}", self.ip, self.port, e);
+                    self.failover[i].down_until = Some(Instant::now() + self.failback_interval);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("The failover list holds the primary server"))
+    }
+
+    /// Connect to the server at the current address.
+    fn connect_current(&mut self) -> io::Result<()> {
+        info!("Connecting to {}:{}", self.ip, self.port);
+
+        // Resolve the address
+        let address = format!("{}:{}", self.ip, self.port);
+        let socket_addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+
+        if socket_addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid IP or port",
+            ));
+        }
+
+        // Connect to the server with a timeout, racing the resolved addresses
+        let stream = connect_happy_eyeballs(&socket_addrs, self.timeout)?;
+        self.stream = Some(stream);
+        self.server_disconnected = false;
+        self.connection_format = self.frame_format;
+        self.pending_upgrade = None;
+        self.decoder = self.frame_format.decoder(framing::DEFAULT_MAX_FRAME_LEN);
+        self.frames.clear();
+
+        if self.has_connected {
+            self.stats.reconnects += 1;
+        }
+        self.has_connected = true;
+        self.failed_over_at = (!self.failover.is_empty() && !self.is_on_primary()).then(Instant::now);
+
+        info!("Connected to the server!");
+        Ok(())
+    }
+
+    /// Switch to the alternate server of `hint`, if any, and wait out its retry-after delay,
+    /// up to the maximum set by `set_max_reconnect_delay()`; without an alternate, the
+    /// server the client was on is avoided by the failover.
+    ///
+    /// Returns whether the client was redirected to the alternate server.
+    fn follow_reconnect_hint(&mut self, hint: &ReconnectHint, received_at: Instant) -> bool {
+        let mut redirected = false;
+        if !hint.alternate_addr.is_empty() {
+            match hint.alternate_addr.rsplit_once(':').map(|(ip, port)| (ip, port.parse())) {
+                Some((ip, Ok(port))) => {
+                    info!("Server redirected the client to {}", hint.alternate_addr);
+                    self.ip = ip.to_string();
+                    self.port = port;
+                    redirected = true;
+                }
+                _ => error!("Ignoring invalid alternate address {}", hint.alternate_addr),
+            }
+        }
+        if !redirected {
+            let down_until = Instant::now() + self.failback_interval;
+            let (ip, port) = (&self.ip, self.port);
+            if let Some(server) = self.failover.iter_mut().find(|server| server.ip == *ip && server.port == port) {
+                server.down_until = Some(down_until);
+            }
+        }
+
+        let mut retry_after = Duration::from_millis(hint.retry_after_ms.into());
+        if retry_after > self.max_reconnect_delay {
+            warn!(
+                "Capping the retry-after delay of {:?} asked by the server to {:?}",
+                retry_after, self.max_reconnect_delay
+            );
+            retry_after = self.max_reconnect_delay;
+        }
+        if let Some(remaining) = retry_after.checked_sub(received_at.elapsed()) {
+            info!("Reconnecting in {:?} as asked by the server", remaining);
+            thread::sleep(time_scale::scale(remaining));
+        }
+        redirected
+    }
+
+    /// Whether the client is on the primary server of the failover list.
+    fn is_on_primary(&self) -> bool {
+        self.failover
+            .first()
+            .is_some_and(|primary| primary.ip == self.ip && primary.port == self.port)
+    }
+
+    /// Leave the alternate server for the primary once the failback interval elapsed,
+    /// staying on the alternate, for another interval, if the primary is still down.
+    fn fail_back_if_due(&mut self) -> io::Result<()> {
+        match self.failed_over_at {
+            Some(failed_over_at) if failed_over_at.elapsed() >= self.failback_interval && self.stream.is_some() => {
+                info!("Trying to fail back to the primary server");
+                self.disconnect()?;
+                self.connect()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Remember the reconnect hint of a goodbye sent by the server on its own; the server
+    /// closes the connection right after it.
+    fn note_reconnect_hint(&mut self, response: &ServerMessage) {
+        if let Some(hint) = reconnect_hint(response) {
+            self.reconnect_hint = Some((hint.clone(), Instant::now()));
+            self.server_disconnected = true;
+        }
+    }
+
+    /// Check, without blocking, whether the server closed the connection, taking in a
+    /// goodbye waiting to be read; anything else is left for the next receive.
+    fn poll_connection(&mut self) -> io::Result<()> {
+        if self.reconnect_hint.is_some() || self.server_disconnected {
+            return Ok(());
+        }
+        if let Some(ref mut stream) = self.stream {
+            let mut buffer = vec![0u8; 1024];
+            stream.set_nonblocking(true)?;
+            let read = stream.read(&mut buffer);
+            stream.set_nonblocking(false)?;
+            match read {
+                Ok(0) => self.server_disconnected = true,
+                Ok(bytes_read) => {
+                    self.stats.bytes_received += bytes_read as u64;
+                    self.take_frames(&buffer[..bytes_read])?;
+                }
+                Err(_) => {}
+            }
+        }
+
+        let goodbye = self.frames.front().and_then(|frame| ServerMessage::decode(frame.as_slice()).ok());
+        if let Some(goodbye) = goodbye.filter(|goodbye| reconnect_hint(goodbye).is_some()) {
+            self.frames.pop_front();
+            self.note_reconnect_hint(&goodbye);
+        }
+        Ok(())
+    }
+
+    /// Reconnect when the server closed the connection with a reconnect hint, including one
+    /// waiting to be read, so that server-directed steering needs no application changes.
+    fn reconnect_if_hinted(&mut self) -> io::Result<()> {
+        self.poll_connection()?;
+        if self.reconnect_hint.is_some() {
+            self.connect()?;
+        }
+        Ok(())
+    }
+
+    /// Disconnect the client, saying goodbye first so that the server
+    /// delivers every in-flight response and releases the connection.
+    pub fn disconnect(&mut self) -> io::Result<()> {
+        if self.stream.is_some() && !self.server_disconnected {
+            if let Err(e) = self.say_bye() {
+                info!("Closing without a goodbye: {}", e);
+            }
+        }
+
+        if let Some(stream) = self.stream.take() {
+            // The server may have already closed the connection, e.g. on shut down.
+            match stream.shutdown(std::net::Shutdown::Both) {
+                Err(e) if e.kind() != io::ErrorKind::NotConnected => return Err(e),
+                _ => {}
+            }
+        }
+
+        info!("Disconnected from the server!");
+        Ok(())
+    }
+
+    /// Send a bye message and wait for its acknowledgement, discarding any
+    /// response that was still in flight.
+    fn say_bye(&mut self) -> io::Result<()> {
+        self.send(client_message::Message::ByeMessage(ByeMessage::default()))?;
+        loop {
+            if let Some(server_message::Message::ByeMessage(_)) = self.receive()?.message {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Switch the connection to `transport` without reconnecting, e.g. to frame with sync
+    /// markers from now on, discarding any response that was still in flight. A reconnect
+    /// starts over with the transport set by `set_sync_marker()`.
+    pub fn upgrade(&mut self, transport: Transport) -> io::Result<()> {
+        let upgrade_request = UpgradeRequest {
+            transport: transport.into(),
+        };
+        self.send(client_message::Message::UpgradeRequest(upgrade_request))?;
+        self.pending_upgrade = Some(self.connection_format.upgraded_to(transport.into()));
+        let result = loop {
+            match self.receive() {
+                Ok(ServerMessage {
+                    message: Some(server_message::Message::UpgradeResponse(_)),
+                    ..
+                }) => break Ok(()),
+                Ok(ServerMessage {
+                    message: Some(server_message::Message::ErrorMessage(error)),
+                    ..
+                }) => break Err(io::Error::other(error.content)),
+                Ok(_) => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.pending_upgrade = None;
+        result
+    }
+
+    /// Send a request, without waiting for its response.
+    pub fn send(&mut self, message: client_message::Message) -> io::Result<()> {
+        self.send_with_id(message, None)
+    }
+
+    /// Send a request tagged with `request_id`, which the server copies into its response.
+    fn send_with_id(&mut self, message: client_message::Message, request_id: Option<u64>) -> io::Result<()> {
+        let buffer = self.encode_request(message.clone(), request_id);
+        if let Some(ref mut stream) = self.stream {
+            // Send the buffer to the server
+            let bytes_written = self.connection_format.write(stream, &buffer)?;
+            self.stats.bytes_sent += bytes_written as u64;
+            self.last_activity = Instant::now();
+
+            debug!("Sent message: {:?}", message);
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No active connection",
+            ))
+        }
+    }
+
+    /// Encode a request tagged with `request_id`, along with the metadata of the client.
+    fn encode_request(&self, message: client_message::Message, request_id: Option<u64>) -> Vec<u8> {
+        let request = ClientMessage {
+            message: Some(message),
+            request_id,
+            metadata: self.metadata.clone(),
+        };
+        request.encode_to_vec()
+    }
+
+    /// Send bytes as a single frame, without encoding them as a message.
+    pub fn send_raw(&mut self, payload: &[u8]) -> io::Result<()> {
+        if let Some(ref mut stream) = self.stream {
+            let bytes_written = self.connection_format.write(stream, payload)?;
+            self.stats.bytes_sent += bytes_written as u64;
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No active connection",
+            ))
+        }
+    }
+
+    /// Receive the payload of a single frame, without decoding it.
+    pub fn receive_raw(&mut self) -> io::Result<Vec<u8>> {
+        self.receive_frame(self.timeout)
+    }
+
+    /// Receive a message, waiting at most the timeout given to the client.
+    pub fn receive(&mut self) -> io::Result<ServerMessage> {
+        self.receive_timeout(self.timeout)
+    }
+
+    /// Receive a message, waiting at most `timeout` (which must not be zero).
+    pub fn receive_timeout(&mut self, timeout: Duration) -> io::Result<ServerMessage> {
+        info!("Receiving message from the server");
+        let frame = self.receive_frame(timeout)?;
+        self.decode_response(&frame)
+    }
+
+    /// Receive a message if one arrives within `timeout`, without counting a timeout: the
+    /// caller is only taking its turn at reading, see `SharedClient`.
+    fn poll_response(&mut self, timeout: Duration) -> io::Result<Option<ServerMessage>> {
+        match self.read_frame(timeout) {
+            Ok(frame) => self.decode_response(&frame).map(Some),
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Decode a received message, taking note of the reconnect hint it may hold.
+    fn decode_response(&mut self, frame: &[u8]) -> io::Result<ServerMessage> {
+        let response = ServerMessage::decode(frame).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to decode ServerMessage: {}", e),
+            )
+        })?;
+        self.note_reconnect_hint(&response);
+        Ok(response)
+    }
+
+    /// Receive the payload of the next frame, see `read_frame()`, counting the timeouts.
+    fn receive_frame(&mut self, timeout: Duration) -> io::Result<Vec<u8>> {
+        let result = self.read_frame(timeout);
+        if let Err(ref e) = result {
+            if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) {
+                self.stats.timeouts += 1;
+            }
+        }
+        result
+    }
+
+    /// Receive the payload of the next frame, reading until one is complete or `timeout`
+    /// elapsed; a frame cut short by the timeout is completed by the next receive.
+    fn read_frame(&mut self, timeout: Duration) -> io::Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(frame) = self.frames.pop_front() {
+                return Ok(frame);
+            }
+
+            // Do not block on a connection that is already known to be dead
+            if self.server_disconnected {
+                return Err(server_disconnected());
+            }
+
+            let Some(ref mut stream) = self.stream else {
+                error!("No active connection");
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "No active connection",
+                ));
+            };
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Response is incomplete"));
+            }
+            stream.set_read_timeout(Some(remaining))?;
+            let mut buffer = vec![0u8; 1024];
+            let bytes_read = match stream.read(&mut buffer) {
+                Ok(bytes_read) => bytes_read,
+                Err(e) => {
+                    if matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted) {
+                        self.server_disconnected = true;
+                    }
+                    return Err(e);
+                }
+            };
+            if bytes_read == 0 {
+                info!("Server disconnected.");
+                self.server_disconnected = true;
+                return Err(server_disconnected());
+            }
+
+            info!("Received {} bytes from the server", bytes_read);
+            self.stats.bytes_received += bytes_read as u64;
+            self.last_activity = Instant::now();
+            self.take_frames(&buffer[..bytes_read])?;
+        }
+    }
+
+    /// Queue the messages completed by `bytes`. The keepalives, which only tell that the
+    /// server is alive, are dropped by the decoder.
+    fn take_frames(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            let resyncs = self.decoder.resyncs();
+            let (frame, consumed) = self.decoder.feed_frame(bytes)?;
+            bytes = &bytes[consumed..];
+            if self.decoder.resyncs() > resyncs {
+                warn!("Skipped corrupted bytes up to the next response");
+                self.stats.resyncs += self.decoder.resyncs() - resyncs;
+            }
+            let Some(frame) = frame else {
+                continue;
+            };
+            // The bytes after the response to an upgrade are of the new transport
+            if let Some(frame_format) = self.pending_upgrade {
+                if is_upgrade_response(&frame) {
+                    self.connection_format = frame_format;
+                    self.decoder = frame_format.decoder(self.decoder.max_frame_len());
+                    self.pending_upgrade = None;
+                }
+            }
+            self.frames.push_back(frame);
+        }
+        Ok(())
+    }
+
+    /// Send a keepalive, answered by the framing layer of the server without reaching its
+    /// handlers, to keep the connection alive without counting as a request.
+    pub fn send_keepalive(&mut self) -> io::Result<()> {
+        if let Some(ref mut stream) = self.stream {
+            let bytes_written = self.connection_format.write_keepalive(stream)?;
+            self.stats.bytes_sent += bytes_written as u64;
+            self.last_activity = Instant::now();
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No active connection",
+            ))
+        }
+    }
+
+    /// Send a request and wait for its response, see `call_message()`.
+    pub fn call(&mut self, message: client_message::Message) -> io::Result<ServerReply> {
+        self.call_message(message).map(ServerReply::from)
+    }
+
+    /// Send a request and wait for its response, as the server sent it, re-sending it on
+    /// timeouts, and on the server it was redirected to when the server closed the
+    /// connection with a hint.
+    ///
+    /// Every call is tagged with its own request id, so that the late responses to the
+    /// attempts that timed out are told apart and dropped.
+    pub fn call_message(&mut self, message: client_message::Message) -> io::Result<ServerMessage> {
+        let start = Instant::now();
+        let mut attempts = 0;
+        let mut redirects = 0;
+        let request_id = self.begin_call();
+
+        let result = loop {
+            attempts += 1;
+            if attempts > 1 {
+                self.stats.retries += 1;
+            }
+
+            let result = self
+                .fail_back_if_due()
+                .and_then(|_| self.reconnect_if_hinted())
+                .and_then(|_| self.send_with_id(message.clone(), Some(request_id)))
+                .and_then(|_| self.receive_response(request_id));
+            match result {
+                Err(ref e)
+                    if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+                        && attempts <= self.max_retries => {}
+                // The server closed the connection instead of answering
+                Ok(ref response) if reconnect_hint(response).is_some() && redirects < MAX_REDIRECTS => {
+                    redirects += 1;
+                }
+                result => break result,
+            }
+        };
+
+        self.end_call(&message, result, start, attempts)
+    }
+
+    /// Count a new call, returning the request id it is tagged with.
+    fn begin_call(&mut self) -> u64 {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.stats.requests += 1;
+        request_id
+    }
+
+    /// Run the response to a call through the validators, then report the call to the hook.
+    fn end_call(
+        &mut self,
+        message: &client_message::Message,
+        result: io::Result<ServerMessage>,
+        start: Instant,
+        attempts: u32,
+    ) -> io::Result<ServerMessage> {
+        let result = result.and_then(|response| {
+            for validator in &self.validators {
+                if let Err(reason) = validator(message, &response) {
+                    error!("Response rejected: {}", reason);
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        ValidationError { response, reason },
+                    ));
+                }
+            }
+            Ok(response)
+        });
+
+        if let Some(hook) = self.call_hook.as_mut() {
+            hook(&CallRecord {
+                latency: start.elapsed(),
+                attempts,
+                succeeded: result.is_ok(),
+            });
+        }
+
+        result
+    }
+
+    /// Receive the response to the request tagged with `request_id`, dropping the responses
+    /// to other requests. The messages the server sends on its own, untagged, are returned.
+    fn receive_response(&mut self, request_id: u64) -> io::Result<ServerMessage> {
+        loop {
+            let response = self.receive()?;
+            match response.request_id {
+                Some(id) if id != request_id => warn!("Dropped the late response to request {}", id),
+                _ => return Ok(response),
+            }
+        }
+    }
+
+    /// Have the server echo `content` back; fails when the call does, or when the server
+    /// answers with anything else, e.g. an error message.
+    pub fn echo(&mut self, content: &str) -> io::Result<String> {
+        let echo_message = EchoMessage {
+            content: content.to_string(),
+        };
+        match self.call(client_message::Message::EchoMessage(echo_message))? {
+            ServerReply::Echo(content) => Ok(content),
+            ServerReply::Error { content, .. } => Err(io::Error::other(content)),
+            reply => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected EchoMessage, but received {:?}", reply),
+            )),
+        }
+    }
+
+    /// Add two integers on the server; the outer result fails when the call does, or when
+    /// the server answers with anything else, the inner one when the server could not
+    /// perform the addition, e.g. on overflow.
+    pub fn add(&mut self, a: i32, b: i32) -> io::Result<Result<i32, ServerError>> {
+        match self.call(client_message::Message::AddRequest(AddRequest { a, b }))? {
+            ServerReply::Add(result) => Ok(result),
+            ServerReply::Error { content, .. } => Err(io::Error::other(content)),
+            reply => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected AddResponse, but received {:?}", reply),
+            )),
+        }
+    }
+
+    /// Send several requests in a single round trip, returning a result per request in
+    /// the same order; the requests the server could not serve fail with its error message.
+    pub fn batch(&mut self, requests: Vec<ClientMessage>) -> Vec<io::Result<ServerMessage>> {
+        let count = requests.len();
+        match self.call_message(client_message::Message::BatchRequest(BatchRequest { requests })) {
+            Ok(ServerMessage {
+                message: Some(server_message::Message::BatchResponse(batch)),
+                ..
+            }) if batch.responses.len() == count => batch
+                .responses
+                .into_iter()
+                .map(|response| match response.message {
+                    Some(server_message::Message::ErrorMessage(error)) => Err(io::Error::other(error.content)),
+                    _ => Ok(response),
+                })
+                .collect(),
+            Ok(_) => (0..count)
+                .map(|_| Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected response to the batch")))
+                .collect(),
+            // io::Error is not Clone, so every request gets a copy of the error
+            Err(e) => (0..count).map(|_| Err(io::Error::new(e.kind(), e.to_string()))).collect(),
+        }
+    }
+
+    /// Iterate over every message the server sends, responses and pushes alike, blocking
+    /// until the next one arrives; the iteration ends once the server disconnects.
+    pub fn responses(&mut self) -> Responses<'_> {
+        Responses { client: self }
+    }
+}
+
+/// Blocking iterator over the messages received on a connection, see `Client::responses()`.
+pub struct Responses<'a> {
+    client: &'a mut Client,
+}
+
+impl Iterator for Responses<'_> {
+    type Item = ServerMessage;
+
+    fn next(&mut self) -> Option<ServerMessage> {
+        loop {
+            match self.client.receive() {
+                Ok(message) => return Some(message),
+                // Waiting for the next message is not a failure here
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => error!("Skipping message: {}", e),
+                Err(e) => {
+                    info!("No more responses: {}", e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// The cheapest round trip, used to keep connections warm.
+fn ping_message() -> client_message::Message {
+    client_message::Message::EchoMessage(EchoMessage::default())
+}
+
+/// How many times a single call follows the server redirecting the client elsewhere.
+const MAX_REDIRECTS: u32 = 3;
+
+/// The reconnect hint of a goodbye the server sent on its own.
+fn reconnect_hint(response: &ServerMessage) -> Option<&ReconnectHint> {
+    match response.message {
+        Some(server_message::Message::ByeMessage(ref bye)) => bye.reconnect_hint.as_ref(),
+        _ => None,
+    }
+}
+
+/// How long a thread waiting on a `SharedClient` reads the connection for every pending
+/// call, before letting the other threads send their requests.
+const READ_TURN: Duration = Duration::from_millis(10);
+
+/// Handle on a single connection shared by several threads, each of which may have a call
+/// in flight: the requests are sent right away, and the responses are routed to their call
+/// by request id by whichever waiting thread is reading the connection.
+#[derive(Clone)]
+pub struct SharedClient {
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    client: Mutex<Client>,
+    // The calls waiting for their response, by request id
+    pending: Mutex<HashMap<u64, mpsc::Sender<ServerMessage>>>,
+}
+
+impl SharedClient {
+    /// Shares `client` between threads.
+    pub fn new(client: Client) -> Self {
+        SharedClient {
+            inner: Arc::new(ClientInner {
+                client: Mutex::new(client),
+                pending: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Send a request and wait for its response, as `Client::call_message()` does, while
+    /// the other threads make their own calls. The messages the server sends on its own,
+    /// such as the shut down notice, are returned to every pending call.
+    pub fn call(&self, message: client_message::Message) -> io::Result<ServerMessage> {
+        let start = Instant::now();
+        let (sender, receiver) = mpsc::channel();
+        let (request_id, max_retries, timeout) =
+            self.with_client(|client| (client.begin_call(), client.max_retries, client.timeout));
+        self.inner.pending.lock().unwrap().insert(request_id, sender);
+
+        let mut attempts = 0;
+        let mut redirects = 0;
+        let result = loop {
+            attempts += 1;
+            let result = self
+                .with_client(|client| {
+                    if attempts > 1 {
+                        client.stats.retries += 1;
+                    }
+                    client
+                        .reconnect_if_hinted()
+                        .and_then(|_| client.send_with_id(message.clone(), Some(request_id)))
+                })
+                .and_then(|_| self.wait_response(&receiver, timeout));
+            match result {
+                Err(ref e)
+                    if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+                        && attempts <= max_retries => {}
+                // The server closed the connection instead of answering
+                Ok(ref response) if reconnect_hint(response).is_some() && redirects < MAX_REDIRECTS => {
+                    redirects += 1;
+                }
+                result => break result,
+            }
+        };
+        self.inner.pending.lock().unwrap().remove(&request_id);
+
+        self.with_client(|client| client.end_call(&message, result, start, attempts))
+    }
+
+    /// Wait at most `timeout` for the response routed to `receiver`, taking turns with the
+    /// other waiting threads at reading the connection.
+    fn wait_response(&self, receiver: &mpsc::Receiver<ServerMessage>, timeout: Duration) -> io::Result<ServerMessage> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Ok(response) = receiver.try_recv() {
+                return Ok(response);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                self.with_client(|client| client.stats.timeouts += 1);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "No response to the call"));
+            }
+
+            let turn = remaining.min(READ_TURN);
+            match self.inner.client.try_lock() {
+                Ok(mut client) => {
+                    if let Some(response) = client.poll_response(turn)? {
+                        self.route(response);
+                    }
+                }
+                // Another thread is sending, or reading and handing the responses over
+                Err(_) => {
+                    if let Ok(response) = receiver.recv_timeout(turn) {
+                        return Ok(response);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hand `response` to the call it answers, or to every pending call when the server
+    /// sent it on its own.
+    fn route(&self, response: ServerMessage) {
+        let pending = self.inner.pending.lock().unwrap();
+        match response.request_id {
+            Some(id) => match pending.get(&id) {
+                // The receiver is gone if the call just timed out
+                Some(sender) => {
+                    let _ = sender.send(response);
+                }
+                None => warn!("Dropped the late response to request {}", id),
+            },
+            None => {
+                for sender in pending.values() {
+                    let _ = sender.send(response.clone());
+                }
+            }
+        }
+    }
+
+    /// Run `f` with exclusive access to the client, e.g. to connect or disconnect it.
+    pub fn with_client<T>(&self, f: impl FnOnce(&mut Client) -> T) -> T {
+        f(&mut self.inner.client.lock().unwrap())
+    }
+
+    /// Keep the connection warm from a background thread: whenever it was idle for
+    /// `interval`, ping the server, reconnecting first if the server closed it; a
+    /// connection the application closed is left alone, and the thread ends with the
+    /// last handle on the client.
+    pub fn keep_warm(&self, interval: Duration) {
+        let inner = Arc::downgrade(&self.inner);
+        thread::spawn(move || loop {
+            thread::sleep(time_scale::scale(interval / 2));
+            let Some(inner) = inner.upgrade() else {
+                return;
+            };
+            let shared = SharedClient { inner };
+            let idle = shared.with_client(|client| {
+                if client.stream.is_none() {
+                    return Ok(None);
+                }
+                client.preconnect().map(|_| Some(client.idle_time()))
+            });
+            let result = match idle {
+                Ok(Some(idle)) if idle >= interval => shared.call(ping_message()).map(|_| ()),
+                idle => idle.map(|_| ()),
+            };
+            if let Err(e) = result {
+                error!("Failed to keep the connection warm: {}", e);
+            }
+        });
+    }
+}
+
+/// Delay before racing the next address while an attempt is still pending (RFC 8305).
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connect to the first reachable address, alternating address families and starting
+/// a new attempt whenever the previous one failed or is taking too long.
+pub fn connect_happy_eyeballs(addrs: &[SocketAddr], timeout: Duration) -> io::Result<TcpStream> {
+    // Interleave the families, starting with the one the resolver preferred
+    let prefer_v6 = addrs.first().is_some_and(|addr| addr.is_ipv6());
+    let (mut preferred, mut other): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().partition(|addr| addr.is_ipv6() == prefer_v6);
+    let mut ordered = Vec::with_capacity(addrs.len());
+    while !preferred.is_empty() || !other.is_empty() {
+        if !preferred.is_empty() {
+            ordered.push(preferred.remove(0));
+        }
+        if !other.is_empty() {
+            ordered.push(other.remove(0));
+        }
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let mut pending = 0;
+    let mut last_error = None;
+    for addr in ordered {
+        let sender = sender.clone();
+        thread::spawn(move || {
+            // The receiver is gone once another attempt won
+            let _ = sender.send(TcpStream::connect_timeout(&addr, timeout));
+        });
+        pending += 1;
+
+        // Give the attempt a head start before racing the next address
+        match receiver.recv_timeout(CONNECTION_ATTEMPT_DELAY) {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => {
+                pending -= 1;
+                last_error = Some(e);
+            }
+            Err(_) => {}
+        }
+    }
+
+    // Every address was tried, wait for the attempts still in flight
+    while pending > 0 {
+        match receiver.recv() {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => break,
+        }
+        pending -= 1;
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Invalid IP or port")
+    }))
+}
+
+/// Whether `frame` holds the response to an upgrade request.
+pub(crate) fn is_upgrade_response(frame: &[u8]) -> bool {
+    matches!(
+        ServerMessage::decode(frame),
+        Ok(ServerMessage {
+            message: Some(server_message::Message::UpgradeResponse(_)),
+            ..
+        })
+    )
+}
+
+fn server_disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "Server disconnected")
+}
+
+/// How the responses of a fan-out call are aggregated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FanOut {
+    /// Return as soon as one server answered.
+    FirstSuccess,
+    /// Return as soon as the given number of servers answered.
+    Quorum(usize),
+    /// Wait for every server to answer.
+    All,
+}
+
+/// Sends the same request to several servers concurrently.
+pub struct MultiClient {
+    servers: Vec<(String, u32)>,
+    timeout_ms: u64,
+}
+
+impl MultiClient {
+    /// Creates a client of `servers`, each given by address and port, connecting to them on
+    /// every call and waiting `timeout_ms` for each.
+    pub fn new(servers: &[(&str, u32)], timeout_ms: u64) -> Self {
+        MultiClient {
+            servers: servers.iter().map(|(ip, port)| (ip.to_string(), *port)).collect(),
+            timeout_ms,
+        }
+    }
+
+    /// Call every server with the message and aggregate the responses per `mode`.
+    pub fn call(&self, message: client_message::Message, mode: FanOut) -> io::Result<Vec<ServerMessage>> {
+        let needed = match mode {
+            FanOut::FirstSuccess => 1,
+            FanOut::Quorum(count) => count,
+            FanOut::All => self.servers.len(),
+        };
+
+        // Each server is called from its own thread with its own connection
+        let (sender, receiver) = mpsc::channel();
+        for (ip, port) in self.servers.iter().cloned() {
+            let sender = sender.clone();
+            let message = message.clone();
+            let timeout_ms = self.timeout_ms;
+            thread::spawn(move || {
+                let mut client = Client::new(&ip, port, timeout_ms);
+                let result = client.connect().and_then(|_| client.call_message(message));
+                let _ = client.disconnect();
+                // The receiver is gone once enough responses were collected
+                let _ = sender.send(result);
+            });
+        }
+        drop(sender);
+
+        let mut responses = Vec::new();
+        let mut last_error = None;
+        for result in receiver {
+            match result {
+                Ok(response) => {
+                    responses.push(response);
+                    if responses.len() >= needed {
+                        return Ok(responses);
+                    }
+                }
+                Err(e) => {
+                    error!("Fan-out call failed: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| io::Error::other("Not enough servers answered")))
+    }
+}
+
+/// Network misbehaviors injected by a `ChaosClient`.
+#[derive(Debug, Clone, Copy)]
+pub struct ChaosSchedule {
+    /// Same seed, same sequence of delays, duplicates and orders.
+    pub seed: u64,
+    /// Delay before each request, picked uniformly in the range.
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// Probability, between 0 and 1, of sending a request a second time.
+    pub duplicate_probability: f64,
+    /// Whether `call_all` sends the requests in a shuffled order.
+    pub reorder: bool,
+}
+
+impl Default for ChaosSchedule {
+    fn default() -> Self {
+        ChaosSchedule {
+            seed: 0,
+            min_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            duplicate_probability: 0.0,
+            reorder: false,
+        }
+    }
+}
+
+/// What a `ChaosClient` injected so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub delayed: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    /// Duplicates that could not be sent, or whose response did not arrive.
+    pub failed_duplicates: u64,
+}
+
+/// Client wrapper injecting latency, duplicated requests and reordered calls, so that
+/// applications can be tested against the network behaviors the server may see.
+pub struct ChaosClient {
+    client: Client,
+    schedule: ChaosSchedule,
+    // xorshift64* state, never zero
+    state: u64,
+    stats: ChaosStats,
+}
+
+/// A request sent by a `ChaosClient`, waiting for its responses.
+struct ChaosCall {
+    request_id: u64,
+    sent_at: Instant,
+    // Two when the request was duplicated
+    responses_due: usize,
+    response: Option<io::Result<ServerMessage>>,
+}
+
+impl ChaosClient {
+    /// Wraps `client`, misbehaving as set by `schedule`.
+    pub fn new(client: Client, schedule: ChaosSchedule) -> Self {
+        ChaosClient {
+            client,
+            schedule,
+            state: schedule.seed.max(1),
+            stats: ChaosStats::default(),
+        }
+    }
+
+    /// The wrapped client, e.g. to connect it.
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    /// Snapshot of what was injected.
+    pub fn stats(&self) -> ChaosStats {
+        self.stats
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Call the server after the scheduled delay, sending the request frame twice when the
+    /// schedule duplicates it; the response to the duplicate is read and dropped.
+    pub fn call(&mut self, message: client_message::Message) -> io::Result<ServerMessage> {
+        self.call_all(vec![message]).pop().expect("A result per message")
+    }
+
+    /// Make several calls, each after the scheduled delay, returning the results in the
+    /// order of the messages. The requests are all sent before the responses are read, in
+    /// a shuffled order when the schedule reorders them, so that they are in flight
+    /// together and reach the server out of order.
+    pub fn call_all(&mut self, messages: Vec<client_message::Message>) -> Vec<io::Result<ServerMessage>> {
+        let mut order: Vec<usize> = (0..messages.len()).collect();
+        if self.schedule.reorder {
+            // Fisher-Yates shuffle
+            for i in (1..order.len()).rev() {
+                let j = (self.next_u64() % (i as u64 + 1)) as usize;
+                order.swap(i, j);
+            }
+            self.stats.reordered += order.iter().enumerate().filter(|(i, index)| i != *index).count() as u64;
+        }
+
+        let mut calls: Vec<Option<ChaosCall>> = messages.iter().map(|_| None).collect();
+        for index in order {
+            calls[index] = Some(self.send(&messages[index]));
+        }
+        let mut calls: Vec<ChaosCall> = calls.into_iter().map(|call| call.expect("Every message is sent")).collect();
+        self.collect_responses(&mut calls);
+
+        messages
+            .iter()
+            .zip(calls)
+            .map(|(message, call)| {
+                let response = call.response.expect("Every call is answered or failed");
+                self.client.end_call(message, response, call.sent_at, 1)
+            })
+            .collect()
+    }
+
+    /// Send `message` after the scheduled delay, and the same frame once more when the
+    /// schedule duplicates it, as a network retransmitting it would.
+    fn send(&mut self, message: &client_message::Message) -> ChaosCall {
+        let spread = self.schedule.max_latency.saturating_sub(self.schedule.min_latency);
+        let delay = self.schedule.min_latency + spread.mul_f64(self.next_f64());
+        if !delay.is_zero() {
+            self.stats.delayed += 1;
+            thread::sleep(delay);
+        }
+
+        let duplicate = self.next_f64() < self.schedule.duplicate_probability;
+        let mut call = ChaosCall {
+            request_id: self.client.begin_call(),
+            sent_at: Instant::now(),
+            responses_due: 0,
+            response: None,
+        };
+        let frame = self.client.encode_request(message.clone(), Some(call.request_id));
+        if let Err(e) = self.client.reconnect_if_hinted().and_then(|_| self.client.send_raw(&frame)) {
+            call.response = Some(Err(e));
+            return call;
+        }
+        call.responses_due = 1;
+
+        if duplicate {
+            self.stats.duplicated += 1;
+            match self.client.send_raw(&frame) {
+                Ok(()) => call.responses_due += 1,
+                Err(e) => {
+                    warn!("Failed to duplicate request {}: {}", call.request_id, e);
+                    self.stats.failed_duplicates += 1;
+                }
+            }
+        }
+        call
+    }
+
+    /// Read the responses to `calls` by request id, until every call has one and the
+    /// responses to the duplicates were dropped. The messages the server sends on its own
+    /// answer every call still waiting, and a failed read fails them.
+    fn collect_responses(&mut self, calls: &mut [ChaosCall]) {
+        while calls.iter().any(|call| call.responses_due > 0) {
+            let response = match self.client.receive() {
+                Ok(response) => response,
+                Err(e) => {
+                    for call in calls.iter_mut().filter(|call| call.responses_due > 0) {
+                        if call.response.is_some() {
+                            warn!("No response to the duplicate of request {}: {}", call.request_id, e);
+                            self.stats.failed_duplicates += 1;
+                        } else {
+                            // io::Error is not Clone, so every call gets a copy of the error
+                            call.response = Some(Err(io::Error::new(e.kind(), e.to_string())));
+                        }
+                        call.responses_due = 0;
+                    }
+                    return;
+                }
+            };
+
+            let Some(request_id) = response.request_id else {
+                for call in calls.iter_mut().filter(|call| call.response.is_none()) {
+                    call.response = Some(Ok(response.clone()));
+                    call.responses_due -= 1;
+                }
+                continue;
+            };
+            match calls.iter_mut().find(|call| call.request_id == request_id && call.responses_due > 0) {
+                Some(call) => {
+                    call.responses_due -= 1;
+                    if call.response.is_none() {
+                        call.response = Some(Ok(response));
+                    } else {
+                        debug!("Dropped the response to the duplicate of request {}", request_id);
+                    }
+                }
+                None => warn!("Dropped the late response to request {}", request_id),
+            }
+        }
+    }
+}
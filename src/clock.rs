@@ -0,0 +1,83 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Source of time for the time-based features of the server.
+///
+/// Production code uses `SystemClock`, while tests can use `ManualClock` to make
+/// time-dependent behavior deterministic instead of relying on real sleeps.
+pub trait Clock: Send + Sync {
+    /// The current instant.
+    fn now(&self) -> Instant;
+
+    /// Blocks the calling thread until `duration` elapsed on this clock.
+    fn sleep(&self, duration: Duration);
+}
+
+/// Shared handle on a clock.
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The real, monotonic, system clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when `advance()` is called.
+///
+/// Threads sleeping on it wake up once the clock was advanced past their deadline.
+pub struct ManualClock {
+    now: Mutex<Instant>,
+    advanced: Condvar,
+}
+
+impl ManualClock {
+    /// Creates a clock frozen at the current instant.
+    pub fn new() -> Self {
+        ManualClock {
+            now: Mutex::new(Instant::now()),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Moves the clock forward, waking the sleepers whose deadline was reached.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+        self.advanced.notify_all();
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        let deadline = *now + duration;
+        while *now < deadline {
+            now = self.advanced.wait(now).unwrap();
+        }
+    }
+}
+
+/// The clock used when none is configured.
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
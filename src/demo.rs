@@ -0,0 +1,34 @@
+use log::{Level, Log, Metadata, Record};
+use std::sync::OnceLock;
+
+/// Prints the log records to the standard error, for the demo mode and the examples,
+/// which should not need a logging setup of their own.
+struct ConsoleLogger {
+    level: Level,
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{:<5} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs the records up to `level` to the standard error.
+///
+/// Does nothing when the application installed a logger already, so that it is always
+/// safe to call.
+pub fn init_console_logging(level: Level) {
+    static LOGGER: OnceLock<ConsoleLogger> = OnceLock::new();
+    let logger = LOGGER.get_or_init(|| ConsoleLogger { level });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level.to_level_filter());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::message::{EvalError, EvalErrorCode};
+use std::collections::HashMap;
+
+/// How deeply parentheses and unary minuses may nest, so that a hostile expression
+/// cannot overflow the stack of the worker.
+pub const MAX_DEPTH: usize = 64;
+
+/// Evaluates an integer expression made of `+ - * / %`, parentheses, integer literals
+/// and variables, with the usual precedence. Arithmetic is checked: overflows and
+/// divisions by zero are errors rather than panics.
+///
+/// # Arguments
+/// - `expression` The expression, e.g. `3*(a+b)`.
+/// - `vars` The values of the variables used in the expression.
+///
+/// # Returns
+/// - Ok    with the value of the expression.
+/// - Err   with the kind and position of the first error.
+pub fn evaluate(expression: &str, vars: &HashMap<String, i64>) -> Result<i64, EvalError> {
+    let mut parser = Parser {
+        input: expression.as_bytes(),
+        position: 0,
+        depth: 0,
+        vars,
+    };
+    let value = parser.expression()?;
+    parser.skip_whitespace();
+    if parser.position < parser.input.len() {
+        return Err(parser.error(EvalErrorCode::Parse, "Unexpected character"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+    depth: usize,
+    vars: &'a HashMap<String, i64>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, code: EvalErrorCode, detail: &str) -> EvalError {
+        self.error_at(self.position, code, detail)
+    }
+
+    fn error_at(&self, position: usize, code: EvalErrorCode, detail: &str) -> EvalError {
+        EvalError {
+            code: code.into(),
+            detail: detail.to_string(),
+            position: position as u32,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.input.get(self.position).is_some_and(u8::is_ascii_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    // The next significant character, without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.position).copied()
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<i64, EvalError> {
+        let mut value = self.term()?;
+        while let Some(operator @ (b'+' | b'-')) = self.peek() {
+            let position = self.position;
+            self.position += 1;
+            let rhs = self.term()?;
+            let result = if operator == b'+' { value.checked_add(rhs) } else { value.checked_sub(rhs) };
+            value = result.ok_or_else(|| self.error_at(position, EvalErrorCode::Overflow, "Overflow"))?;
+        }
+        Ok(value)
+    }
+
+    // term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Result<i64, EvalError> {
+        let mut value = self.unary()?;
+        while let Some(operator @ (b'*' | b'/' | b'%')) = self.peek() {
+            let position = self.position;
+            self.position += 1;
+            let rhs = self.unary()?;
+            if operator != b'*' && rhs == 0 {
+                return Err(self.error_at(position, EvalErrorCode::DivisionByZero, "Division by zero"));
+            }
+            let result = match operator {
+                b'*' => value.checked_mul(rhs),
+                b'/' => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            };
+            value = result.ok_or_else(|| self.error_at(position, EvalErrorCode::Overflow, "Overflow"))?;
+        }
+        Ok(value)
+    }
+
+    // unary := '-' unary | primary
+    fn unary(&mut self) -> Result<i64, EvalError> {
+        if self.peek() != Some(b'-') {
+            return self.primary();
+        }
+        let position = self.position;
+        self.position += 1;
+        self.enter()?;
+        let value = self.unary()?;
+        self.depth -= 1;
+        value.checked_neg().ok_or_else(|| self.error_at(position, EvalErrorCode::Overflow, "Overflow"))
+    }
+
+    // primary := number | variable | '(' expression ')'
+    fn primary(&mut self) -> Result<i64, EvalError> {
+        let start = self.position;
+        match self.peek() {
+            Some(b'(') => {
+                self.position += 1;
+                self.enter()?;
+                let value = self.expression()?;
+                self.depth -= 1;
+                if self.peek() != Some(b')') {
+                    return Err(self.error(EvalErrorCode::Parse, "Expected ')'"));
+                }
+                self.position += 1;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let token = self.token(|c| c.is_ascii_digit());
+                token.parse().map_err(|_| self.error_at(start, EvalErrorCode::Overflow, "Literal out of range"))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == b'_' => {
+                let start = self.position;
+                let name = self.token(|c| c.is_ascii_alphanumeric() || c == b'_');
+                let vars: &'a HashMap<String, i64> = self.vars;
+                vars.get(name).copied().ok_or_else(|| {
+                    self.error_at(start, EvalErrorCode::UnknownVariable, &format!("Unknown variable {}", name))
+                })
+            }
+            Some(_) => Err(self.error(EvalErrorCode::Parse, "Unexpected character")),
+            None => Err(self.error(EvalErrorCode::Parse, "Unexpected end of expression")),
+        }
+    }
+
+    // Consumes the characters matching `accept`, which are all ASCII.
+    fn token(&mut self, accept: impl Fn(u8) -> bool) -> &'a str {
+        let start = self.position;
+        while self.input.get(self.position).is_some_and(|&c| accept(c)) {
+            self.position += 1;
+        }
+        let input: &'a [u8] = self.input;
+        std::str::from_utf8(&input[start..self.position]).expect("Tokens are ASCII")
+    }
+
+    fn enter(&mut self) -> Result<(), EvalError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error(EvalErrorCode::Parse, "Expression is nested too deeply"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use log::{debug, info};
+use std::{
+    fs, io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// File descriptors kept free by default for the listener, logs, PID file and the
+/// like, on top of those used by the connections.
+pub const DEFAULT_HEADROOM: u64 = 32;
+
+/// File descriptors each connection may take at once: its stream, the handle the server
+/// keeps on it to reach the client from outside its worker, the clone its delayed-echo
+/// timer thread writes to, and the clone taken to notify it of the shut down.
+pub const FDS_PER_CONNECTION: u64 = 4;
+
+/// The soft limit on the number of file descriptors the process may open.
+///
+/// # Returns
+/// - Ok    with the limit, `u64::MAX` when unlimited.
+/// - Err   when the limit could not be read, or on platforms without one.
+#[cfg(unix)]
+pub fn soft_limit() -> io::Result<u64> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: `limit` is a valid rlimit for getrlimit to fill.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        return Ok(u64::MAX);
+    }
+    // rlim_t is not 64-bit on every platform.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(limit.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+pub fn soft_limit() -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "No file descriptor limit"))
+}
+
+/// The number of file descriptors the process currently has open.
+///
+/// # Returns
+/// - Ok    with the count.
+/// - Err   when the platform does not list them in `/proc/self/fd` or `/dev/fd`.
+pub fn open_count() -> io::Result<u64> {
+    let entries = fs::read_dir("/proc/self/fd").or_else(|_| fs::read_dir("/dev/fd"))?;
+    // The descriptor used to list the directory is not counted.
+    Ok((entries.count() as u64).saturating_sub(1))
+}
+
+/// Tells when the process is about to run out of file descriptors, so that new
+/// connections can be refused before accepts start failing.
+///
+/// The limit and the descriptors open beforehand are read once, the connections are
+/// counted as they are reserved and released, so that nothing is read per accept. As
+/// that estimate drifts from what is actually open, e.g. as connections seldom take all
+/// their descriptors, the open descriptors are read again before refusing a connection
+/// when the budget was detected.
+#[derive(Debug, Clone)]
+pub struct FdBudget {
+    limit: u64,
+    headroom: u64,
+    // Open before the first connection, e.g. the listener and the logs.
+    baseline: u64,
+    // Shared with the reservations, which release their connection when dropped.
+    connections: Arc<AtomicU64>,
+    // Whether the open descriptors can be read again, see `detect()`.
+    recount: bool,
+}
+
+/// The file descriptors of a connection, given back to the budget once dropped.
+#[derive(Debug)]
+pub struct FdReservation {
+    connections: Arc<AtomicU64>,
+}
+
+impl Drop for FdReservation {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl FdBudget {
+    /// Creates a budget keeping `headroom` descriptors free below `limit`.
+    pub fn new(limit: u64, headroom: u64) -> Self {
+        FdBudget {
+            limit,
+            headroom,
+            baseline: 0,
+            connections: Arc::default(),
+            recount: false,
+        }
+    }
+
+    /// Creates a budget from the soft limit of the process, counting the descriptors it
+    /// has open already.
+    ///
+    /// # Returns
+    /// - Ok    with the budget.
+    /// - Err   when the limit or the open descriptors cannot be read on this platform.
+    pub fn detect(headroom: u64) -> io::Result<Self> {
+        let limit = soft_limit()?;
+        let open = open_count()?;
+        info!("File descriptor limit is {}, {} are open, keeping {} free", limit, open, headroom);
+        Ok(FdBudget {
+            baseline: open,
+            recount: true,
+            ..FdBudget::new(limit, headroom)
+        })
+    }
+
+    /// The soft limit of the budget.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The descriptors counted as open: those open beforehand, and those of the
+    /// connections reserved since.
+    pub fn open(&self) -> u64 {
+        self.open_with(self.connections.load(Ordering::SeqCst))
+    }
+
+    /// The descriptors counted as open with `connections` reserved.
+    fn open_with(&self, connections: u64) -> u64 {
+        self.baseline.saturating_add(connections.saturating_mul(FDS_PER_CONNECTION))
+    }
+
+    /// Whether `open` descriptors leave less than the headroom below the limit.
+    pub fn is_exhausted_at(&self, open: u64) -> bool {
+        open.saturating_add(self.headroom) >= self.limit
+    }
+
+    /// Whether the descriptors counted as open leave no room for another connection
+    /// above the headroom.
+    pub fn is_exhausted(&self) -> bool {
+        self.is_exhausted_at(self.open_with(self.connections.load(Ordering::SeqCst) + 1))
+    }
+
+    /// Counts the descriptors of a new connection, accepted already, unless they would
+    /// exhaust the budget.
+    ///
+    /// # Returns
+    /// - Some  with the reservation, to be dropped once the connection is released.
+    /// - None  when the connection should be refused.
+    pub fn reserve(&self) -> Option<FdReservation> {
+        let counted = self
+            .connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |connections| {
+                (!self.is_exhausted_at(self.open_with(connections + 1))).then_some(connections + 1)
+            })
+            .is_ok();
+        if !counted {
+            if !self.recount {
+                return None;
+            }
+            // The stream of the connection is open already, its other descriptors not yet.
+            let open = open_count().ok()?.saturating_add(FDS_PER_CONNECTION - 1);
+            if self.is_exhausted_at(open) {
+                return None;
+            }
+            debug!("{} file descriptors are open, fewer than counted, accepting the connection", open);
+            self.connections.fetch_add(1, Ordering::SeqCst);
+        }
+        Some(FdReservation {
+            connections: self.connections.clone(),
+        })
+    }
+}
@@ -0,0 +1,349 @@
+use crate::message::{
+    add_response, client_message, eval_response, server_message, AddError, AddErrorCode, AddRequest, AddResponse,
+    BatchRequest, BatchResponse, ByeMessage, ClientMessage, DelayedEchoRequest, DescriptorRequest, DescriptorResponse,
+    EchoMessage, ErrorCode, ErrorMessage, EvalError, EvalErrorCode, EvalRequest, EvalResponse, MyStatsRequest,
+    MyStatsResponse, ReconnectHint, ServerMessage, SessionSummary, ShutdownReason, Transport, UpgradeRequest,
+    UpgradeResponse,
+};
+use prost::Message;
+
+/// A message in either direction of the protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WireMessage {
+    Client(ClientMessage),
+    Server(ServerMessage),
+}
+
+impl WireMessage {
+    fn encode_to_vec(&self) -> Vec<u8> {
+        match self {
+            WireMessage::Client(message) => message.encode_to_vec(),
+            WireMessage::Server(message) => message.encode_to_vec(),
+        }
+    }
+
+    // Decodes `encoded` as a message of the same direction as `self`.
+    fn decode_like(&self, encoded: &[u8]) -> Result<WireMessage, prost::DecodeError> {
+        match self {
+            WireMessage::Client(_) => ClientMessage::decode(encoded).map(WireMessage::Client),
+            WireMessage::Server(_) => ServerMessage::decode(encoded).map(WireMessage::Server),
+        }
+    }
+}
+
+/// A message pinned to the bytes that were sent on the wire when it was introduced.
+///
+/// The bytes must never change: peers built against older versions of the protocol
+/// still send and expect them.
+pub struct Fixture {
+    pub name: &'static str,
+    pub encoded: &'static [u8],
+    pub message: fn() -> WireMessage,
+}
+
+impl Fixture {
+    /// Checks that the message still encodes to, and decodes from, the pinned bytes.
+    ///
+    /// # Returns
+    /// - Ok    when the current messages are compatible with the fixture.
+    /// - Err   describing the mismatch otherwise.
+    pub fn check(&self) -> Result<(), String> {
+        let message = (self.message)();
+
+        let encoded = message.encode_to_vec();
+        if encoded != self.encoded {
+            return Err(format!("{} encodes to {:02x?} instead of {:02x?}", self.name, encoded, self.encoded));
+        }
+
+        match message.decode_like(self.encoded) {
+            Ok(decoded) if decoded == message => Ok(()),
+            Ok(decoded) => Err(format!("{} decodes to {:?} instead of {:?}", self.name, decoded, message)),
+            Err(e) => Err(format!("{} fails to decode: {}", self.name, e)),
+        }
+    }
+}
+
+fn client(message: client_message::Message) -> WireMessage {
+    WireMessage::Client(ClientMessage { message: Some(message), ..Default::default() })
+}
+
+fn server(message: server_message::Message) -> WireMessage {
+    WireMessage::Server(ServerMessage { message: Some(message), ..Default::default() })
+}
+
+fn add_result(result: i32) -> AddResponse {
+    AddResponse { outcome: Some(add_response::Outcome::Result(result)) }
+}
+
+/// The golden encoding of every message of the protocol.
+pub const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "client echo",
+        encoded: &[0x0a, 0x04, 0x0a, 0x02, b'h', b'i'],
+        message: || client(client_message::Message::EchoMessage(EchoMessage { content: "hi".to_string() })),
+    },
+    Fixture {
+        name: "client echo with metadata",
+        // A single entry, as maps are encoded in no particular order.
+        encoded: &[0x0a, 0x04, 0x0a, 0x02, b'h', b'i', 0x7a, 0x06, 0x0a, 0x01, b'k', 0x12, 0x01, b'v'],
+        message: || {
+            WireMessage::Client(ClientMessage {
+                message: Some(client_message::Message::EchoMessage(EchoMessage { content: "hi".to_string() })),
+                metadata: [("k".to_string(), "v".to_string())].into(),
+                ..Default::default()
+            })
+        },
+    },
+    Fixture {
+        name: "client echo with request id",
+        encoded: &[0x0a, 0x04, 0x0a, 0x02, b'h', b'i', 0x70, 0x07],
+        message: || {
+            WireMessage::Client(ClientMessage {
+                message: Some(client_message::Message::EchoMessage(EchoMessage { content: "hi".to_string() })),
+                request_id: Some(7),
+                ..Default::default()
+            })
+        },
+    },
+    Fixture {
+        name: "client add",
+        // A negative operand pins the int32 encoding, which differs from sint32's.
+        encoded: &[
+            0x12, 0x0d, 0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x10, 0x02,
+        ],
+        message: || client(client_message::Message::AddRequest(AddRequest { a: -1, b: 2 })),
+    },
+    Fixture {
+        name: "client bye",
+        encoded: &[0x1a, 0x00],
+        message: || client(client_message::Message::ByeMessage(ByeMessage::default())),
+    },
+    Fixture {
+        name: "client my stats",
+        encoded: &[0x22, 0x00],
+        message: || client(client_message::Message::MyStatsRequest(MyStatsRequest {})),
+    },
+    Fixture {
+        name: "client delayed echo",
+        encoded: &[0x2a, 0x06, 0x0a, 0x02, b'h', b'i', 0x10, 0x05],
+        message: || {
+            client(client_message::Message::DelayedEchoRequest(DelayedEchoRequest {
+                content: "hi".to_string(),
+                delay_ms: 5,
+            }))
+        },
+    },
+    Fixture {
+        name: "client descriptor",
+        encoded: &[0x32, 0x00],
+        message: || client(client_message::Message::DescriptorRequest(DescriptorRequest {})),
+    },
+    Fixture {
+        name: "client batch",
+        encoded: &[0x3a, 0x0a, 0x0a, 0x02, 0x1a, 0x00, 0x0a, 0x04, 0x12, 0x02, 0x08, 0x01],
+        message: || {
+            client(client_message::Message::BatchRequest(BatchRequest {
+                requests: vec![
+                    ClientMessage {
+                        message: Some(client_message::Message::ByeMessage(ByeMessage::default())),
+                        ..Default::default()
+                    },
+                    ClientMessage {
+                        message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 0 })),
+                        ..Default::default()
+                    },
+                ],
+            }))
+        },
+    },
+    Fixture {
+        name: "client eval",
+        // A single variable, as maps are encoded in no particular order.
+        encoded: &[0x42, 0x0a, 0x0a, 0x01, b'a', 0x12, 0x05, 0x0a, 0x01, b'a', 0x10, 0x01],
+        message: || {
+            client(client_message::Message::EvalRequest(EvalRequest {
+                expression: "a".to_string(),
+                vars: [("a".to_string(), 1)].into(),
+            }))
+        },
+    },
+    Fixture {
+        name: "client upgrade",
+        encoded: &[0x4a, 0x02, 0x08, 0x01],
+        message: || {
+            client(client_message::Message::UpgradeRequest(UpgradeRequest {
+                transport: Transport::SyncMarker as i32,
+            }))
+        },
+    },
+    Fixture {
+        name: "server echo",
+        encoded: &[0x0a, 0x04, 0x0a, 0x02, b'h', b'i'],
+        message: || server(server_message::Message::EchoMessage(EchoMessage { content: "hi".to_string() })),
+    },
+    Fixture {
+        name: "server echo with request id",
+        // The id 0 is a request id like any other, and encoded as such.
+        encoded: &[0x0a, 0x04, 0x0a, 0x02, b'h', b'i', 0x70, 0x00],
+        message: || {
+            WireMessage::Server(ServerMessage {
+                message: Some(server_message::Message::EchoMessage(EchoMessage { content: "hi".to_string() })),
+                request_id: Some(0),
+                ..Default::default()
+            })
+        },
+    },
+    Fixture {
+        name: "server add",
+        encoded: &[0x12, 0x02, 0x08, 0x03],
+        message: || server(server_message::Message::AddResponse(add_result(3))),
+    },
+    Fixture {
+        name: "server add error",
+        encoded: &[0x12, 0x07, 0x12, 0x05, 0x08, 0x01, 0x12, 0x01, b'x'],
+        message: || {
+            server(server_message::Message::AddResponse(AddResponse {
+                outcome: Some(add_response::Outcome::Error(AddError {
+                    code: AddErrorCode::Overflow as i32,
+                    detail: "x".to_string(),
+                })),
+            }))
+        },
+    },
+    Fixture {
+        name: "server error",
+        encoded: &[0x1a, 0x06, 0x0a, 0x02, b'n', b'o', 0x10, 0x02],
+        message: || {
+            server(server_message::Message::ErrorMessage(ErrorMessage {
+                content: "no".to_string(),
+                shutdown_reason: ShutdownReason::Signal as i32,
+                ..Default::default()
+            }))
+        },
+    },
+    Fixture {
+        name: "server busy",
+        encoded: &[0x1a, 0x08, 0x0a, 0x04, b'b', b'u', b's', b'y', 0x18, 0x01],
+        message: || {
+            server(server_message::Message::ErrorMessage(ErrorMessage {
+                content: "busy".to_string(),
+                code: ErrorCode::ServerBusy as i32,
+                ..Default::default()
+            }))
+        },
+    },
+    Fixture {
+        name: "server bye",
+        encoded: &[0x22, 0x00],
+        message: || server(server_message::Message::ByeMessage(ByeMessage::default())),
+    },
+    Fixture {
+        name: "server bye with reconnect hint",
+        encoded: &[0x22, 0x08, 0x0a, 0x06, 0x08, 0xf4, 0x03, 0x12, 0x01, b'h'],
+        message: || {
+            server(server_message::Message::ByeMessage(ByeMessage {
+                reconnect_hint: Some(ReconnectHint {
+                    retry_after_ms: 500,
+                    alternate_addr: "h".to_string(),
+                }),
+                ..Default::default()
+            }))
+        },
+    },
+    Fixture {
+        name: "server bye with session summary",
+        encoded: &[0x22, 0x0c, 0x12, 0x0a, 0x08, 0x01, 0x10, 0x02, 0x18, 0x03, 0x20, 0x04, 0x28, 0x05],
+        message: || {
+            server(server_message::Message::ByeMessage(ByeMessage {
+                session_summary: Some(SessionSummary {
+                    requests_served: 1,
+                    bytes_received: 2,
+                    bytes_sent: 3,
+                    errors: 4,
+                    duration_ms: 5,
+                }),
+                ..Default::default()
+            }))
+        },
+    },
+    Fixture {
+        name: "server my stats",
+        encoded: &[0x2a, 0x08, 0x08, 0x01, 0x10, 0x02, 0x18, 0x03, 0x20, 0x04],
+        message: || {
+            server(server_message::Message::MyStatsResponse(MyStatsResponse {
+                requests_served: 1,
+                bytes_received: 2,
+                bytes_sent: 3,
+                session_age_ms: 4,
+            }))
+        },
+    },
+    Fixture {
+        name: "server descriptor",
+        encoded: &[0x32, 0x04, 0x0a, 0x02, 0x01, 0x02],
+        message: || {
+            server(server_message::Message::DescriptorResponse(DescriptorResponse {
+                file_descriptor_set: vec![0x01, 0x02],
+                ..Default::default()
+            }))
+        },
+    },
+    Fixture {
+        name: "server batch",
+        encoded: &[0x3a, 0x0a, 0x0a, 0x02, 0x22, 0x00, 0x0a, 0x04, 0x12, 0x02, 0x08, 0x03],
+        message: || {
+            server(server_message::Message::BatchResponse(BatchResponse {
+                responses: vec![
+                    ServerMessage {
+                        message: Some(server_message::Message::ByeMessage(ByeMessage::default())),
+                        ..Default::default()
+                    },
+                    ServerMessage {
+                        message: Some(server_message::Message::AddResponse(add_result(3))),
+                        ..Default::default()
+                    },
+                ],
+            }))
+        },
+    },
+    Fixture {
+        name: "server eval",
+        encoded: &[0x42, 0x02, 0x08, 0x07],
+        message: || {
+            server(server_message::Message::EvalResponse(EvalResponse {
+                outcome: Some(eval_response::Outcome::Result(7)),
+            }))
+        },
+    },
+    Fixture {
+        name: "server eval error",
+        encoded: &[0x42, 0x09, 0x12, 0x07, 0x08, 0x03, 0x12, 0x01, b'x', 0x18, 0x02],
+        message: || {
+            server(server_message::Message::EvalResponse(EvalResponse {
+                outcome: Some(eval_response::Outcome::Error(EvalError {
+                    code: EvalErrorCode::DivisionByZero as i32,
+                    detail: "x".to_string(),
+                    position: 2,
+                })),
+            }))
+        },
+    },
+    Fixture {
+        name: "server upgrade",
+        encoded: &[0x4a, 0x02, 0x08, 0x01],
+        message: || {
+            server(server_message::Message::UpgradeResponse(UpgradeResponse {
+                transport: Transport::SyncMarker as i32,
+            }))
+        },
+    },
+];
+
+/// Checks every fixture against the current messages, so that renumbered or retyped
+/// fields are caught before they reach the wire.
+///
+/// # Returns
+/// - A description of each incompatible fixture, empty when the protocol is compatible.
+pub fn verify() -> Vec<String> {
+    FIXTURES.iter().filter_map(|fixture| fixture.check().err()).collect()
+}
@@ -0,0 +1,598 @@
+use crate::message::Transport;
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Size of the length prefix that precedes every frame payload.
+pub const HEADER_LEN: usize = 4;
+
+/// Largest payload accepted by default, protecting the reader from absurd length prefixes.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// The length prefix of a keepalive, reserved as no frame is that long, and followed by
+/// no payload. Peers answer a keepalive with another one from their framing layer, without
+/// handing it to the application, so that it keeps the transport alive without showing up
+/// in the metrics or the logs. An empty frame is an empty message, like any other.
+pub const KEEPALIVE_PREFIX: u32 = u32::MAX;
+
+/// A keepalive, without sync marker.
+pub const KEEPALIVE_FRAME: [u8; HEADER_LEN] = KEEPALIVE_PREFIX.to_be_bytes();
+
+/// Set in the length prefix of the frames holding a fragment of a message, see
+/// `FrameFormat::fragment_len`. Their payload starts with a `FRAGMENT_HEADER_LEN` bytes
+/// header: the id of the message, then the index of the fragment and the number of
+/// fragments, all big-endian.
+pub const FRAGMENT_FLAG: u32 = 0x8000_0000;
+
+/// Size of the header that precedes the part of the message held by a fragment.
+pub const FRAGMENT_HEADER_LEN: usize = 8;
+
+/// Largest message reassembled from fragments by default.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+// Tells the fragments of the messages apart, unique across the connections of the process.
+static NEXT_MESSAGE_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Precedes every frame when the peers agreed on a `FrameFormat` with sync markers, so
+/// that a reader can find the next frame after corrupted bytes. Its bytes are distinct,
+/// so that scanning for it never needs to backtrack.
+pub const SYNC_MARKER: [u8; 4] = [0xfa, 0xce, 0xb0, 0x0c];
+
+/// Whether `first_byte`, the first byte a client sent, starts an unframed message, as sent
+/// by the clients predating the framing.
+///
+/// A framed stream starts with the sync marker, or with the most significant byte of a
+/// length prefix, which is zero below 16 MiB and has `FRAGMENT_FLAG` set for a fragment or
+/// a keepalive.
+/// An unframed `ClientMessage` starts with the tag of one of its fields, a single byte
+/// between 0x01 and 0x7f.
+pub fn is_unframed_start(first_byte: u8) -> bool {
+    (0x01..=0x7f).contains(&first_byte)
+}
+
+/// Prepends the big-endian `u32` length prefix to a payload.
+///
+/// # Arguments
+/// - `payload` The encoded message to frame.
+///
+/// # Returns
+/// - The bytes to write on the transport.
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Writes a payload as a single frame, in one write so that frames sent from several
+/// threads on the same stream are not interleaved.
+///
+/// # Arguments
+/// - `writer`  The transport.
+/// - `payload` The encoded message to frame.
+///
+/// # Returns
+/// - Ok    with the number of bytes written, length prefix included.
+/// - Err   when the transport failed.
+pub fn write_frame<W: Write>(mut writer: W, payload: &[u8]) -> io::Result<usize> {
+    let frame = encode_frame(payload);
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(frame.len())
+}
+
+/// How the frames of a connection are delimited, which both peers must agree on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameFormat {
+    /// Whether every frame starts with `SYNC_MARKER`, so that the reader resynchronizes on
+    /// the next frame after corrupted bytes instead of dropping the connection.
+    pub sync_marker: bool,
+    /// Whether the messages are sent as is, without length prefix, as by the clients
+    /// predating the framing. Each read is then taken for a whole message, which breaks
+    /// when the transport splits or coalesces them.
+    pub unframed: bool,
+    /// Split the payloads longer than this into fragments of this length, for peers that
+    /// limit the size of a frame. The peers need not agree on it, as every decoder
+    /// reassembles the fragments, but a fragment and its header must fit the largest
+    /// frame the peer accepts.
+    pub fragment_len: Option<usize>,
+}
+
+impl FrameFormat {
+    /// Frames with a sync marker.
+    pub const SYNCED: FrameFormat = FrameFormat {
+        sync_marker: true,
+        unframed: false,
+        fragment_len: None,
+    };
+
+    /// No framing, see `FrameFormat::unframed`.
+    pub const UNFRAMED: FrameFormat = FrameFormat {
+        sync_marker: false,
+        unframed: true,
+        fragment_len: None,
+    };
+
+    /// The format `upgraded` to by a transport upgrade, fragmenting the payloads as this one.
+    pub fn upgraded_to(&self, upgraded: FrameFormat) -> FrameFormat {
+        FrameFormat {
+            fragment_len: self.fragment_len,
+            ..upgraded
+        }
+    }
+
+    /// Prepends the sync marker, if any, and the length prefix to a payload.
+    ///
+    /// # Arguments
+    /// - `payload` The encoded message to frame.
+    ///
+    /// # Returns
+    /// - The bytes to write on the transport, several frames when the payload was
+    ///   fragmented.
+    pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        if self.unframed {
+            return payload.to_vec();
+        }
+        if let Some(fragment_len) = self.fragment_len.filter(|&fragment_len| payload.len() > fragment_len) {
+            return self.encode_fragments(payload, fragment_len);
+        }
+        if !self.sync_marker {
+            return encode_frame(payload);
+        }
+        let mut frame = Vec::with_capacity(SYNC_MARKER.len() + HEADER_LEN + payload.len());
+        self.push_prefix(&mut frame, payload.len() as u32);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Splits a payload into fragments of `fragment_len` bytes, or more when it would take
+    /// more fragments than their header can count.
+    fn encode_fragments(&self, payload: &[u8], fragment_len: usize) -> Vec<u8> {
+        let fragment_len = fragment_len.max(payload.len().div_ceil(u16::MAX as usize)).max(1);
+        let total = payload.len().div_ceil(fragment_len) as u16;
+        let message_id = NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed);
+
+        let overhead = SYNC_MARKER.len() + HEADER_LEN + FRAGMENT_HEADER_LEN;
+        let mut frames = Vec::with_capacity(payload.len() + total as usize * overhead);
+        for (index, fragment) in payload.chunks(fragment_len).enumerate() {
+            self.push_prefix(&mut frames, FRAGMENT_FLAG | (FRAGMENT_HEADER_LEN + fragment.len()) as u32);
+            frames.extend_from_slice(&message_id.to_be_bytes());
+            frames.extend_from_slice(&(index as u16).to_be_bytes());
+            frames.extend_from_slice(&total.to_be_bytes());
+            frames.extend_from_slice(fragment);
+        }
+        frames
+    }
+
+    /// Appends the sync marker, if any, and the length prefix of a frame.
+    fn push_prefix(&self, frame: &mut Vec<u8>, prefix: u32) {
+        if self.sync_marker {
+            frame.extend_from_slice(&SYNC_MARKER);
+        }
+        frame.extend_from_slice(&prefix.to_be_bytes());
+    }
+
+    /// A keepalive of this format, nothing when unframed as a keepalive cannot be told
+    /// from a message then.
+    pub fn encode_keepalive(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(SYNC_MARKER.len() + HEADER_LEN);
+        if !self.unframed {
+            self.push_prefix(&mut frame, KEEPALIVE_PREFIX);
+        }
+        frame
+    }
+
+    /// Writes a payload as a single frame of this format, see `write_frame()`.
+    ///
+    /// # Returns
+    /// - Ok    with the number of bytes written, marker and length prefix included.
+    /// - Err   when the transport failed.
+    pub fn write<W: Write>(&self, mut writer: W, payload: &[u8]) -> io::Result<usize> {
+        let frame = self.encode(payload);
+        writer.write_all(&frame)?;
+        writer.flush()?;
+        Ok(frame.len())
+    }
+
+    /// Writes a keepalive of this format, see `encode_keepalive()`.
+    ///
+    /// # Returns
+    /// - Ok    with the number of bytes written.
+    /// - Err   when the transport failed.
+    pub fn write_keepalive<W: Write>(&self, mut writer: W) -> io::Result<usize> {
+        let keepalive = self.encode_keepalive();
+        writer.write_all(&keepalive)?;
+        writer.flush()?;
+        Ok(keepalive.len())
+    }
+
+    /// A decoder of the frames of this format, accepting payloads of up to
+    /// `max_frame_len` bytes.
+    pub fn decoder(&self, max_frame_len: usize) -> FrameDecoder {
+        let decoder = FrameDecoder::with_max_frame_len(max_frame_len);
+        if self.unframed {
+            decoder.unframed()
+        } else if self.sync_marker {
+            decoder.with_sync_marker()
+        } else {
+            decoder
+        }
+    }
+}
+
+impl From<Transport> for FrameFormat {
+    fn from(transport: Transport) -> Self {
+        FrameFormat {
+            sync_marker: transport == Transport::SyncMarker,
+            ..FrameFormat::default()
+        }
+    }
+}
+
+/// Reads a single frame, blocking until it is complete, for the peers that make one
+/// request at a time on a blocking stream. The keepalives are skipped. Fragments are not
+/// reassembled, and fail the read as their length prefix exceeds any limit; use a
+/// `FrameDecoder` for them.
+///
+/// # Arguments
+/// - `reader`        The transport.
+/// - `max_frame_len` The largest payload accepted.
+///
+/// # Returns
+/// - Ok    with the payload, or `None` when the transport was closed before a frame began.
+/// - Err   when the transport failed or was closed within a frame, or when the length
+///   prefix exceeds `max_frame_len`.
+pub fn read_frame<R: Read>(mut reader: R, max_frame_len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; HEADER_LEN];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) if header == KEEPALIVE_FRAME => {}
+            Ok(()) => break,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > max_frame_len {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Frame of {} bytes exceeds the limit of {} bytes", len, max_frame_len),
+        ));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// Where the decoder currently is within a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecoderState {
+    /// Looking for the sync marker, `matched` bytes of it were received so far.
+    ReadingMarker { matched: usize },
+    /// Collecting the length prefix, `filled` bytes of it were received so far.
+    ReadingHeader { filled: usize },
+    /// Collecting the payload, `remaining` bytes of it are still missing.
+    ReadingBody { remaining: usize },
+}
+
+/// Incremental frame parser.
+///
+/// Bytes can be fed in chunks of any size, as they come from the transport,
+/// and complete payloads are returned as soon as their last byte arrives.
+pub struct FrameDecoder {
+    state: DecoderState,
+    header: [u8; HEADER_LEN],
+    body: Vec<u8>,
+    max_frame_len: usize,
+    // Whether the frames start with `SYNC_MARKER`.
+    sync_marker: bool,
+    // Whether every input is a whole message, see `FrameFormat::unframed`.
+    unframed: bool,
+    // Whether the payload being read is a fragment of a message.
+    fragment: bool,
+    // The fragments of the message being reassembled, if any.
+    reassembly: Option<Reassembly>,
+    max_message_len: usize,
+    // Set from corrupted bytes until the next marker is found.
+    resyncing: bool,
+    resyncs: u64,
+    skipped_bytes: u64,
+    keepalives: u64,
+}
+
+/// A message being reassembled from its fragments.
+struct Reassembly {
+    message_id: u32,
+    total: u16,
+    // The index of the next fragment expected.
+    next: u16,
+    payload: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Creates a decoder accepting payloads of up to `DEFAULT_MAX_FRAME_LEN` bytes.
+    pub fn new() -> Self {
+        Self::with_max_frame_len(DEFAULT_MAX_FRAME_LEN)
+    }
+
+    /// Creates a decoder accepting payloads of up to `max_frame_len` bytes.
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        FrameDecoder {
+            state: DecoderState::ReadingHeader { filled: 0 },
+            header: [0; HEADER_LEN],
+            body: Vec::new(),
+            max_frame_len,
+            sync_marker: false,
+            unframed: false,
+            fragment: false,
+            reassembly: None,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN.max(max_frame_len),
+            resyncing: false,
+            resyncs: 0,
+            skipped_bytes: 0,
+            keepalives: 0,
+        }
+    }
+
+    /// Expect every frame to start with `SYNC_MARKER`. Bytes that do not, and frames
+    /// whose length prefix exceeds the limit, are then skipped up to the next marker
+    /// rather than failing the stream.
+    pub fn with_sync_marker(mut self) -> Self {
+        self.sync_marker = true;
+        self.state = self.frame_start();
+        self
+    }
+
+    /// Fail the stream when the fragments of a message add up to more than
+    /// `max_message_len` bytes, `DEFAULT_MAX_MESSAGE_LEN` by default.
+    pub fn with_max_message_len(mut self, max_message_len: usize) -> Self {
+        self.max_message_len = max_message_len;
+        self
+    }
+
+    /// Take every input for a whole message, see `FrameFormat::unframed`.
+    pub fn unframed(mut self) -> Self {
+        self.unframed = true;
+        self
+    }
+
+    /// The largest payload accepted.
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+
+    /// How many times the decoder lost track of the frames and scanned for the next marker.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    /// How many bytes were skipped while scanning for markers.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// How many keepalives were received, which are not returned as frames.
+    pub fn keepalives(&self) -> u64 {
+        self.keepalives
+    }
+
+    /// The length of a keepalive on the wire, sync marker included.
+    pub(crate) fn keepalive_len(&self) -> usize {
+        if self.sync_marker { SYNC_MARKER.len() + HEADER_LEN } else { HEADER_LEN }
+    }
+
+    /// The current state of the parser.
+    pub fn state(&self) -> DecoderState {
+        self.state
+    }
+
+    /// Whether the decoder sits between two frames, with no partial frame buffered.
+    pub fn is_idle(&self) -> bool {
+        self.state == self.frame_start() && !self.resyncing && self.reassembly.is_none()
+    }
+
+    /// The state of the decoder between two frames.
+    fn frame_start(&self) -> DecoderState {
+        if self.sync_marker {
+            DecoderState::ReadingMarker { matched: 0 }
+        } else {
+            DecoderState::ReadingHeader { filled: 0 }
+        }
+    }
+
+    /// Starts scanning for the next marker, counting a resync unless already scanning.
+    fn lose_sync(&mut self, skipped: usize) {
+        if !self.resyncing {
+            self.resyncing = true;
+            self.resyncs += 1;
+        }
+        self.skipped_bytes += skipped as u64;
+    }
+
+    /// Consumes bytes received from the transport.
+    ///
+    /// # Arguments
+    /// - `input` The received bytes, which may hold any part of any number of frames.
+    ///
+    /// # Returns
+    /// - Ok    with the payloads of every frame completed by `input`, in order.
+    /// - Err   when a length prefix exceeds the maximum frame length, or when the fragments
+    ///   of a message are out of order or add up to more than the maximum message length.
+    ///   Without sync markers, the stream cannot be resynchronized after an oversized
+    ///   frame, so the connection should be dropped.
+    pub fn feed(&mut self, mut input: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let mut frames = Vec::new();
+        while !input.is_empty() {
+            let (frame, consumed) = self.feed_frame(input)?;
+            frames.extend(frame);
+            input = &input[consumed..];
+        }
+        Ok(frames)
+    }
+
+    /// Consumes bytes received from the transport up to the end of the next frame, so that
+    /// the bytes after it can be decoded differently, e.g. after a transport upgrade.
+    ///
+    /// # Arguments
+    /// - `input` The received bytes, which may hold any part of any number of frames.
+    ///
+    /// # Returns
+    /// - Ok    with the payload of the frame completed by `input`, if any, and the number of
+    ///   bytes consumed, all of them unless a frame or a keepalive was completed. A
+    ///   keepalive returns no payload, and is counted in `keepalives()`.
+    /// - Err   when a length prefix exceeds the maximum frame length, see `feed()`.
+    pub fn feed_frame(&mut self, mut input: &[u8]) -> io::Result<(Option<Vec<u8>>, usize)> {
+        let input_len = input.len();
+        if self.unframed {
+            return Ok(((input_len > 0).then(|| input.to_vec()), input_len));
+        }
+        while !input.is_empty() {
+            match self.state {
+                DecoderState::ReadingMarker { matched } => {
+                    let byte = input[0];
+                    input = &input[1..];
+                    if byte == SYNC_MARKER[matched] {
+                        if matched + 1 == SYNC_MARKER.len() {
+                            self.resyncing = false;
+                            self.state = DecoderState::ReadingHeader { filled: 0 };
+                        } else {
+                            self.state = DecoderState::ReadingMarker { matched: matched + 1 };
+                        }
+                    } else {
+                        // The byte may start the next marker, the bytes of the marker being distinct.
+                        let restarts = byte == SYNC_MARKER[0];
+                        self.lose_sync(matched + usize::from(!restarts));
+                        self.state = DecoderState::ReadingMarker { matched: usize::from(restarts) };
+                    }
+                }
+
+                DecoderState::ReadingHeader { filled } => {
+                    let count = (HEADER_LEN - filled).min(input.len());
+                    self.header[filled..filled + count].copy_from_slice(&input[..count]);
+                    input = &input[count..];
+
+                    let filled = filled + count;
+                    if filled < HEADER_LEN {
+                        self.state = DecoderState::ReadingHeader { filled };
+                        continue;
+                    }
+
+                    let prefix = u32::from_be_bytes(self.header);
+                    if prefix == KEEPALIVE_PREFIX {
+                        self.keepalives += 1;
+                        self.state = self.frame_start();
+                        return Ok((None, input_len - input.len()));
+                    }
+                    self.fragment = prefix & FRAGMENT_FLAG != 0;
+                    let len = (prefix & !FRAGMENT_FLAG) as usize;
+                    if self.fragment && len < FRAGMENT_HEADER_LEN && self.sync_marker {
+                        self.lose_sync(SYNC_MARKER.len() + HEADER_LEN);
+                        self.state = DecoderState::ReadingMarker { matched: 0 };
+                        continue;
+                    }
+                    if self.fragment && len < FRAGMENT_HEADER_LEN {
+                        self.state = self.frame_start();
+                        return Err(io::Error::new(ErrorKind::InvalidData, "Fragment is too short for its header"));
+                    }
+                    if len > self.max_frame_len && self.sync_marker {
+                        self.lose_sync(SYNC_MARKER.len() + HEADER_LEN);
+                        self.state = DecoderState::ReadingMarker { matched: 0 };
+                        continue;
+                    }
+                    if len > self.max_frame_len {
+                        self.state = DecoderState::ReadingHeader { filled: 0 };
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Frame of {} bytes exceeds the limit of {} bytes", len, self.max_frame_len),
+                        ));
+                    }
+
+                    if len == 0 {
+                        self.state = self.frame_start();
+                        return Ok((Some(Vec::new()), input_len - input.len()));
+                    }
+                    self.body = Vec::with_capacity(len);
+                    self.state = DecoderState::ReadingBody { remaining: len };
+                }
+
+                DecoderState::ReadingBody { remaining } => {
+                    let count = remaining.min(input.len());
+                    self.body.extend_from_slice(&input[..count]);
+                    input = &input[count..];
+
+                    if count == remaining {
+                        self.state = self.frame_start();
+                        let payload = std::mem::take(&mut self.body);
+                        if !self.fragment {
+                            return Ok((Some(payload), input_len - input.len()));
+                        }
+                        if let Some(message) = self.reassemble(&payload)? {
+                            return Ok((Some(message), input_len - input.len()));
+                        }
+                    } else {
+                        self.state = DecoderState::ReadingBody { remaining: remaining - count };
+                    }
+                }
+            }
+        }
+
+        Ok((None, input_len))
+    }
+
+    /// Adds a fragment to the message being reassembled. The fragments of a message are
+    /// never interleaved with those of another.
+    ///
+    /// # Returns
+    /// - Ok    with the message once its last fragment was added.
+    /// - Err   when the fragment does not follow the previous one, or the message exceeds
+    ///   the maximum message length.
+    fn reassemble(&mut self, fragment: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let message_id = u32::from_be_bytes(fragment[0..4].try_into().unwrap());
+        let index = u16::from_be_bytes(fragment[4..6].try_into().unwrap());
+        let total = u16::from_be_bytes(fragment[6..8].try_into().unwrap());
+        let part = &fragment[FRAGMENT_HEADER_LEN..];
+
+        if index == 0 {
+            self.reassembly = Some(Reassembly {
+                message_id,
+                total,
+                next: 0,
+                payload: Vec::new(),
+            });
+        }
+        let reassembly = match self.reassembly.as_mut() {
+            Some(reassembly) if (reassembly.message_id, reassembly.total, reassembly.next) == (message_id, total, index) => {
+                reassembly
+            }
+            _ => {
+                self.reassembly = None;
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Fragment {} of {} of message {} is out of order", index, total, message_id),
+                ));
+            }
+        };
+        if reassembly.payload.len() + part.len() > self.max_message_len {
+            self.reassembly = None;
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Fragmented message exceeds the limit of {} bytes", self.max_message_len),
+            ));
+        }
+
+        reassembly.payload.extend_from_slice(part);
+        reassembly.next += 1;
+        if reassembly.next < reassembly.total {
+            return Ok(None);
+        }
+        Ok(self.reassembly.take().map(|reassembly| reassembly.payload))
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,145 @@
+use crate::message::{AddErrorCode, ErrorCode, EvalErrorCode, ShutdownReason, Transport};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::io;
+
+/// Replaces the value of the redacted fields.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Human-readable JSON rendering of the wire messages, for log pipelines and tooling.
+///
+/// Messages are rendered in serde's default representation, with camelCase field names,
+/// oneofs flattened into their message and enums by name, e.g. an echo request is rendered
+/// as `{"echoMessage":{"content":"Hello"}}`. Unlike the proto3 JSON mapping, 64-bit
+/// integers are numbers and the fields holding their default value are rendered.
+pub trait Json: Sized {
+    /// Render the message as JSON.
+    ///
+    /// # Returns
+    /// - Ok    with the JSON text.
+    /// - Err   when the message could not be serialized.
+    fn to_json(&self) -> io::Result<String>;
+
+    /// Parse a message from its JSON rendering, missing fields taking their default value.
+    ///
+    /// # Returns
+    /// - Ok    with the parsed message.
+    /// - Err   with `InvalidData` when the text is not a valid rendering of the message.
+    fn from_json(json: &str) -> io::Result<Self>;
+}
+
+impl<M> Json for M
+where
+    M: prost::Message + Serialize + DeserializeOwned,
+{
+    fn to_json(&self) -> io::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    fn from_json(json: &str) -> io::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Render a message as JSON for logs and diagnostics, masking sensitive fields.
+///
+/// # Arguments
+/// - `message` The message, or oneof of a message, to render.
+/// - `fields` The names of the fields to mask at any depth, as rendered in JSON (camelCase).
+///
+/// # Returns
+/// - Ok    with the JSON text, in which the fields hold `REDACTED`.
+/// - Err   when the message could not be serialized.
+pub fn to_redacted_json<M: Serialize>(message: &M, fields: &[String]) -> io::Result<String> {
+    let mut value = serde_json::to_value(message)?;
+    redact(&mut value, fields);
+    Ok(value.to_string())
+}
+
+fn redact(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.iter().any(|field| field == key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value, fields);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| redact(value, fields)),
+        _ => {}
+    }
+}
+
+/// Renders a protobuf enum by name, as in the `.proto` file, rather than by number.
+///
+/// Also defines the module serializing the `i32` fields that prost generates for the enum.
+macro_rules! enum_by_name {
+    ($module:ident, $enum:ident) => {
+        impl Serialize for $enum {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str_name())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $enum {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let name = String::deserialize(deserializer)?;
+                $enum::from_str_name(&name)
+                    .ok_or_else(|| serde::de::Error::custom(format!("unknown {} {}", stringify!($enum), name)))
+            }
+        }
+
+        pub(crate) mod $module {
+            use super::*;
+
+            // Unknown values, e.g. sent by a newer peer, are kept as numbers.
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum NameOrNumber {
+                Name($enum),
+                Number(i32),
+            }
+
+            pub fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+                match $enum::try_from(*value) {
+                    Ok(value) => value.serialize(serializer),
+                    Err(_) => serializer.serialize_i32(*value),
+                }
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+                match NameOrNumber::deserialize(deserializer)? {
+                    NameOrNumber::Name(value) => Ok(value as i32),
+                    NameOrNumber::Number(value) => Ok(value),
+                }
+            }
+        }
+    };
+}
+
+enum_by_name!(shutdown_reason, ShutdownReason);
+enum_by_name!(error_code, ErrorCode);
+enum_by_name!(add_error_code, AddErrorCode);
+enum_by_name!(eval_error_code, EvalErrorCode);
+enum_by_name!(transport, Transport);
+
+/// Deserializes the oneof flattened into a message, which serde would otherwise turn
+/// into `None` on any error, hiding typos and invalid values.
+pub(crate) mod oneof {
+    use super::*;
+    use serde_json::{Map, Value};
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: DeserializeOwned,
+    {
+        let fields = Map::deserialize(deserializer)?;
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        T::deserialize(Value::Object(fields)).map(Some).map_err(serde::de::Error::custom)
+    }
+}
@@ -1,5 +1,40 @@
+pub mod accept;
+pub mod add;
+#[cfg(feature = "tokio")]
+pub mod async_client;
+#[cfg(feature = "tokio")]
+pub mod async_server;
+pub mod background;
+pub mod bind;
+pub mod client;
+pub mod clock;
+pub mod demo;
+pub mod eval;
+pub mod fd_limit;
+pub mod fixtures;
+pub mod framing;
+pub mod id;
+pub mod json;
+pub mod memory;
+pub mod pid_file;
+pub mod proxy_protocol;
 pub mod server;
+pub(crate) mod session;
+pub mod shutdown;
+pub mod slab;
+pub mod startup;
+#[cfg(unix)]
+pub mod systemd;
+pub mod time_scale;
+pub(crate) mod timer_queue;
+pub mod trace_context;
+pub mod usage;
+pub mod waker;
 
 pub mod message {
     include!(concat!(env!("OUT_DIR"), "/messages.rs"));
+
+    /// The encoded `FileDescriptorSet` of `messages.proto`, describing every message of the
+    /// protocol for tools that do not compile against this crate.
+    pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/messages_descriptor.bin"));
 }
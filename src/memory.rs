@@ -0,0 +1,83 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Bounds the bytes the connections hold at once, so that a busy server does not grow
+/// beyond what a small device has: their read buffers, their held replies, and their
+/// delayed echoes until sent.
+///
+/// The bytes are counted as they are reserved and released, shared by every connection.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    limit: u64,
+    // Shared with the reservations, which release their bytes when dropped.
+    used: Arc<AtomicU64>,
+}
+
+/// Bytes counted in a budget, given back once dropped.
+#[derive(Debug)]
+pub struct MemoryReservation {
+    bytes: u64,
+    used: Arc<AtomicU64>,
+}
+
+impl MemoryReservation {
+    /// The bytes reserved.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Takes over the bytes of `other`, given back along with these ones.
+    pub fn merge(&mut self, mut other: MemoryReservation) {
+        self.bytes += std::mem::take(&mut other.bytes);
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes.
+    pub fn new(limit: u64) -> Self {
+        MemoryBudget {
+            limit,
+            used: Arc::default(),
+        }
+    }
+
+    /// The bytes the budget allows.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The bytes reserved and not released yet.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Whether every byte of the budget is reserved.
+    pub fn is_exhausted(&self) -> bool {
+        self.used() >= self.limit
+    }
+
+    /// Counts `bytes` more, unless they would exceed the limit.
+    ///
+    /// # Returns
+    /// - Some  with the reservation, to be dropped once the bytes are released.
+    /// - None  when the bytes do not fit.
+    pub fn reserve(&self, bytes: u64) -> Option<MemoryReservation> {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|&used| used <= self.limit)
+            })
+            .ok()
+            .map(|_| MemoryReservation {
+                bytes,
+                used: self.used.clone(),
+            })
+    }
+}
@@ -0,0 +1,109 @@
+use log::{info, warn};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A file holding the id of the running server process, removed when dropped.
+///
+/// A file left behind by a crashed instance is detected as stale, because its process
+/// is gone, and replaced.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Creates the PID file for the current process.
+    ///
+    /// # Arguments
+    /// - `path` Where to create the file.
+    ///
+    /// # Returns
+    /// - Ok    upon writing the file.
+    /// - Err   with `AlreadyExists` when another running process holds the file, or the
+    ///   underlying error when it could not be written.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        // Two attempts: the second one follows the removal of a stale file.
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", process::id())?;
+                    file.sync_all()?;
+                    info!("Wrote PID file {}", path.display());
+                    return Ok(PidFile { path: path.to_path_buf() });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if let Some(pid) = running_pid(path)? {
+                        return Err(io::Error::new(
+                            ErrorKind::AlreadyExists,
+                            format!("Server is already running with pid {}", pid),
+                        ));
+                    }
+                    warn!("Removing stale PID file {}", path.display());
+                    fs::remove_file(path)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(io::Error::new(ErrorKind::AlreadyExists, "PID file keeps reappearing"))
+    }
+
+    /// The location of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Only remove the file if it still belongs to this process.
+        if read_pid(&self.path).ok().flatten() == Some(process::id()) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Failed to remove PID file {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Reads the process id stored in a PID file.
+///
+/// # Returns
+/// - Ok(Some)  with the stored process id.
+/// - Ok(None)  when the file does not exist or does not hold a process id.
+/// - Err       when the file could not be read.
+pub fn read_pid(path: impl AsRef<Path>) -> io::Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.trim().parse().ok()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Tells whether an instance is running according to a PID file.
+///
+/// # Returns
+/// - Ok(Some)  with the process id when the process recorded in the file is alive.
+/// - Ok(None)  when there is no file, or it is stale.
+/// - Err       when the file could not be read.
+pub fn running_pid(path: impl AsRef<Path>) -> io::Result<Option<u32>> {
+    Ok(read_pid(path)?.filter(|pid| is_alive(*pid)))
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists and may be signalled.
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: u32) -> bool {
+    // Without a way to probe the process, never treat the file as stale.
+    true
+}
@@ -0,0 +1,111 @@
+use std::{
+    io::{self, ErrorKind, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+/// Signature that starts every version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest possible version 1 header, including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Reads a PROXY protocol (version 1 or 2) header from the start of a connection.
+///
+/// Only the header is consumed, the client payload that follows is left in the stream.
+///
+/// # Arguments
+/// - `stream` The freshly accepted connection.
+///
+/// # Returns
+/// - Ok(Some)  with the address of the real client.
+/// - Ok(None)  when the proxy does not disclose it (`UNKNOWN` or `LOCAL` headers).
+/// - Err       when the connection does not start with a valid header.
+pub fn read_header(stream: &mut impl Read) -> io::Result<Option<SocketAddr>> {
+    let mut prefix = [0; 5];
+    stream.read_exact(&mut prefix)?;
+
+    if &prefix == b"PROXY" {
+        read_v1(stream)
+    } else if prefix == V2_SIGNATURE[..5] {
+        let mut rest = [0; 7];
+        stream.read_exact(&mut rest)?;
+        if rest != V2_SIGNATURE[5..] {
+            return Err(invalid("Invalid PROXY protocol v2 signature"));
+        }
+        read_v2(stream)
+    } else {
+        Err(invalid("Missing PROXY protocol header"))
+    }
+}
+
+/// Parses the rest of a text header, e.g. ` TCP4 192.0.2.1 192.0.2.2 5000 8080\r\n`.
+fn read_v1(stream: &mut impl Read) -> io::Result<Option<SocketAddr>> {
+    // Read byte by byte, so that nothing past the header is consumed.
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    while !line.ends_with(b"\r\n") {
+        if line.len() + 5 >= V1_MAX_LEN {
+            return Err(invalid("PROXY protocol v1 header is too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY protocol v1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["", "UNKNOWN", ..] => Ok(None),
+        ["", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source.parse().map_err(|_| invalid("Invalid PROXY protocol v1 address"))?;
+            let port: u16 = source_port.parse().map_err(|_| invalid("Invalid PROXY protocol v1 port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("Malformed PROXY protocol v1 header")),
+    }
+}
+
+/// Parses the rest of a binary header, following the signature.
+fn read_v2(stream: &mut impl Read) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header)?;
+    let version_command = header[0];
+    let family = header[1];
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+
+    // The address block, and any TLVs after it, must be consumed in every case.
+    let mut block = vec![0; len];
+    stream.read_exact(&mut block)?;
+
+    // The LOCAL command is used by the proxy for its own health checks.
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+
+    match family >> 4 {
+        // AF_INET: source address, destination address, source port, destination port.
+        1 if len >= 12 => {
+            let ip = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            let port = u16::from_be_bytes([block[8], block[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // AF_INET6: same layout with 16 byte addresses.
+        2 if len >= 36 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&block[..16]);
+            let port = u16::from_be_bytes([block[32], block[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+        }
+        // AF_UNSPEC or AF_UNIX do not carry an IP address.
+        0 | 3 => Ok(None),
+        _ => Err(invalid("Malformed PROXY protocol v2 address block")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
@@ -1,250 +1,2043 @@
-use crate::message::{ client_message, server_message, AddRequest, AddResponse, ClientMessage, EchoMessage, ServerMessage, ErrorMessage};
-use log::{error, info, warn};
+use crate::accept::{AcceptErrorAction, AcceptErrorPolicy, AcceptErrorStats, AcceptErrors};
+use crate::background::{self, BackgroundTasks};
+use crate::bind::{self, BindPolicy};
+use crate::clock::{self, SharedClock};
+use crate::demo;
+use crate::fd_limit::{self, FdBudget, FdReservation};
+use crate::framing::{self, FrameDecoder, FrameFormat};
+use crate::id::{SharedIdGenerator, SnowflakeIdGenerator};
+use crate::memory::{MemoryBudget, MemoryReservation};
+use crate::pid_file::PidFile;
+use crate::proxy_protocol;
+use crate::session::{DeprecatedRequests, Reply, Session};
+use crate::shutdown::ShutdownToken;
+use crate::slab::Slab;
+use crate::startup::{Limits, StartupReport};
+#[cfg(unix)]
+use crate::systemd;
+use crate::time_scale;
+use crate::timer_queue::TimerQueue;
+use crate::usage::{ClientUsage, UsageCounters, UsageReport, UsageReportSink, UsageTotals};
+use crate::waker::AcceptWaker;
+use crate::message::{client_message, server_message, ErrorCode, ErrorMessage, ReconnectHint, ServerMessage, SessionSummary, ShutdownReason};
+use log::{error, info, warn, Level};
 use prost::Message;
 use std::{
-        io::{self, ErrorKind, Read, Write}, net::{TcpListener, TcpStream}, sync::{
+        io::{self, ErrorKind, Read, Write}, net::{Shutdown, SocketAddr, TcpListener, TcpStream}, path::Path, sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, Mutex
-    }, thread, time::Duration
+        Arc, Mutex, MutexGuard, OnceLock, TryLockError
+    }, thread, time::{Duration, Instant}
 };
 use threadpool::ThreadPool;
 
+/// Called with the raw bytes of a request the server does not understand.
+///
+/// Returning a message sends it as the response, returning `None` falls back to the
+/// "Bad Request!" error.
+pub type UnknownMessageHandler = Arc<dyn Fn(&[u8]) -> Option<ServerMessage> + Send + Sync>;
+
+/// Called with every response before it is sent, e.g. to annotate or rewrite it.
+pub type ResponsePostProcessor = Arc<dyn Fn(&mut ServerMessage) + Send + Sync>;
+
+/// The longest delay a `DelayedEchoRequest` may ask for, unless configured otherwise.
+pub const DEFAULT_MAX_ECHO_DELAY: Duration = Duration::from_secs(10);
+
+/// The number of threads serving the connections, unless configured otherwise.
+pub const DEFAULT_WORKERS: usize = 15;
+
+/// The size of the buffer each connection is read into, unless configured otherwise.
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 512;
+
+/// How often the idle accept loop wakes up to ping the systemd watchdog, when enabled,
+/// unless configured otherwise.
+pub const DEFAULT_ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a proxy may take to send the PROXY protocol header of a connection.
+pub const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the server may take to write a message to a client outside of its session, i.e.
+/// a busy refusal or a shut down notification, so that a client not reading does not hold it.
+pub const NOTIFICATION_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often a connection held back by the memory budget checks whether it may be read again.
+const MEMORY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How each connection is read.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ConnectionOptions {
+    pub(crate) read_buffer_size: usize,
+    pub(crate) max_frame_len: usize,
+    // Connections idle for longer are closed, they are kept open forever without one.
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) frame_format: FrameFormat,
+    // Whether the clients predating the framing are told from their first byte and served.
+    pub(crate) unframed_clients: bool,
+    // The replies are written as they come without one.
+    pub(crate) write_coalescing: Option<WriteCoalescing>,
+}
+
+/// How the replies of a connection are held to be written together, see
+/// `ServerBuilder::with_write_coalescing()`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WriteCoalescing {
+    // The longest a reply is held.
+    pub(crate) window: Duration,
+    // The most replies held, written at once when reached.
+    pub(crate) max_replies: usize,
+}
+
+/// The replies of a connection held to be written together, framed already.
+#[derive(Debug, Default)]
+pub(crate) struct HeldReplies {
+    bytes: Vec<u8>,
+    count: usize,
+    // When the first of them must be written, so that none is held for longer than the window.
+    due: Option<Instant>,
+}
+
+impl HeldReplies {
+    /// Holds `frame` until the others are written.
+    ///
+    /// # Returns
+    /// Whether the replies must be written now, as the most that may be held are.
+    pub(crate) fn hold(&mut self, frame: Vec<u8>, coalescing: WriteCoalescing) -> bool {
+        self.bytes.extend_from_slice(&frame);
+        self.count += 1;
+        self.due.get_or_insert_with(|| Instant::now() + time_scale::scale(coalescing.window));
+        self.count >= coalescing.max_replies
+    }
+
+    /// When the replies must be written, `None` when none is held.
+    pub(crate) fn due(&self) -> Option<Instant> {
+        self.due
+    }
+
+    /// Takes the replies to write them, emptying the hold.
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        self.count = 0;
+        self.due = None;
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+impl ConnectionOptions {
+    /// Whether the first byte of each client tells whether it frames its requests. Not with
+    /// sync markers, which no client predating the framing sends, and whose streams may
+    /// start with corrupted bytes.
+    pub(crate) fn detects_unframed_clients(&self) -> bool {
+        self.unframed_clients && !self.frame_format.sync_marker
+    }
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            max_frame_len: framing::DEFAULT_MAX_FRAME_LEN,
+            idle_timeout: None,
+            frame_format: FrameFormat::default(),
+            unframed_clients: true,
+            write_coalescing: None,
+        }
+    }
+}
+
+/// The metadata key of the warning attached to the responses to deprecated requests.
+pub const DEPRECATED: &str = "deprecated";
+
 struct Client {
     stream: TcpStream,
+    // Reused by every read.
+    read_buffer: Vec<u8>,
+    // Reassembles the requests from whatever the reads return.
+    decoder: FrameDecoder,
+    // How the replies are framed, like the requests.
+    frame_format: SharedFrameFormat,
+    // Answers the requests, independently of the stream.
+    session: Session,
+    // Cancelled when the server stops, so that long-running work returns promptly.
+    shutdown_token: ShutdownToken,
+    // Set until the first byte of the client tells whether it frames its requests.
+    detect_unframed: bool,
+    // Sends the delayed echoes, created with the first one, holding their memory until sent.
+    delayed_echoes: Option<TimerQueue<(Vec<u8>, Option<MemoryReservation>)>>,
+    // Restored once a read was cut short to write the held replies.
+    idle_timeout: Option<Duration>,
+    write_coalescing: Option<WriteCoalescing>,
+    held_replies: HeldReplies,
+    // The held replies and the delayed echoes are counted in it, when set.
+    memory_budget: Option<MemoryBudget>,
+    held_memory: Option<MemoryReservation>,
+    // Holds the last message sent to the client once the server asked to drain the
+    // connection.
+    drain: DrainSlot,
+}
+
+/// The last message sent to a drained client, before closing the connection.
+#[derive(Debug, Clone)]
+enum Farewell {
+    /// A goodbye telling the client where to reconnect, see `Server::drain()`.
+    Reconnect(ReconnectHint),
+    /// The shut down notification, see `Server::begin_drain()`.
+    Shutdown(ShutdownReason),
 }
 
+/// Set by the server with the farewell to send to the client before closing.
+type DrainSlot = Arc<Mutex<Option<Farewell>>>;
+
+/// The transport of a connection, which the client may upgrade, shared by everything that
+/// writes to it. Held while writing, so that no frame is written across an upgrade.
+type SharedFrameFormat = Arc<Mutex<FrameFormat>>;
+
 impl Client {
     /// Creates a new client instance.
     ///
     /// # Arguments
     /// - `stream` TCP stream object that reads from and writes to the network.
     pub fn new(stream: TcpStream) -> Self {
-        Client { stream }
+        Client {
+            stream,
+            read_buffer: vec![0; DEFAULT_READ_BUFFER_SIZE],
+            decoder: FrameDecoder::new(),
+            frame_format: SharedFrameFormat::default(),
+            session: Session::new(),
+            shutdown_token: ShutdownToken::new(),
+            detect_unframed: false,
+            delayed_echoes: None,
+            idle_timeout: None,
+            write_coalescing: None,
+            held_replies: HeldReplies::default(),
+            memory_budget: None,
+            held_memory: None,
+            drain: DrainSlot::default(),
+        }
+    }
+
+    /// Read the connection as set by `options`.
+    fn with_options(mut self, options: ConnectionOptions) -> Self {
+        self.read_buffer = vec![0; options.read_buffer_size];
+        self.decoder = options.frame_format.decoder(options.max_frame_len);
+        *self.frame_format.lock().unwrap() = options.frame_format;
+        self.detect_unframed = options.detects_unframed_clients();
+        self.write_coalescing = options.write_coalescing;
+        self.idle_timeout = options.idle_timeout.map(time_scale::scale);
+        if let Err(e) = self.stream.set_read_timeout(self.idle_timeout) {
+            warn!("Failed to set the idle timeout: {}", e);
+        }
+        self
+    }
+
+    /// Handle the requests with `session`.
+    fn with_session(mut self, session: Session) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Frame the replies as `frame_format` tells, shared with the server so that its
+    /// notifications follow the upgrades of the connection.
+    fn with_frame_format(mut self, frame_format: SharedFrameFormat) -> Self {
+        self.frame_format = frame_format;
+        self
+    }
+
+    /// Count the held replies in `memory_budget`, writing them through once it is exhausted.
+    fn with_memory_budget(mut self, memory_budget: Option<MemoryBudget>) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    /// Close the connection gracefully once `drain` is set.
+    fn with_drain_slot(mut self, drain: DrainSlot) -> Self {
+        self.drain = drain;
+        self
+    }
+
+    /// Stop serving the connection, and drop its pending work, once `token` is cancelled.
+    pub fn with_shutdown_token(mut self, token: ShutdownToken) -> Self {
+        self.shutdown_token = token;
+        self
+    }
+
+    /// Whether the server is stopping, so that the connection should not be served anymore.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_token.is_cancelled()
     }
 
-    /// Handle the incoming client request and send a reply according to the request.
+    /// Whether a request, or the end of the stream, is waiting to be read, without blocking.
+    pub fn has_pending_input(&self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0; 1];
+        let result = match self.stream.peek(&mut byte) {
+            Ok(_) => Ok(true),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    /// Whether the client closed the session, with a bye message or by disconnecting.
+    pub fn is_closed(&self) -> bool {
+        self.session.is_closed()
+    }
+
+    /// Whether the requests of the client should not be read for now, as the memory budget
+    /// is exhausted while its delayed echoes hold some of it. They give it back once sent.
+    pub fn is_held_back(&self) -> bool {
+        self.memory_budget.as_ref().is_some_and(MemoryBudget::is_exhausted)
+            && self.delayed_echoes.as_ref().is_some_and(|delayed_echoes| delayed_echoes.pending() > 0)
+    }
+
+    /// Read from the client and handle every request completed by the read, replying to
+    /// each one according to its type. A request split across reads waits for the rest.
     ///
     /// # Returns
     /// - Ok    upon successful message decoding and handling.
-    /// - Err   when either the decoding or the handling fails.
+    /// - Err   when the framing is broken, or when the handling fails.
     pub fn handle(&mut self) -> io::Result<()> {
-        let mut buffer = [0; 512];
+        // The read returns when the held replies are due, if nothing arrives before.
+        let mut cut_short = false;
+        if let Some(due) = self.held_replies.due() {
+            let left = due.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                self.write_held_replies()?;
+            } else if self.idle_timeout.is_none_or(|idle_timeout| left < idle_timeout) {
+                self.stream.set_read_timeout(Some(left))?;
+                cut_short = true;
+            }
+        }
+
         // Read data from the client
-        let bytes_read = self.stream.read(&mut buffer)?;
+        let read = self.stream.read(&mut self.read_buffer);
+        if cut_short {
+            self.stream.set_read_timeout(self.idle_timeout)?;
+        }
+        let bytes_read = match read {
+            Ok(bytes_read) => bytes_read,
+            // No request followed the held replies.
+            Err(ref e) if cut_short && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return self.write_held_replies();
+            }
+            // The idle timeout ran out.
+            Err(ref e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                info!("Closing idle client.");
+                let payload = self.session.encode_response(self.session.goodbye(None));
+                if let Err(e) = self.write_frame(&payload) {
+                    warn!("Failed to say goodbye to an idle client: {}", e);
+                }
+                self.session.close();
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
         if bytes_read == 0 {
+            if !self.decoder.is_idle() {
+                warn!("Client disconnected in the middle of a request.");
+            }
             info!("Client disconnected.");
+            self.session.close();
             return Ok(());
         }
+        if std::mem::take(&mut self.detect_unframed) && framing::is_unframed_start(self.read_buffer[0]) {
+            info!("Client does not frame its requests, taking each read for a request.");
+            *self.frame_format.lock().unwrap() = FrameFormat::UNFRAMED;
+            self.decoder = FrameFormat::UNFRAMED.decoder(self.decoder.max_frame_len());
+        }
 
-        // Decode the message to decide on the type of the request.
-        if let Ok(client_request) = ClientMessage::decode(&buffer[..bytes_read]) {
-            match client_request.message {
-                Some(client_message::Message::EchoMessage(echo_message)) => {
-                    self.handle_echo_request(echo_message);
-                } Some(client_message::Message::AddRequest(add_request)) => {
-                    self.handle_add_request(add_request);
-                } None => {
-                    // In case the received request was not identified, this will execute.
-                    error!("Bad Request!");
-                    self.handle_bad_request();
-                }
+        // One request at a time, as the bytes after an upgrade request are of another transport.
+        let mut offset = 0;
+        while offset < bytes_read {
+            let keepalives = self.decoder.keepalives();
+            let (request, consumed) = decode_request(&mut self.decoder, &mut self.session, &self.read_buffer[offset..bytes_read])?;
+            offset += consumed;
+            let keepalive = self.decoder.keepalives() > keepalives;
+            if request.is_none() && !keepalive {
+                continue;
+            }
+            // Requests sent after a goodbye are not served.
+            if self.session.is_closed() {
+                break;
+            }
+            match request {
+                Some(request) => self.handle_frame(&request)?,
+                None => self.reply_keepalive()?,
             }
-        } else {
-            // Executes when the decoding of the message fails.
-            error!("Failed to decode message");
-            self.handle_bad_request();
         }
+        Ok(())
+    }
 
+    /// Handle a single request and send a reply according to its type.
+    ///
+    /// # Returns
+    /// - Ok    upon successful message decoding and handling.
+    /// - Err   when the reply could not be sent or scheduled.
+    fn handle_frame(&mut self, request: &[u8]) -> io::Result<()> {
+        match self.session.handle_frame(request) {
+            Reply::Now(payload) => {
+                // Nothing may follow the acknowledgement of a goodbye.
+                if self.session.is_closed() {
+                    self.cancel_delayed_echoes();
+                }
+                self.reply(&payload)?;
+            }
+            Reply::Later { delay, payload, reservation } => self.send_later(delay, payload, reservation)?,
+            Reply::Upgrade { frame_format, payload } => {
+                self.write_held_replies()?;
+                // Held until switched, so that no other reply is written in between.
+                let mut current = self.frame_format.lock().unwrap();
+                let written = current.write(&self.stream, &payload)?;
+                self.session.record_sent(written);
+                *current = current.upgraded_to(frame_format);
+                self.decoder = frame_format.decoder(self.decoder.max_frame_len());
+            }
+        }
         Ok(())
     }
 
-    /// Handle echo requests by echoing back the same message.
+    /// Write `payload` as a single frame of the current transport, after the held replies.
+    fn write_frame(&mut self, payload: &[u8]) -> io::Result<usize> {
+        self.write_held_replies()?;
+        let written = self.frame_format.lock().unwrap().write(&self.stream, payload)?;
+        self.session.record_sent(written);
+        Ok(written)
+    }
+
+    /// Reply with `payload`, held to be written with the next replies when coalescing them.
+    fn reply(&mut self, payload: &[u8]) -> io::Result<()> {
+        let frame = self.frame_format.lock().unwrap().encode(payload);
+        self.session.record_sent(frame.len());
+        self.reply_frame(frame)
+    }
+
+    /// Answer a keepalive in kind, like a reply.
+    fn reply_keepalive(&mut self) -> io::Result<()> {
+        let frame = self.frame_format.lock().unwrap().encode_keepalive();
+        self.reply_frame(frame)
+    }
+
+    /// Reply with `frame`, encoded already, see `reply()`.
+    fn reply_frame(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        let write_coalescing = self.write_coalescing;
+        match write_coalescing {
+            Some(write_coalescing) if self.hold_memory(frame.len()) => {
+                if self.held_replies.hold(frame, write_coalescing) {
+                    self.write_held_replies()?;
+                }
+                Ok(())
+            }
+            _ => {
+                self.write_held_replies()?;
+                // Held while writing, like a frame.
+                let _frame_format = self.frame_format.lock().unwrap();
+                (&self.stream).write_all(&frame)?;
+                (&self.stream).flush()
+            }
+        }
+    }
+
+    /// Count `bytes` more of held replies in the memory budget, if any.
     ///
-    /// # Arguments
-    /// - `echo_message` The message received from the client.
-    fn handle_echo_request(&mut self, echo_message: EchoMessage) {
-        // If the received request was simply an echo request, send the message back
-        info!("Received Echo Request: {}", echo_message.content);
-
-        // Create the response
-        let response = ServerMessage {
-            message: Some(server_message::Message::EchoMessage(echo_message))
+    /// # Returns
+    /// Whether the budget holds them, the reply being written through otherwise.
+    fn hold_memory(&mut self, bytes: usize) -> bool {
+        let Some(memory_budget) = &self.memory_budget else {
+            return true;
+        };
+        let Some(reservation) = memory_budget.reserve(bytes as u64) else {
+            return false;
         };
+        match &mut self.held_memory {
+            Some(held_memory) => held_memory.merge(reservation),
+            None => self.held_memory = Some(reservation),
+        }
+        true
+    }
 
-        self.send_response(response);
+    /// Write the held replies, if any, at once.
+    fn write_held_replies(&mut self) -> io::Result<()> {
+        // Given back to the memory budget once written.
+        let _held_memory = self.held_memory.take();
+        let bytes = self.held_replies.take();
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        // Held while writing, like a frame.
+        let _frame_format = self.frame_format.lock().unwrap();
+        (&self.stream).write_all(&bytes)?;
+        (&self.stream).flush()
+    }
+
+    /// Write the held replies once they are due, when no request followed them.
+    fn write_due_replies(&mut self) -> io::Result<()> {
+        match self.held_replies.due() {
+            Some(due) if due <= Instant::now() => self.write_held_replies(),
+            _ => Ok(()),
+        }
     }
 
-    /// Handle the add requests by adding the two integers within the request then sending the result.
+    /// Send `payload` once `delay` elapsed, then give `reservation` back.
     ///
-    /// # Arguments
-    /// - `add_request` The client request containing the two integers to be added.
-    fn handle_add_request(&mut self, add_request: AddRequest) {
-        // If the received request is an add request, perform the operation.
-        info!("Received Add Request: {} + {}", add_request.a, add_request.b);
-
-        // Perform the request.
-        let add_response = AddResponse {
-            result: add_request.a + add_request.b
+    /// The response is written by the timer thread of the connection, so that the pool
+    /// worker keeps serving it meanwhile.
+    ///
+    /// # Returns
+    /// - Ok    upon scheduling the response.
+    /// - Err   when the stream could not be handed to the timer.
+    fn send_later(&mut self, delay: Duration, payload: Vec<u8>, reservation: Option<MemoryReservation>) -> io::Result<()> {
+        let delayed_echoes = match self.delayed_echoes.as_ref() {
+            Some(delayed_echoes) => delayed_echoes,
+            None => {
+                let stream = self.stream.try_clone()?;
+                let frame_format = self.frame_format.clone();
+                let usage = self.session.usage().clone();
+                // The client was told about the shut down already, and the stream must
+                // not outlive the server, so the pending echoes are dropped then.
+                self.delayed_echoes.insert(TimerQueue::new(
+                    self.session.clock().clone(),
+                    self.shutdown_token.clone(),
+                    move |(payload, _reservation): (Vec<u8>, Option<MemoryReservation>)| {
+                        let frame = frame_format.lock().unwrap().encode(&payload);
+                        // Counted before the client can see the echo, like the replies.
+                        usage.record_sent(frame.len());
+                        if let Err(e) = (&stream).write_all(&frame).and_then(|_| (&stream).flush()) {
+                            warn!("Failed to send delayed echo: {}", e);
+                        }
+                    },
+                ))
+            }
         };
+        delayed_echoes.schedule(time_scale::scale(delay), (payload, reservation));
+        Ok(())
+    }
 
-        // Create the response.
-        let response = ServerMessage {
-            message: Some(server_message::Message::AddResponse(add_response))
+    /// Drop the delayed echoes not sent yet, waiting for the one being sent if any.
+    fn cancel_delayed_echoes(&self) {
+        if let Some(delayed_echoes) = &self.delayed_echoes {
+            let cancelled = delayed_echoes.cancel();
+            if cancelled > 0 {
+                info!("Dropped {} delayed echo(es) on goodbye.", cancelled);
+            }
+        }
+    }
+
+    /// Once the connection was drained, wait for the responses still in flight, then say
+    /// farewell to the client and close the connection. Otherwise, drop the delayed echoes
+    /// not sent yet, so that nothing is written once the connection was released.
+    fn finish_drain(&mut self) {
+        if let Err(e) = self.write_held_replies() {
+            warn!("Failed to send the held replies: {}", e);
+        }
+        let delayed_echoes = self.delayed_echoes.take();
+        let Some(farewell) = self.drain.lock().unwrap().take() else {
+            return;
         };
 
-        self.send_response(response);
+        if let Some(delayed_echoes) = delayed_echoes {
+            delayed_echoes.finish();
+        }
+
+        let payload = self.session.encode_response(farewell.into_message(&self.session));
+        if let Err(e) = self.write_frame(&payload) {
+            warn!("Failed to say goodbye to a drained client: {}", e);
+        }
+        let _ = self.stream.shutdown(Shutdown::Both);
+        info!("Client drained.");
+    }
+}
+
+/// Feeds what a client sent to `decoder`, up to the end of the next request, counting the
+/// bytes it consumed and the resynchronizations it went through in the usage of `session`.
+///
+/// # Returns
+/// - Ok    with the request completed by `bytes`, if any, and the number of bytes consumed.
+/// - Err   when the framing is broken beyond repair.
+pub(crate) fn decode_request(decoder: &mut FrameDecoder, session: &mut Session, bytes: &[u8]) -> io::Result<(Option<Vec<u8>>, usize)> {
+    let resyncs = decoder.resyncs();
+    let keepalives = decoder.keepalives();
+    let (request, consumed) = decoder.feed_frame(bytes)?;
+    // Markers, length prefixes, fragment headers and skipped bytes included, but not the
+    // keepalives, which are no traffic of the client.
+    let keepalive_len = if decoder.keepalives() > keepalives { decoder.keepalive_len() } else { 0 };
+    session.receive(consumed - keepalive_len);
+    if decoder.resyncs() > resyncs {
+        warn!(
+            "Skipped corrupted bytes up to the next frame, {} byte(s) skipped so far.",
+            decoder.skipped_bytes()
+        );
+        session.record_resyncs(decoder.resyncs() - resyncs);
     }
+    Ok((request, consumed))
+}
 
-    /// Handle a bad request sent by the client.
-    fn handle_bad_request(&mut self) {
-        let response = ServerMessage {
-            message: Some(server_message::Message::ErrorMessage(ErrorMessage {
-                content: "Bad Request!".to_string(),
-            })),
-        };
-        self.send_response(response);
+impl Farewell {
+    /// The message saying farewell to the client of `session`.
+    fn into_message(self, session: &Session) -> ServerMessage {
+        match self {
+            Farewell::Reconnect(reconnect_hint) => session.goodbye(Some(reconnect_hint)),
+            Farewell::Shutdown(reason) => shutdown_message(reason, Some(session.summary())),
+        }
     }
+}
 
-    /// Send the a response message to the client.
-    ///
-    /// # Arguments
-    /// - `response` The server message sent to hte client.
-    fn send_response(&mut self, response: ServerMessage) {
-        let payload = response.encode_to_vec();
-        self.stream.write_all(&payload).expect("Failed to send response");
-        self.stream.flush().expect("Failed to flush stream");
+/// Reads the file descriptor limit, without which connections are never refused.
+fn detect_fd_budget(headroom: u64) -> Option<FdBudget> {
+    match FdBudget::detect(headroom) {
+        Ok(budget) => Some(budget),
+        Err(e) => {
+            warn!("File descriptor limit is unknown: {}", e);
+            None
+        }
+    }
+}
+
+/// The message type name of a request, as in the protocol definition.
+pub(crate) fn request_name(message: &client_message::Message) -> &'static str {
+    match message {
+        client_message::Message::EchoMessage(_) => "EchoMessage",
+        client_message::Message::AddRequest(_) => "AddRequest",
+        client_message::Message::ByeMessage(_) => "ByeMessage",
+        client_message::Message::MyStatsRequest(_) => "MyStatsRequest",
+        client_message::Message::DelayedEchoRequest(_) => "DelayedEchoRequest",
+        client_message::Message::DescriptorRequest(_) => "DescriptorRequest",
+        client_message::Message::BatchRequest(_) => "BatchRequest",
+        client_message::Message::EvalRequest(_) => "EvalRequest",
+        client_message::Message::UpgradeRequest(_) => "UpgradeRequest",
+    }
+}
+
+/// Builds the error message answering a request that could not be served.
+pub(crate) fn error_response(content: String) -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::ErrorMessage(ErrorMessage {
+            content,
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+/// Reads the PROXY protocol header of a connection, recording the real client address.
+///
+/// The header must arrive within `PROXY_HEADER_TIMEOUT`, so that a silent peer does not hold
+/// the thread reading it; the read timeout of the stream is restored afterwards.
+///
+/// # Returns
+/// - true  when the connection can be served.
+/// - false when the header was invalid or late, and the connection must be dropped.
+fn read_proxy_header(
+    stream: &mut TcpStream,
+    addr: &mut SocketAddr,
+    id: usize,
+    active_clients: &Mutex<Slab<Connection>>,
+) -> bool {
+    let header = stream.read_timeout().and_then(|idle_timeout| {
+        stream.set_read_timeout(Some(time_scale::scale(PROXY_HEADER_TIMEOUT)))?;
+        let header = proxy_protocol::read_header(stream);
+        stream.set_read_timeout(idle_timeout)?;
+        header
+    });
+    match header {
+        Ok(Some(client_addr)) => {
+            info!("Client {} connected through proxy {}", client_addr, addr);
+            *addr = client_addr;
+            if let Some(connection) = active_clients.lock().unwrap().get_mut(id) {
+                connection.addr = client_addr;
+            }
+            true
+        }
+        Ok(None) => true,
+        Err(e) => {
+            error!("Rejecting client {}: {}", addr, e);
+            false
+        }
+    }
+}
+
+/// A connection served by `Server::poll_once()` rather than by a pool worker.
+struct PolledClient {
+    id: usize,
+    addr: SocketAddr,
+    client: Client,
+    // The PROXY protocol header is read once the first bytes arrived.
+    proxy_header_pending: bool,
+}
+
+/// The state the server keeps for each connected client.
+struct Connection {
+    // A handle on the client stream, used to reach the client from outside its worker.
+    stream: TcpStream,
+    addr: SocketAddr,
+    // Unique across restarts, unlike the key of the connection.
+    session_id: u64,
+    usage: Arc<UsageCounters>,
+    connected_at: Instant,
+    drain: DrainSlot,
+    frame_format: SharedFrameFormat,
+    // Gives the descriptors of the connection back to the budget once released.
+    _fd_reservation: Option<FdReservation>,
+    // Gives the read buffer of the connection back to the memory budget once released.
+    _memory_reservation: Option<MemoryReservation>,
+}
+
+impl Connection {
+    /// Stops reading requests from the client: its worker sends the responses in flight,
+    /// then `farewell`, before closing the connection.
+    fn drain(&self, farewell: Farewell) {
+        *self.drain.lock().unwrap() = Some(farewell);
+        // Wakes up the worker blocked on reading the next request.
+        if let Err(e) = self.stream.shutdown(Shutdown::Read) {
+            warn!("Failed to drain client {}: {}", self.addr, e);
+        }
+    }
+}
+
+/// The error telling a client that its connection is refused, before it is closed.
+pub(crate) fn busy_message() -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::ErrorMessage(ErrorMessage {
+            content: "Server busy".to_string(),
+            code: ErrorCode::ServerBusy.into(),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+/// The error rejecting a request the memory budget of the server cannot hold.
+pub(crate) fn resource_exhausted_message() -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::ErrorMessage(ErrorMessage {
+            content: "Out of memory".to_string(),
+            code: ErrorCode::ResourceExhausted.into(),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+/// The notification sent to the clients when the server shuts down.
+///
+/// # Arguments
+/// - `reason` Why the server shuts down.
+/// - `session_summary` The usage of the connection being closed.
+pub(crate) fn shutdown_message(reason: ShutdownReason, session_summary: Option<SessionSummary>) -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::ErrorMessage(ErrorMessage {
+            content: "Server is shutting down.".to_string(),
+            shutdown_reason: reason.into(),
+            session_summary,
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+/// What a registered connection shares with the server.
+struct Registration {
+    id: usize,
+    usage: Arc<UsageCounters>,
+    drain: DrainSlot,
+    frame_format: SharedFrameFormat,
+}
+
+/// Collects the usage of the connected clients, and takes the usage of the departed ones.
+fn usage_report(active_clients: &Mutex<Slab<Connection>>, departed_usage: &Mutex<Vec<ClientUsage>>) -> UsageReport {
+    let mut clients: Vec<ClientUsage> = std::mem::take(&mut *departed_usage.lock().unwrap());
+    clients.extend(
+        active_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, connection)| connection.usage.snapshot(connection.addr, true)),
+    );
+    UsageReport::new(clients)
+}
+
+/// Releases the connection of a pool worker once dropped, however the worker ends, so that
+/// a panic does not leak its slot.
+struct ReleaseOnDrop {
+    active_clients: Arc<Mutex<Slab<Connection>>>,
+    departed_usage: Arc<Mutex<Vec<ClientUsage>>>,
+    retired_usage: Arc<UsageCounters>,
+    id: usize,
+}
+
+impl Drop for ReleaseOnDrop {
+    fn drop(&mut self) {
+        release_client(&self.active_clients, &self.departed_usage, &self.retired_usage, self.id);
+    }
+}
+
+/// Removes a client from the list of active clients, keeping its usage for the next report
+/// and for the totals.
+fn release_client(
+    active_clients: &Mutex<Slab<Connection>>,
+    departed_usage: &Mutex<Vec<ClientUsage>>,
+    retired_usage: &UsageCounters,
+    id: usize,
+) {
+    // This variable is shared across threads so a mutex must be used.
+    let mut clients = active_clients.lock().unwrap();
+    let connection = clients.remove(id);
+    if let Some(ref connection) = connection {
+        // Under the lock, so that the totals never miss the connection.
+        retired_usage.absorb(&connection.usage);
+    }
+    drop(clients);
+    if let Some(connection) = connection {
+        departed_usage.lock().unwrap().push(connection.usage.snapshot(connection.addr, false));
     }
 }
 
 pub struct Server {
     listener: TcpListener,
+    // Set by `run()`, or the first `poll_once()`, unless the server was stopped before.
     is_running: Arc<AtomicBool>,
+    // Set by `stop()`, even before the server runs, and cleared on restart.
+    stop_requested: AtomicBool,
+    // Set by `begin_drain()` with the reason of the shut down: no connection is accepted
+    // anymore, the current ones are closed once their requests are answered.
+    draining: Mutex<Option<ShutdownReason>>,
     // Use thread a thread pool instead of spawning a new thread
     // for each client for performance optimizations.
     thread_pool: ThreadPool,
-    // Used to track if there are any active clients.
-    active_clients: Arc<Mutex<Vec<TcpStream>>>,
+    // Used to track if there are any active clients, keyed by a connection id.
+    active_clients: Arc<Mutex<Slab<Connection>>>,
+    // Whether connections start with a PROXY protocol header from a load balancer.
+    proxy_protocol: bool,
+    unknown_message_handler: Option<UnknownMessageHandler>,
+    response_post_processors: Vec<ResponsePostProcessor>,
+    redacted_fields: Vec<String>,
+    // The longest delay a delayed echo may ask for.
+    max_echo_delay: Duration,
+    deprecated_requests: DeprecatedRequests,
+    // Why the server was stopped, reported to the clients and the embedding application.
+    shutdown_reason: Mutex<Option<ShutdownReason>>,
+    // Held for as long as the server exists, so that the file is removed on drop.
+    pid_file: Option<PidFile>,
+    // Time source of the time-based features, replaceable in tests.
+    clock: SharedClock,
+    // Connections served by the single-threaded `poll_once()` mode.
+    polled_clients: Mutex<Vec<PolledClient>>,
+    // What to do about failed accepts, with the counters reported to the application.
+    accept_errors: Mutex<AcceptErrors>,
+    // Connections are refused once few file descriptors are left, when the limit is known.
+    fd_budget: Option<FdBudget>,
+    // Bounds the memory the connections hold, which is unbounded without one.
+    memory_budget: Option<MemoryBudget>,
+    // The startup report as JSON, rendered once for the logs and the descriptor responses.
+    startup_json: OnceLock<Option<Arc<str>>>,
+    // Cancelled on stop, to tell the long-running work to return, and replaced on restart.
+    shutdown_token: Mutex<ShutdownToken>,
+    // Whether a `run()` returned since the server was stopped, so that the next one
+    // restarts it rather than honouring a `stop()` issued before it got scheduled.
+    run_ended: AtomicBool,
+    // Held while stopping, so that a restart waits for the previous run to be torn down.
+    stopping: Mutex<()>,
+    // Threads started with the server and joined when it stops.
+    background_tasks: Mutex<BackgroundTasks>,
+    // The usage of the clients that disconnected since the last usage report.
+    departed_usage: Arc<Mutex<Vec<ClientUsage>>>,
+    // The usage of every client that disconnected, for the totals.
+    retired_usage: Arc<UsageCounters>,
+    // Names the sessions, the spans and the background jobs.
+    id_generator: SharedIdGenerator,
+    // How often the idle accept loop wakes up to ping the watchdog.
+    accept_poll_interval: Duration,
+    // Wakes up the accept loop blocked waiting for a connection.
+    accept_waker: AcceptWaker,
+    // Connections are refused beyond this number, when set.
+    max_connections: Option<usize>,
+    connection_options: ConnectionOptions,
 }
 
-impl Server {
-    /// Creates a new server instance
+/// Configures a server before binding it, for the settings that `Server::new()` fixes.
+///
+/// The handlers and the other features are configured on the built server, with its
+/// `with_*` methods.
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+    addr: String,
+    bind_policy: BindPolicy,
+    workers: usize,
+    accept_poll_interval: Duration,
+    max_connections: Option<usize>,
+    connection_options: ConnectionOptions,
+}
+
+impl ServerBuilder {
+    /// Starts configuring a server listening on `addr`, with the defaults of `Server::new()`.
+    pub fn new(addr: &str) -> Self {
+        ServerBuilder {
+            addr: addr.to_string(),
+            bind_policy: BindPolicy::default(),
+            workers: DEFAULT_WORKERS,
+            accept_poll_interval: DEFAULT_ACCEPT_POLL_INTERVAL,
+            max_connections: None,
+            connection_options: ConnectionOptions::default(),
+        }
+    }
+
+    /// Listen on `addr` instead.
+    pub fn with_addr(mut self, addr: &str) -> Self {
+        self.addr = addr.to_string();
+        self
+    }
+
+    /// Retry or fall back to other addresses when the address is busy.
+    pub fn with_bind_policy(mut self, policy: BindPolicy) -> Self {
+        self.bind_policy = policy;
+        self
+    }
+
+    /// Serve the connections with `workers` threads, `DEFAULT_WORKERS` by default.
     ///
-    /// # Arguments
-    /// - `addr` The ip address for the server.
+    /// A connection holds a worker until it closes, so this bounds the clients served at
+    /// once. The connections accepted while every worker is busy are still registered,
+    /// but wait in a queue, unanswered, until a worker is released: their requests pile up
+    /// in the socket buffers and eventually block the writes of the clients. Use
+    /// `with_max_connections()` to refuse them instead, and `queued_connections()` to
+    /// watch the queue.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Read each connection `read_buffer_size` bytes at most at a time,
+    /// `DEFAULT_READ_BUFFER_SIZE` by default. Larger requests take several reads.
+    pub fn with_read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.connection_options.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    /// Drop the connections sending a request larger than `max_frame_len` bytes,
+    /// `DEFAULT_MAX_FRAME_LEN` by default.
+    pub fn with_max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.connection_options.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// Close the connections that sent nothing for `idle_timeout`, when served by `run()`.
+    /// They are kept open until the client leaves by default.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.connection_options.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Expect every frame to start with `framing::SYNC_MARKER`, and start the replies with
+    /// it too. A connection then survives corrupted bytes, which are skipped up to the
+    /// next frame and counted in `UsageTotals::resyncs`, rather than being dropped.
+    /// The clients must frame their requests the same way, see `Client::set_sync_marker()`.
+    pub fn with_sync_marker(mut self) -> Self {
+        self.connection_options.frame_format.sync_marker = true;
+        self
+    }
+
+    /// Split the replies longer than `fragment_len` bytes into fragments, for clients that
+    /// limit the size of a frame, see `FrameFormat::fragment_len`. The fragmented requests
+    /// are reassembled whether or not this is set.
+    pub fn with_fragment_len(mut self, fragment_len: usize) -> Self {
+        self.connection_options.frame_format.fragment_len = Some(fragment_len);
+        self
+    }
+
+    /// Serve the clients predating the framing, which send their requests as is and take
+    /// every read for a response, as told from the first byte they send. Enabled by
+    /// default, so that they keep working, unless `with_sync_marker()` is set. Until that
+    /// byte arrives, every client is taken to frame its messages, e.g. a shut down
+    /// notification is framed.
+    pub fn with_unframed_clients(mut self, enabled: bool) -> Self {
+        self.connection_options.unframed_clients = enabled;
+        self
+    }
+
+    /// Hold the replies of each connection for up to `window`, or until `max_replies` of
+    /// them are held, and write them at once, so that a burst of small replies, e.g. to
+    /// pipelined requests, takes fewer writes and packets. The replies are written as they
+    /// come by default, which delays none of them.
+    pub fn with_write_coalescing(mut self, window: Duration, max_replies: usize) -> Self {
+        self.connection_options.write_coalescing = Some(WriteCoalescing { window, max_replies });
+        self
+    }
+
+    /// Wake up the idle accept loop every `interval` to ping the systemd watchdog, when
+    /// it is enabled, `DEFAULT_ACCEPT_POLL_INTERVAL` by default. Otherwise the loop sleeps
+    /// until a connection arrives or the server stops, on platforms with `poll()`, and
+    /// checks for connections every `interval` on the others.
+    pub fn with_accept_poll_interval(mut self, interval: Duration) -> Self {
+        self.accept_poll_interval = interval;
+        self
+    }
+
+    /// Refuse the connections beyond `max_connections`, with a "Server busy" error
+    /// message. Connections are only limited by the file descriptors by default.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Binds the server.
     ///
     /// # Returns
-    /// - Ok    upon successful message decoding and handling.
-    /// - Err   when either the decoding or the handling fails.
-    pub fn new(addr: &str) -> io::Result<Self> {
-        let listener = TcpListener::bind(addr)?;
+    /// - Ok    upon binding one of the addresses.
+    /// - Err   when a setting is invalid, or no address could be bound.
+    pub fn build(self) -> io::Result<Server> {
+        if self.workers == 0 || self.connection_options.read_buffer_size == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "The server needs at least one worker and a non-empty read buffer",
+            ));
+        }
+        if self.connection_options.idle_timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "The idle timeout must not be zero"));
+        }
+
+        let listener = bind::bind(&self.addr, &self.bind_policy)?;
         let is_running = Arc::new(AtomicBool::new(false));
-        let thread_pool = ThreadPool::new(15);
-        let active_clients = Arc::new(Mutex::new(Vec::new()));
+        let thread_pool = ThreadPool::new(self.workers);
+        let active_clients = Arc::new(Mutex::new(Slab::new()));
         Ok(Server {
             listener,
             is_running,
+            stop_requested: AtomicBool::new(false),
+            draining: Mutex::new(None),
             thread_pool,
             active_clients,
+            proxy_protocol: false,
+            unknown_message_handler: None,
+            response_post_processors: Vec::new(),
+            redacted_fields: Vec::new(),
+            max_echo_delay: DEFAULT_MAX_ECHO_DELAY,
+            deprecated_requests: DeprecatedRequests::default(),
+            shutdown_reason: Mutex::new(None),
+            pid_file: None,
+            clock: clock::system(),
+            polled_clients: Mutex::new(Vec::new()),
+            accept_errors: Mutex::new(AcceptErrors::default()),
+            fd_budget: detect_fd_budget(fd_limit::DEFAULT_HEADROOM),
+            memory_budget: None,
+            startup_json: OnceLock::new(),
+            shutdown_token: Mutex::new(ShutdownToken::new()),
+            run_ended: AtomicBool::new(false),
+            stopping: Mutex::new(()),
+            background_tasks: Mutex::new(BackgroundTasks::default()),
+            departed_usage: Arc::new(Mutex::new(Vec::new())),
+            retired_usage: Arc::new(UsageCounters::default()),
+            id_generator: Arc::new(SnowflakeIdGenerator::default()),
+            accept_poll_interval: self.accept_poll_interval,
+            accept_waker: AcceptWaker::new()?,
+            max_connections: self.max_connections,
+            connection_options: self.connection_options,
         })
     }
+}
+
+impl Server {
+    /// Creates a new server instance
+    ///
+    /// # Arguments
+    /// - `addr` The ip address for the server. With port 0, the system picks a free port,
+    ///   reported by `local_addr()`.
+    ///
+    /// # Returns
+    /// - Ok    upon successful message decoding and handling.
+    /// - Err   when either the decoding or the handling fails.
+    pub fn new(addr: &str) -> io::Result<Self> {
+        Self::with_bind_policy(addr, &BindPolicy::default())
+    }
+
+    /// Creates a new server instance, retrying or falling back to other addresses
+    /// when `addr` is busy.
+    ///
+    /// # Arguments
+    /// - `addr` The preferred ip address for the server.
+    /// - `policy` What to do when the address is already in use.
+    ///
+    /// # Returns
+    /// - Ok    upon binding one of the addresses.
+    /// - Err   when no address could be bound.
+    pub fn with_bind_policy(addr: &str, policy: &BindPolicy) -> io::Result<Self> {
+        Self::builder(addr).with_bind_policy(policy.clone()).build()
+    }
+
+    /// Starts configuring a server listening on `addr`, for the settings `new()` fixes:
+    /// the worker count, the buffer sizes, the timeouts and the connection limit.
+    pub fn builder(addr: &str) -> ServerBuilder {
+        ServerBuilder::new(addr)
+    }
+
+    /// Creates a server for trying the system out: it listens on an ephemeral port of the
+    /// loopback interface, see `local_addr()`, and logs to the console. Every built-in
+    /// request is served with the default settings.
+    ///
+    /// # Returns
+    /// - Ok    upon binding a port.
+    /// - Err   when no port could be bound.
+    pub fn demo() -> io::Result<Self> {
+        demo::init_console_logging(Level::Info);
+        Self::new("127.0.0.1:0")
+    }
+
+    /// The number of connections being served, at most the number of workers.
+    pub fn busy_workers(&self) -> usize {
+        self.thread_pool.active_count()
+    }
+
+    /// The number of connections accepted but waiting for a worker to be released.
+    pub fn queued_connections(&self) -> usize {
+        self.thread_pool.queued_count()
+    }
+
+    /// The address the server listens on, e.g. to find the port picked when binding port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Expect every connection to start with a PROXY protocol (v1 or v2) header, as sent
+    /// by a TCP load balancer, and use the client address it carries. Connections without
+    /// a valid header are rejected.
+    ///
+    /// # Arguments
+    /// - `enabled` Whether the header is expected.
+    pub fn with_proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Keep `headroom` file descriptors free below the limit of the process, refusing new
+    /// connections with a `SERVER_BUSY` error beyond that, rather than failing accepts.
+    pub fn with_fd_headroom(mut self, headroom: u64) -> Self {
+        self.fd_budget = detect_fd_budget(headroom);
+        self
+    }
+
+    /// Bound the bytes the connections hold at once to `limit`: their read buffers, their
+    /// held replies, and their delayed echoes until sent. Beyond that, new connections are
+    /// refused with a `SERVER_BUSY` error, delayed echoes are rejected with a
+    /// `RESOURCE_EXHAUSTED` error, replies are written through rather than held, and the
+    /// connections whose delayed echoes hold memory are not read until these are sent.
+    /// Unbounded by default.
+    pub fn with_memory_budget(mut self, limit: u64) -> Self {
+        self.memory_budget = Some(MemoryBudget::new(limit));
+        self
+    }
+
+    /// Decide what the accept loop does when accepting a connection fails, instead of
+    /// backing off up to 1 s.
+    pub fn with_accept_error_policy(mut self, policy: AcceptErrorPolicy) -> Self {
+        self.accept_errors = Mutex::new(AcceptErrors::new(policy));
+        self
+    }
+
+    /// Use `clock` as the time source of the time-based features, e.g. a `ManualClock`
+    /// to test them deterministically.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Record the process id in `path` for as long as the server exists, so that init
+    /// scripts can tell whether an instance is already running.
+    ///
+    /// # Returns
+    /// - Ok    upon writing the file, replacing a stale one left by a crashed instance.
+    /// - Err   when another running instance holds the file, or it could not be written.
+    pub fn with_pid_file(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        self.pid_file = Some(PidFile::create(path)?);
+        Ok(self)
+    }
+
+    /// The PID file written for the server, if any.
+    pub fn pid_file(&self) -> Option<&Path> {
+        self.pid_file.as_ref().map(PidFile::path)
+    }
+
+    /// Let `handler` answer the requests that the server does not understand, e.g. to
+    /// experiment with new message types without changing the built-in handlers.
+    ///
+    /// # Arguments
+    /// - `handler` Called with the raw bytes of each unknown or undecodable request.
+    pub fn with_unknown_message_handler(mut self, handler: UnknownMessageHandler) -> Self {
+        self.unknown_message_handler = Some(handler);
+        self
+    }
+
+    /// Let `post_processor` annotate or transform every response before it is sent, after
+    /// the post-processors registered earlier. Responses are post-processed centrally, so
+    /// that handlers do not need to know about e.g. tracing or timing.
+    ///
+    /// # Arguments
+    /// - `post_processor` Called with each response, including error messages.
+    pub fn with_response_post_processor(mut self, post_processor: ResponsePostProcessor) -> Self {
+        self.response_post_processors.push(post_processor);
+        self
+    }
+
+    /// Mask sensitive fields in the requests logged at debug level. Free-text content is
+    /// only ever logged at that level.
+    ///
+    /// # Arguments
+    /// - `redacted_fields` The names of the masked fields, as rendered in JSON (camelCase),
+    ///   e.g. `content`.
+    pub fn with_redacted_fields(mut self, redacted_fields: Vec<String>) -> Self {
+        self.redacted_fields = redacted_fields;
+        self
+    }
+
+    /// Bound the delay that a `DelayedEchoRequest` may ask for, longer delays are rejected
+    /// with an error message.
+    ///
+    /// # Arguments
+    /// - `max_echo_delay` The longest accepted delay, `DEFAULT_MAX_ECHO_DELAY` by default.
+    pub fn with_max_echo_delay(mut self, max_echo_delay: Duration) -> Self {
+        self.max_echo_delay = max_echo_delay;
+        self
+    }
+
+    /// Mark a request type as deprecated, ahead of its removal: its responses carry
+    /// `warning` in their `deprecated` metadata entry, and its use is logged, so that the
+    /// clients still sending it can be found and migrated.
+    ///
+    /// # Arguments
+    /// - `request` The message type name of the request, e.g. `DelayedEchoRequest`.
+    /// - `warning` Tells the client what to use instead, or when the request goes away.
+    pub fn with_deprecated_request(mut self, request: &str, warning: &str) -> Self {
+        Arc::make_mut(&mut self.deprecated_requests).insert(request.to_string(), warning.to_string());
+        self
+    }
+
+    /// Generate the ids of the sessions, trace spans and background jobs with
+    /// `id_generator`, e.g. to follow a fleet-wide scheme. The ids are time-ordered
+    /// `SnowflakeIdGenerator` ones by default.
+    pub fn with_id_generator(mut self, id_generator: SharedIdGenerator) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    /// Runs `task` on its own thread for as long as the server runs, e.g. to reap idle
+    /// resources or flush metrics periodically. The task is started by `run()`, or right
+    /// away when the server already runs, and should return once the token is cancelled:
+    /// `stop()` waits for it. It runs once: a restarted server does not run it again, see
+    /// `spawn_background_on_each_run()`.
+    ///
+    /// # Arguments
+    /// - `name` Names the thread and the task in the logs.
+    /// - `task` Called with the token cancelled when the server stops.
+    ///
+    /// # Returns
+    /// - Ok    with the id of the job, upon registering or starting the task.
+    /// - Err   when the server was already stopped, or the thread could not be spawned.
+    pub fn spawn_background(
+        &self,
+        name: impl Into<String>,
+        task: impl FnOnce(ShutdownToken) + Send + 'static,
+    ) -> io::Result<u64> {
+        let name = name.into();
+        let job_id = self.id_generator.next_id();
+        self.background_tasks
+            .lock()
+            .unwrap()
+            .spawn(name.clone(), Box::new(task), &self.shutdown_token())?;
+        info!("Background task {} scheduled as job {}.", name, job_id);
+        Ok(job_id)
+    }
+
+    /// Runs `task` like `spawn_background()`, and again on every later run of the server,
+    /// e.g. for the tasks that must not stop once the server restarted.
+    ///
+    /// # Arguments
+    /// - `name` Names the thread and the task in the logs.
+    /// - `task` Called on every run, with the token cancelled when that run stops.
+    ///
+    /// # Returns
+    /// - Ok    with the id of the job, upon registering or starting the task.
+    /// - Err   when the server was already stopped, or the thread could not be spawned.
+    pub fn spawn_background_on_each_run(
+        &self,
+        name: impl Into<String>,
+        task: impl Fn(ShutdownToken) + Send + Sync + 'static,
+    ) -> io::Result<u64> {
+        let name = name.into();
+        let job_id = self.id_generator.next_id();
+        self.background_tasks
+            .lock()
+            .unwrap()
+            .spawn_recurring(name.clone(), Arc::new(task), &self.shutdown_token())?;
+        info!("Background task {} scheduled on each run as job {}.", name, job_id);
+        Ok(job_id)
+    }
+
+    /// Report the usage of every client to `sink` each `interval`, and once more when the
+    /// server stops, e.g. for operators billing or planning capacity by device.
+    ///
+    /// # Returns
+    /// - Ok    upon scheduling the reports, which start with `run()`, and again with every
+    ///   later run.
+    /// - Err   when the server was already stopped.
+    pub fn with_usage_report(self, interval: Duration, sink: UsageReportSink) -> io::Result<Self> {
+        let active_clients = self.active_clients.clone();
+        let departed_usage = self.departed_usage.clone();
+        self.spawn_background_on_each_run("usage-report", move |token| loop {
+            let stopping = token.wait_timeout(time_scale::scale(interval));
+            let report = usage_report(&active_clients, &departed_usage);
+            if let Err(e) = report.write_to(&sink) {
+                error!("Failed to write usage report: {}", e);
+            }
+            if stopping {
+                break;
+            }
+        })?;
+        Ok(self)
+    }
+
+    /// Reports the usage of the connected clients, and of the ones that disconnected since
+    /// the previous report, which are reported only once.
+    pub fn usage_report(&self) -> UsageReport {
+        usage_report(&self.active_clients, &self.departed_usage)
+    }
+
+    /// The usage of every client since the server was created, connected or not.
+    ///
+    /// The counters are kept per connection and only summed up here, so that counting
+    /// does not slow down the workers serving requests concurrently.
+    pub fn usage_totals(&self) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        totals.add(&self.retired_usage);
+        for (_, connection) in self.active_clients.lock().unwrap().iter() {
+            totals.add(&connection.usage);
+        }
+        totals
+    }
+
+    /// The token of the current run.
+    fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown_token.lock().unwrap().clone()
+    }
 
     /// Runs the server, listening for incoming connections and handling them
+    ///
+    /// A server whose `run()` returned after `stop()` can be run again, on the same
+    /// address. The background tasks registered with `spawn_background()` ran once and are
+    /// not started again, unlike the ones registered with `spawn_background_on_each_run()`,
+    /// such as the usage reports.
     pub fn run(&self) -> io::Result<()> {
-        // Set the server as running
-        self.is_running.store(true, Ordering::SeqCst);
+        if self.stop_requested.load(Ordering::SeqCst) && self.run_ended.load(Ordering::SeqCst) {
+            self.restart();
+        }
+        let result = if self.start_running() {
+            self.serve()
+        } else {
+            info!("Server was stopped before it ran.");
+            Ok(())
+        };
+        self.run_ended.store(true, Ordering::SeqCst);
+        result
+    }
+
+    /// Marks the server as running, unless a `stop()` was issued before it got scheduled.
+    ///
+    /// # Returns
+    /// - Whether the server runs.
+    fn start_running(&self) -> bool {
+        // Under the lock taken by `stop_with_reason()`, so that a concurrent stop either
+        // sees the server running and stops it, or keeps it from running.
+        let _shutdown_reason = self.shutdown_reason.lock().unwrap();
+        if !self.stop_requested.load(Ordering::SeqCst) {
+            self.is_running.store(true, Ordering::SeqCst);
+        }
+        self.is_running.load(Ordering::SeqCst)
+    }
+
+    /// Resets the state left by the previous run.
+    fn restart(&self) {
+        let _stopped = self.stopping.lock().unwrap();
+        *self.shutdown_token.lock().unwrap() = ShutdownToken::new();
+        self.background_tasks.lock().unwrap().restart();
+        *self.shutdown_reason.lock().unwrap() = None;
+        *self.draining.lock().unwrap() = None;
+        self.run_ended.store(false, Ordering::SeqCst);
+        self.stop_requested.store(false, Ordering::SeqCst);
+        info!("Server restarted.");
+    }
+
+    /// What build and configuration the server runs, as logged when it starts.
+    pub fn startup_report(&self) -> io::Result<StartupReport> {
+        let limits = Limits {
+            workers: self.thread_pool.max_count(),
+            max_connections: self.max_connections,
+            read_buffer_size: self.connection_options.read_buffer_size,
+            max_frame_len: self.connection_options.max_frame_len,
+            idle_timeout_ms: self.connection_options.idle_timeout.map(|timeout| timeout.as_millis() as u64),
+            max_echo_delay_ms: self.max_echo_delay.as_millis() as u64,
+            fd_limit: self.fd_budget.as_ref().map(FdBudget::limit),
+        };
+        Ok(StartupReport::new(self.listener.local_addr()?, limits))
+    }
+
+    /// The startup report rendered as JSON, `None` when it cannot be.
+    fn startup_json(&self) -> Option<Arc<str>> {
+        self.startup_json
+            .get_or_init(|| match self.startup_report().and_then(|report| report.to_json().map_err(io::Error::other)) {
+                Ok(report) => Some(report.into()),
+                Err(e) => {
+                    warn!("Failed to render the startup report: {}", e);
+                    None
+                }
+            })
+            .clone()
+    }
+
+    /// The accept loop of `run()`.
+    fn serve(&self) -> io::Result<()> {
         info!("Server is running on {}", self.listener.local_addr()?);
+        if let Some(report) = self.startup_json() {
+            info!("Startup report: {}", report);
+        }
+
+        self.background_tasks.lock().unwrap().start_all(&self.shutdown_token())?;
 
         // Set the listener to non-blocking mode
         self.listener.set_nonblocking(true)?;
 
+        // Tell systemd that the server accepts connections. The watchdog is pinged from the
+        // accept loop, so that systemd restarts the service if the loop ever wedges.
+        #[cfg(unix)]
+        let mut watchdog = {
+            if let Err(e) = systemd::notify("READY=1") {
+                warn!("Failed to notify systemd: {}", e);
+            }
+            systemd::Watchdog::from_env(self.clock.clone())
+        };
+
+        // The loop blocks until a connection arrives or the server stops, waking up on its
+        // own only to ping the watchdog.
+        #[cfg(unix)]
+        let wake_every = watchdog.is_enabled().then(|| time_scale::scale(self.accept_poll_interval));
+        #[cfg(not(unix))]
+        let wake_every = Some(time_scale::scale(self.accept_poll_interval));
+
         while self.is_running.load(Ordering::SeqCst) {
+            #[cfg(unix)]
+            watchdog.tick();
+
+            // Connections are left in the backlog while draining.
+            if self.is_draining() {
+                let shutdown_token = self.shutdown_token();
+                match wake_every {
+                    Some(timeout) => {
+                        shutdown_token.wait_timeout(timeout);
+                    }
+                    None => shutdown_token.wait(),
+                }
+                continue;
+            }
+
             match self.listener.accept() {
                 Ok((stream, addr)) => {
-                    info!("New client connected: {}", addr);
+                    self.accept_errors.lock().unwrap().on_success();
+
                     // Add the client to the list of active clients.
-                    {
-                        self.active_clients.lock().unwrap().push(stream.try_clone().unwrap());
-                    } // Lock is released here.
+                    let Registration { id, usage, drain, frame_format } = match self.register_client(&stream, addr) {
+                        Some(registration) => registration,
+                        None => continue,
+                    };
 
-                    // Make a clone of the is_running attribute to be used within the threads.
-                    let is_running = self.is_running.clone();
+                    // Make a clone of the shutdown token to be used within the threads.
+                    let shutdown_token = self.shutdown_token();
 
                     // Make a clone of the active_clients attribute to be used within the threads.
                     let active_clients = self.active_clients.clone();
+                    let departed_usage = self.departed_usage.clone();
+                    let retired_usage = self.retired_usage.clone();
+                    let proxy_protocol = self.proxy_protocol;
+                    let unknown_message_handler = self.unknown_message_handler.clone();
+                    let response_post_processors = self.response_post_processors.clone();
+                    let redacted_fields = self.redacted_fields.clone();
+                    let max_echo_delay = self.max_echo_delay;
+                    let deprecated_requests = self.deprecated_requests.clone();
+                    let id_generator = self.id_generator.clone();
+                    let clock = self.clock.clone();
+                    let connection_options = self.connection_options;
+                    let memory_budget = self.memory_budget.clone();
+                    let startup_report = self.startup_json();
                     // Create a thread for each client request.
                     self.thread_pool.execute( move || {
-                        // Create a client instance.
-                        let mut client = Client::new(stream);
-                        // The thread will loop indefinetly until the serverr shuts down or an error occurs.
-                        while is_running.load(Ordering::SeqCst) {
-                            if let Err(e) = client.handle() {
-                                error!("Error handling client: {}", e);
-                                break;
+                        // Dropped last, once the client is.
+                        let release = ReleaseOnDrop {
+                            active_clients,
+                            departed_usage,
+                            retired_usage,
+                            id,
+                        };
+                        let mut stream = stream;
+                        let mut addr = addr;
+
+                        // Behind a load balancer, the real client address comes first on the stream.
+                        // It is read here rather than in the accept loop so that a slow proxy does not
+                        // hold back other connections.
+                        let accepted = !proxy_protocol
+                            || read_proxy_header(&mut stream, &mut addr, id, &release.active_clients);
+
+                        if accepted {
+                            // Create a client instance.
+                            let session = Session::new()
+                                .with_unknown_message_handler(unknown_message_handler)
+                                .with_response_post_processors(response_post_processors)
+                                .with_redacted_fields(redacted_fields)
+                                .with_max_echo_delay(max_echo_delay)
+                                .with_deprecated_requests(deprecated_requests)
+                                .with_clock(clock)
+                                .with_id_generator(id_generator)
+                                .with_memory_budget(memory_budget.clone())
+                                .with_startup_report(startup_report)
+                                .with_usage(usage);
+                            let mut client = Client::new(stream)
+                                .with_options(connection_options)
+                                .with_memory_budget(memory_budget)
+                                .with_session(session)
+                                .with_shutdown_token(shutdown_token)
+                                .with_frame_format(frame_format)
+                                .with_drain_slot(drain);
+                            // The thread will loop indefinetly until the serverr shuts down, the client
+                            // says goodbye or an error occurs.
+                            while !client.is_shutting_down() && !client.is_closed() {
+                                // Not read until its delayed echoes give memory back.
+                                if client.is_held_back() {
+                                    if let Err(e) = client.write_due_replies() {
+                                        error!("Error handling client: {}", e);
+                                        break;
+                                    }
+                                    thread::sleep(time_scale::scale(MEMORY_POLL_INTERVAL));
+                                    continue;
+                                }
+                                if let Err(e) = client.handle() {
+                                    error!("Error handling client: {}", e);
+                                    break;
+                                }
                             }
+                            client.finish_drain();
                         }
 
                         // Remove the client from the list of active clients.
-                        // This variable is shared across threads so a mutex must be used.
-                        {
-                            active_clients.lock().unwrap().retain(|s| s.peer_addr().unwrap() != addr);
-                        } // Lock is released here.
+                        drop(release);
+                        info!("Client {} released.", addr);
                     });
                 }
 
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    // If there are no incoming connections, sleep for 100 ms.
-                    thread::sleep(Duration::from_millis(100));
+                    // If there are no incoming connections, wait for one.
+                    if let Err(e) = self.accept_waker.wait(&self.listener, wake_every) {
+                        error!("Failed to wait for connections: {}", e);
+                        thread::sleep(time_scale::scale(self.accept_poll_interval));
+                    }
                 }
 
                 Err(e) => {
                     // Connection was not accepted succesfully.
                     error!("Error accepting connection: {}", e);
+                    self.handle_accept_error();
                 }
             }
         }
 
+        #[cfg(unix)]
+        if let Err(e) = systemd::notify("STOPPING=1") {
+            warn!("Failed to notify systemd: {}", e);
+        }
+
+        // Leave the listener as it was bound, for the next run.
+        self.listener.set_nonblocking(false)?;
         info!("Server stopped.");
         Ok(())
     }
 
-    /// Send an error to all clients that are still active of the shut down.
-    pub fn notify_clients_of_shutdown(&self) {
+    /// Apply the accept error policy after a failed accept.
+    fn handle_accept_error(&self) {
+        let action = self.accept_errors.lock().unwrap().on_error();
+        match action {
+            AcceptErrorAction::Retry => {}
+            AcceptErrorAction::Wait(delay) => {
+                warn!("Not accepting connections for {:?}", delay);
+                // Cut short by `stop()`.
+                self.shutdown_token().sleep(&*self.clock, time_scale::scale(delay));
+            }
+            AcceptErrorAction::Shutdown => self.stop_with_reason(ShutdownReason::FatalAcceptError),
+        }
+    }
+
+    /// The counters of the failed accepts, by action taken.
+    pub fn accept_error_stats(&self) -> AcceptErrorStats {
+        self.accept_errors.lock().unwrap().stats()
+    }
+
+    /// Performs one step of the single-threaded execution mode, as an alternative to
+    /// `run()`: accepts the pending connections, then handles at most one request on each
+    /// connection that has one ready. Nothing is handed to the thread pool and nothing
+    /// blocks on an idle connection, so tests can interleave clients, requests and
+    /// `stop()` deterministically from a single thread.
+    ///
+    /// # Returns
+    /// - Ok    with the number of events processed (connections accepted or closed,
+    ///   requests handled).
+    /// - Err   when accepting connections fails.
+    pub fn poll_once(&self) -> io::Result<usize> {
+        let mut polled_clients = self.polled_clients.lock().unwrap();
+        let mut events = 0;
+
+        // Once stopped, release the connections that were still served.
+        if !self.start_running() {
+            for polled in polled_clients.drain(..) {
+                release_client(&self.active_clients, &self.departed_usage, &self.retired_usage, polled.id);
+                events += 1;
+            }
+            return Ok(events);
+        }
+
+        // Accept every pending connection, unless draining.
+        self.listener.set_nonblocking(true)?;
+        while !self.is_draining() {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    // Accepted streams may inherit the non-blocking mode of the listener.
+                    stream.set_nonblocking(false)?;
+                    if let Some(Registration { id, usage, drain, frame_format }) = self.register_client(&stream, addr) {
+                        polled_clients.push(PolledClient {
+                            id,
+                            addr,
+                            client: Client::new(stream)
+                                .with_options(self.connection_options)
+                                .with_session(
+                                    Session::new()
+                                        .with_unknown_message_handler(self.unknown_message_handler.clone())
+                                        .with_response_post_processors(self.response_post_processors.clone())
+                                        .with_redacted_fields(self.redacted_fields.clone())
+                                        .with_max_echo_delay(self.max_echo_delay)
+                                        .with_deprecated_requests(self.deprecated_requests.clone())
+                                        .with_clock(self.clock.clone())
+                                        .with_id_generator(self.id_generator.clone())
+                                        .with_memory_budget(self.memory_budget.clone())
+                                        .with_startup_report(self.startup_json())
+                                        .with_usage(usage),
+                                )
+                                .with_memory_budget(self.memory_budget.clone())
+                                .with_shutdown_token(self.shutdown_token())
+                                .with_frame_format(frame_format)
+                                .with_drain_slot(drain),
+                            proxy_header_pending: self.proxy_protocol,
+                        });
+                        events += 1;
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Serve the connections that have data, or were closed.
+        polled_clients.retain_mut(|polled| {
+            let keep = match polled.client.has_pending_input() {
+                // Not read until its delayed echoes give memory back.
+                Ok(_) if polled.client.is_held_back() => match polled.client.write_due_replies() {
+                    Ok(()) => return true,
+                    Err(e) => {
+                        error!("Error handling client: {}", e);
+                        false
+                    }
+                },
+                Ok(false) => match polled.client.write_due_replies() {
+                    Ok(()) => return true,
+                    Err(e) => {
+                        error!("Error handling client: {}", e);
+                        false
+                    }
+                },
+                Ok(true) if polled.proxy_header_pending => {
+                    polled.proxy_header_pending = false;
+                    read_proxy_header(&mut polled.client.stream, &mut polled.addr, polled.id, &self.active_clients)
+                }
+                Ok(true) => match polled.client.handle() {
+                    Ok(()) => !polled.client.is_closed(),
+                    Err(e) => {
+                        error!("Error handling client: {}", e);
+                        false
+                    }
+                },
+                Err(e) => {
+                    error!("Error polling client: {}", e);
+                    false
+                }
+            };
+            events += 1;
+
+            if !keep {
+                polled.client.finish_drain();
+                release_client(&self.active_clients, &self.departed_usage, &self.retired_usage, polled.id);
+                info!("Client {} released.", polled.addr);
+            }
+            keep
+        });
+
+        Ok(events)
+    }
+
+    /// Adds an accepted connection to the list of active clients.
+    ///
+    /// # Returns
+    /// - Some  with what the client shares with the server.
+    /// - None  when the connection could not be registered and was dropped.
+    fn register_client(&self, stream: &TcpStream, addr: SocketAddr) -> Option<Registration> {
+        info!("New client connected: {}", addr);
+        let fd_reservation = self.fd_budget.as_ref().map(FdBudget::reserve);
+        let read_buffer_size = self.connection_options.read_buffer_size as u64;
+        let memory_reservation = self.memory_budget.as_ref().map(|budget| budget.reserve(read_buffer_size));
+        let refusal = if matches!(fd_reservation, Some(None)) {
+            Some("running out of file descriptors")
+        } else if matches!(memory_reservation, Some(None)) {
+            Some("running out of memory")
+        } else if self
+            .max_connections
+            .is_some_and(|max| self.active_clients.lock().unwrap().len() >= max)
+        {
+            Some("too many connections")
+        } else {
+            None
+        };
+        if let Some(reason) = refusal {
+            warn!("Refusing client {}: {}", addr, reason);
+            let frame_format = self.connection_options.frame_format;
+            if let Err(e) = self.notify(stream, &frame_format, busy_message()) {
+                warn!("Failed to notify client {}: {}", addr, e);
+            }
+            return None;
+        }
+
+        let handle = match stream.try_clone() {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Failed to register client {}: {}", addr, e);
+                return None;
+            }
+        };
+
         // This variable is shared across threads so a mutex must be used.
+        let usage = Arc::new(UsageCounters::default());
+        let drain = DrainSlot::default();
+        let frame_format = Arc::new(Mutex::new(self.connection_options.frame_format));
+        let session_id = self.id_generator.next_id();
+        let mut clients = self.active_clients.lock().unwrap();
+        let connection = Connection {
+            stream: handle,
+            addr,
+            session_id,
+            usage: usage.clone(),
+            connected_at: self.clock.now(),
+            drain: drain.clone(),
+            frame_format: frame_format.clone(),
+            _fd_reservation: fd_reservation.flatten(),
+            _memory_reservation: memory_reservation.flatten(),
+        };
+        // Accepted before the server started draining, but registered since.
+        if let Some(reason) = *self.draining.lock().unwrap() {
+            connection.drain(Farewell::Shutdown(reason));
+        }
+        let id = clients.insert(connection);
+        drop(clients);
+        info!("Client {} opened session {}.", addr, session_id);
+        Some(Registration { id, usage, drain, frame_format })
+    }
+
+    /// Lists the resources still held by the server, which should all have been released
+    /// once it was stopped.
+    ///
+    /// # Returns
+    /// - A description of each leaked resource, empty when the server is quiesced.
+    pub fn leaks(&self) -> Vec<String> {
+        let mut leaks = Vec::new();
+
+        if self.is_running.load(Ordering::SeqCst) {
+            leaks.push("server is still running".to_string());
+        }
+
+        for (id, connection) in self.active_clients.lock().unwrap().iter() {
+            leaks.push(format!("connection #{} to {} is still registered", id, connection.addr));
+        }
+
+        let polled = self.polled_clients.lock().unwrap().len();
+        if polled > 0 {
+            leaks.push(format!("{} polled connection(s) are still open", polled));
+        }
+
+        if let Some(used) = self.memory_used().filter(|&used| used > 0) {
+            leaks.push(format!("{} byte(s) of the memory budget are still held", used));
+        }
+
+        let queued = self.thread_pool.queued_count();
+        if queued > 0 {
+            leaks.push(format!("{} job(s) are still queued", queued));
+        }
+
+        let active = self.thread_pool.active_count();
+        if active > 0 {
+            leaks.push(format!("{} worker(s) are still busy", active));
+        }
+
+        for name in self.background_tasks.lock().unwrap().unfinished() {
+            leaks.push(format!("background task {} is still running", name));
+        }
+
+        leaks
+    }
+
+    /// Verifies that a stopped server released every connection, job and worker.
+    ///
+    /// # Panics
+    /// - When anything leaked, listing what was left behind.
+    pub fn assert_quiesced(&self) {
+        let leaks = self.leaks();
+        assert!(leaks.is_empty(), "Server is not quiesced: {}", leaks.join(", "));
+    }
+
+    /// The number of clients currently connected to the server.
+    pub fn active_client_count(&self) -> usize {
+        self.active_clients.lock().unwrap().len()
+    }
+
+    /// The bytes the connections hold, `None` without a memory budget.
+    pub fn memory_used(&self) -> Option<u64> {
+        self.memory_budget.as_ref().map(MemoryBudget::used)
+    }
+
+    /// The addresses of the clients currently connected to the server.
+    pub fn active_client_addrs(&self) -> Vec<SocketAddr> {
+        self.active_clients.lock().unwrap().iter().map(|(_, connection)| connection.addr).collect()
+    }
+
+    /// The session ids of the connected clients, with their address.
+    pub fn active_sessions(&self) -> Vec<(u64, SocketAddr)> {
         let clients = self.active_clients.lock().unwrap();
+        clients.iter().map(|(_, connection)| (connection.session_id, connection.addr)).collect()
+    }
+
+    /// Why the server was stopped.
+    ///
+    /// # Returns
+    /// - Some  with the reason given to `stop_with_reason()`.
+    /// - None  while the server was not stopped.
+    pub fn shutdown_reason(&self) -> Option<ShutdownReason> {
+        *self.shutdown_reason.lock().unwrap()
+    }
+
+    /// Stops serving the clients connected from `addr`, e.g. to move a misbehaving device
+    /// or one to upgrade elsewhere, while the other clients are still served: no request is
+    /// read from them anymore, the responses in flight are sent, then a goodbye carrying
+    /// `reconnect_hint` before the connection is closed.
+    ///
+    /// # Returns
+    /// - true  when a client connected from `addr` was found.
+    /// - false otherwise.
+    pub fn drain(&self, addr: SocketAddr, reconnect_hint: ReconnectHint) -> bool {
+        let clients = self.active_clients.lock().unwrap();
+        let mut drained = false;
+        for (_, connection) in clients.iter().filter(|(_, connection)| connection.addr == addr) {
+            info!("Draining client {}", addr);
+            connection.drain(Farewell::Reconnect(reconnect_hint.clone()));
+            drained = true;
+        }
+        drained
+    }
+
+    /// Starts a graceful shut down, in this order: no connection is accepted anymore, no
+    /// request is read from the connected clients anymore, the requests already read are
+    /// answered, including the delayed echoes, then each client is notified of the shut
+    /// down and its connection closed. The server keeps running until `stop()`.
+    ///
+    /// With `run()`, `drain_and_stop()` does all of it. With `poll_once()`, keep polling
+    /// until `active_client_count()` drops to zero, then call `stop_with_reason()`.
+    ///
+    /// # Arguments
+    /// - `reason` The cause of the shut down, sent to the clients.
+    pub fn begin_drain(&self, reason: ShutdownReason) {
+        if self.stop_requested.load(Ordering::SeqCst) {
+            warn!("Server was already stopped.");
+            return;
+        }
+        if self.draining.lock().unwrap().replace(reason).is_some() {
+            warn!("Server is already draining.");
+            return;
+        }
+
+        info!("Server draining ({})...", reason.as_str_name());
+        self.accept_waker.wake();
+        for (_, connection) in self.active_clients.lock().unwrap().iter() {
+            connection.drain(Farewell::Shutdown(reason));
+        }
+    }
+
+    /// Whether `begin_drain()` was called, so that no connection is accepted anymore.
+    pub fn is_draining(&self) -> bool {
+        self.draining.lock().unwrap().is_some()
+    }
+
+    /// Shuts the server down gracefully, see `begin_drain()`, then stops it once every
+    /// client was closed. The clients still busy after `grace_period` are notified of the
+    /// shut down and closed right away, as by `stop_with_reason()`.
+    ///
+    /// # Arguments
+    /// - `reason` The cause of the shut down.
+    /// - `grace_period` How long the requests in flight may take to finish.
+    pub fn drain_and_stop(&self, reason: ShutdownReason, grace_period: Duration) {
+        self.begin_drain(reason);
+
+        let deadline = self.clock.now() + time_scale::scale(grace_period);
+        while self.active_client_count() > 0 && self.clock.now() < deadline {
+            self.clock.sleep(time_scale::scale(Duration::from_millis(10)));
+        }
+        let remaining = self.active_client_count();
+        if remaining > 0 {
+            warn!("{} client(s) still busy after the grace period, closing them.", remaining);
+        }
+
+        self.stop_with_reason(reason);
+    }
+
+    /// Send an error to all clients that are still active of the shut down.
+    pub fn notify_clients_of_shutdown(&self) {
+        let reason = self.shutdown_reason().unwrap_or(ShutdownReason::Unspecified);
+
+        // Collected first, so that the clients lock is not held while writing to them.
+        let now = self.clock.now();
+        let notifications: Vec<_> = self
+            .active_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(_, connection)| {
+                // The notification replaces any farewell the worker did not send yet.
+                connection.drain.lock().unwrap().take();
+
+                let duration = now.duration_since(connection.connected_at);
+                let notification = shutdown_message(reason, Some(connection.usage.summary(duration)));
+                match connection.stream.try_clone() {
+                    Ok(stream) => Some((stream, connection.addr, connection.frame_format.clone(), connection.usage.clone(), notification)),
+                    Err(e) => {
+                        warn!("Failed to notify client {}: {}", connection.addr, e);
+                        None
+                    }
+                }
+            })
+            .collect();
 
         // Iterate over the clients that are still running.
-        for mut client in clients.iter() {
-            // Create a server shut down message to the clients.
-            let shutdown_message = ServerMessage {
-                message: Some(server_message::Message::ErrorMessage(ErrorMessage {
-                    content: "Server is shutting down.".to_string(),
-                })),
+        for (stream, addr, frame_format, usage, notification) in notifications {
+            // A worker blocked writing to a client that reads nothing holds the transport,
+            // the client is then closed without notification, which unblocks the worker.
+            let Some(frame_format) = self.lock_frame_format(&frame_format) else {
+                warn!("Client {} is not reading, closing it without notification.", addr);
+                let _ = stream.shutdown(Shutdown::Both);
+                continue;
             };
 
             // Send the message over the network.
-            let payload = shutdown_message.encode_to_vec();
-            if let Err(e) = client.write_all(&payload) {
-                warn!("Failed to notify client: {}", e);
+            match self.notify(&stream, &frame_format, notification) {
+                Ok(written) => usage.record_sent(written),
+                Err(e) => warn!("Failed to notify client {}: {}", addr, e),
+            }
+            drop(frame_format);
+
+            // Close the connection so that a worker blocked on reading from it wakes up.
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+    }
+
+    /// Waits for `NOTIFICATION_WRITE_TIMEOUT` at most for the transport of a connection,
+    /// held by whoever writes to it.
+    fn lock_frame_format<'a>(&self, frame_format: &'a Mutex<FrameFormat>) -> Option<MutexGuard<'a, FrameFormat>> {
+        let deadline = Instant::now() + time_scale::scale(NOTIFICATION_WRITE_TIMEOUT);
+        loop {
+            match frame_format.try_lock() {
+                Ok(frame_format) => return Some(frame_format),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(time_scale::scale(Duration::from_millis(10)));
+                }
+                Err(_) => return None,
             }
         }
     }
 
-    /// Stops the server by setting the `is_running` flag to `false`
+    /// Send `message` to a client outside of its session, through the response
+    /// post-processors like any response, waiting for `NOTIFICATION_WRITE_TIMEOUT` at most.
+    ///
+    /// # Returns
+    /// - Ok    with the number of bytes written.
+    /// - Err   when the message could not be written in time.
+    fn notify(&self, stream: &TcpStream, frame_format: &FrameFormat, mut message: ServerMessage) -> io::Result<usize> {
+        for post_processor in &self.response_post_processors {
+            post_processor(&mut message);
+        }
+        stream.set_write_timeout(Some(time_scale::scale(NOTIFICATION_WRITE_TIMEOUT)))?;
+        frame_format.write(stream, &message.encode_to_vec())
+    }
+
+    /// Stops the server by setting the `is_running` flag to `false`; a server stopped
+    /// before it runs returns from `run()` right away.
     pub fn stop(&self) {
-        if self.is_running.load(Ordering::SeqCst) {
-            // Notify active clients of the shut down.
-            info!("Server stopped, notifying clients...");
-            self.notify_clients_of_shutdown();
+        self.stop_with_reason(ShutdownReason::Requested);
+    }
+
+    /// Stops the server, telling the clients and the logs why.
+    ///
+    /// # Arguments
+    /// - `reason` The cause of the shut down, see `ShutdownReason::exit_code()` for the
+    ///   matching process exit status.
+    pub fn stop_with_reason(&self, reason: ShutdownReason) {
+        let mut shutdown_reason = self.shutdown_reason.lock().unwrap();
+        if !self.stop_requested.swap(true, Ordering::SeqCst) {
+            *shutdown_reason = Some(reason);
+            // Only the first stop waits for this lock, a stop from a background task being
+            // joined must not block.
+            let _stopping = self.stopping.lock().unwrap();
 
-            // Shutdown the server.
+            // Shutdown the server first, so that no worker starts handling a new request
+            // after the clients were told about the shut down.
             self.is_running.store(false, Ordering::SeqCst);
+            self.accept_waker.wake();
+            drop(shutdown_reason);
+            self.shutdown_token().cancel();
+
+            // Notify active clients of the shut down.
+            if reason.is_graceful() {
+                info!("Server stopped ({}), notifying clients...", reason.as_str_name());
+            } else {
+                error!("Server stopped ({}), notifying clients...", reason.as_str_name());
+            }
+            self.notify_clients_of_shutdown();
 
-            // Join all threads in the thread pool.
+            // Join all threads in the thread pool, then the background tasks.
             self.thread_pool.join();
+            let tasks = self.background_tasks.lock().unwrap().stop();
+            background::join(tasks);
 
             info!("Shutdown signal sent.");
         } else {
-            warn!("Server was already stopped or not running.");
+            drop(shutdown_reason);
+            warn!("Server was already stopped.");
+        }
+    }
+}
+
+impl Drop for Server {
+    /// Report the resources that were not released, as they outlive the server silently.
+    fn drop(&mut self) {
+        // Locks may be poisoned while unwinding, and a server that was never stopped nor
+        // connected to has nothing to release yet.
+        if thread::panicking()
+            || (!self.stop_requested.load(Ordering::SeqCst) && self.active_client_count() == 0)
+        {
+            return;
+        }
+
+        for leak in self.leaks() {
+            warn!("Server dropped while {}", leak);
         }
     }
 }
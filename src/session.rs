@@ -0,0 +1,585 @@
+use crate::clock::{self, SharedClock};
+use crate::eval;
+use crate::framing::FrameFormat;
+use crate::id::{SharedIdGenerator, SnowflakeIdGenerator};
+use crate::json;
+use crate::memory::{MemoryBudget, MemoryReservation};
+use crate::message::{self, add_response, client_message, eval_response, server_message, AddError, AddErrorCode, AddRequest, AddResponse, BatchRequest, BatchResponse, ByeMessage, ClientMessage, DelayedEchoRequest, DescriptorRequest, DescriptorResponse, EchoMessage, EvalRequest, EvalResponse, MyStatsRequest, MyStatsResponse, ReconnectHint, ServerMessage, SessionSummary, Transport, UpgradeRequest, UpgradeResponse};
+use crate::server::{error_response, request_name, resource_exhausted_message, ResponsePostProcessor, UnknownMessageHandler, DEFAULT_MAX_ECHO_DELAY, DEPRECATED};
+use crate::trace_context::{TraceContext, TRACEPARENT, TRACESTATE};
+use crate::usage::UsageCounters;
+use log::{debug, error, info, log_enabled, warn, Level};
+use prost::Message;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The warnings of the deprecated requests, keyed by message type name.
+pub(crate) type DeprecatedRequests = Arc<HashMap<String, String>>;
+
+/// The encoded reply to a request, to be framed and written by the transport.
+pub(crate) enum Reply {
+    /// Sent right away.
+    Now(Vec<u8>),
+    /// Sent once `delay` elapsed, unless the server stops meanwhile. The reservation, if
+    /// any, holds the memory of the payload until then.
+    Later { delay: Duration, payload: Vec<u8>, reservation: Option<MemoryReservation> },
+    /// Sent right away, as the last frame of the current transport: the frames after it,
+    /// in both directions, are of `frame_format`.
+    Upgrade { frame_format: FrameFormat, payload: Vec<u8> },
+}
+
+/// The request handling of a connection, independent of how it is read and written, so
+/// that the blocking and the async servers answer alike.
+pub(crate) struct Session {
+    // Set once the client said goodbye or disconnected, the connection must not be read anymore.
+    closed: bool,
+    unknown_message_handler: Option<UnknownMessageHandler>,
+    response_post_processors: Vec<ResponsePostProcessor>,
+    // Fields masked when requests are logged.
+    redacted_fields: Vec<String>,
+    max_echo_delay: Duration,
+    // The delayed echoes are counted in it until sent, and rejected once exhausted.
+    memory_budget: Option<MemoryBudget>,
+    deprecated_requests: DeprecatedRequests,
+    // Time source of the session age and of the delayed echoes.
+    clock: SharedClock,
+    connected_at: Instant,
+    // Shared with the server, which reports the usage of every client.
+    usage: Arc<UsageCounters>,
+    // The bytes read up to the end of the request being handled, counted once handled.
+    received: usize,
+    // The id and the metadata of the request being handled, propagated to its response.
+    request_id: Option<u64>,
+    metadata: HashMap<String, String>,
+    // Names the spans of the traces continued by the requests.
+    id_generator: SharedIdGenerator,
+    // Served along with the descriptors, rendered once by the server.
+    startup_report: Option<Arc<str>>,
+}
+
+impl Session {
+    /// Creates a session handling the requests with the default settings.
+    pub(crate) fn new() -> Self {
+        let clock = clock::system();
+        let connected_at = clock.now();
+        Session {
+            closed: false,
+            unknown_message_handler: None,
+            response_post_processors: Vec::new(),
+            redacted_fields: Vec::new(),
+            max_echo_delay: DEFAULT_MAX_ECHO_DELAY,
+            memory_budget: None,
+            deprecated_requests: DeprecatedRequests::default(),
+            clock,
+            connected_at,
+            usage: Arc::new(UsageCounters::default()),
+            received: 0,
+            request_id: None,
+            metadata: HashMap::new(),
+            id_generator: Arc::new(SnowflakeIdGenerator::default()),
+            startup_report: None,
+        }
+    }
+
+    /// Run `post_processors`, in order, on every response before it is sent.
+    pub(crate) fn with_response_post_processors(mut self, post_processors: Vec<ResponsePostProcessor>) -> Self {
+        self.response_post_processors = post_processors;
+        self
+    }
+
+    /// Mask `redacted_fields` when requests are logged.
+    pub(crate) fn with_redacted_fields(mut self, redacted_fields: Vec<String>) -> Self {
+        self.redacted_fields = redacted_fields;
+        self
+    }
+
+    /// Reject the delayed echoes asking to wait longer than `max_echo_delay`.
+    pub(crate) fn with_max_echo_delay(mut self, max_echo_delay: Duration) -> Self {
+        self.max_echo_delay = max_echo_delay;
+        self
+    }
+
+    /// Serve `startup_report`, rendered as JSON, along with the protocol descriptors.
+    pub(crate) fn with_startup_report(mut self, startup_report: Option<Arc<str>>) -> Self {
+        self.startup_report = startup_report;
+        self
+    }
+
+    /// Hold the delayed echoes in `memory_budget` until sent, rejecting those it cannot hold.
+    pub(crate) fn with_memory_budget(mut self, memory_budget: Option<MemoryBudget>) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    /// Warn the client in the responses to the requests listed in `deprecated_requests`.
+    pub(crate) fn with_deprecated_requests(mut self, deprecated_requests: DeprecatedRequests) -> Self {
+        self.deprecated_requests = deprecated_requests;
+        self
+    }
+
+    /// Measure the session age with `clock`, starting now.
+    pub(crate) fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.connected_at = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    /// Count the usage of the connection in `usage`.
+    pub(crate) fn with_usage(mut self, usage: Arc<UsageCounters>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// Name the spans of the traces continued by the requests with `id_generator`.
+    pub(crate) fn with_id_generator(mut self, id_generator: SharedIdGenerator) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    /// Let `handler` answer the requests that the server does not understand.
+    pub(crate) fn with_unknown_message_handler(mut self, handler: Option<UnknownMessageHandler>) -> Self {
+        self.unknown_message_handler = handler;
+        self
+    }
+
+    /// The time source of the session, which the delayed replies wait on.
+    pub(crate) fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    /// Whether the client closed the session, with a bye message or by disconnecting.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// The usage of the session so far, told to the client when the connection closes.
+    pub(crate) fn summary(&self) -> SessionSummary {
+        self.usage.summary(self.clock.now().duration_since(self.connected_at))
+    }
+
+    /// The counters of the connection, which the writers count the bytes they send in.
+    pub(crate) fn usage(&self) -> &Arc<UsageCounters> {
+        &self.usage
+    }
+
+    /// Counts `bytes` more read from the client, once the next request is handled.
+    pub(crate) fn receive(&mut self, bytes: usize) {
+        self.received += bytes;
+    }
+
+    /// Counts `bytes` written to the client.
+    pub(crate) fn record_sent(&self, bytes: usize) {
+        self.usage.record_sent(bytes);
+    }
+
+    /// Counts `resyncs` more losses of the frame boundaries of the connection.
+    pub(crate) fn record_resyncs(&self, resyncs: u64) {
+        self.usage.record_resyncs(resyncs);
+    }
+
+    /// The goodbye of the server closing the connection on its own, e.g. when the client
+    /// was idle for too long.
+    ///
+    /// # Arguments
+    /// - `reconnect_hint` Where and when the client should reconnect, if anywhere.
+    pub(crate) fn goodbye(&self, reconnect_hint: Option<ReconnectHint>) -> ServerMessage {
+        ServerMessage {
+            message: Some(server_message::Message::ByeMessage(ByeMessage {
+                reconnect_hint,
+                session_summary: Some(self.summary()),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Marks the session closed, e.g. once the client disconnected.
+    pub(crate) fn close(&mut self) {
+        self.closed = true;
+        self.usage.record_received(std::mem::take(&mut self.received));
+    }
+
+    /// Handle a single request according to its type.
+    ///
+    /// # Arguments
+    /// - `request` The payload of the frame received from the client.
+    ///
+    /// # Returns
+    /// The reply to send, already counted in the usage of the connection, except for its
+    /// bytes which the writer counts.
+    pub(crate) fn handle_frame(&mut self, request: &[u8]) -> Reply {
+        // Decode the message to decide on the type of the request.
+        let handled = match ClientMessage::decode(request) {
+            Ok(ClientMessage { message: Some(message), request_id, metadata }) => {
+                self.begin_request(request_id, metadata);
+                self.handle_request(message)
+            }
+            Ok(ClientMessage { message: None, request_id, metadata }) => {
+                self.begin_request(request_id, metadata);
+                // In case the received request was not identified, this will execute.
+                error!("Bad Request!");
+                Handled::Now(self.handle_bad_request(request))
+            }
+            Err(_) => {
+                self.request_id = None;
+                self.metadata.clear();
+                // Executes when the decoding of the message fails.
+                error!("Failed to decode message");
+                Handled::Now(self.handle_bad_request(request))
+            }
+        };
+        let reply = match handled {
+            Handled::Now(mut response) => {
+                self.propagate_metadata(&mut response);
+                Reply::Now(self.encode_response(response))
+            }
+            Handled::Later(delay, mut response, reservation) => {
+                self.propagate_metadata(&mut response);
+                Reply::Later { delay, payload: self.encode_response(response), reservation }
+            }
+            Handled::Upgrade(frame_format, mut response) => {
+                self.propagate_metadata(&mut response);
+                Reply::Upgrade { frame_format, payload: self.encode_response(response) }
+            }
+        };
+        // Counted once handled, so that a stats request does not report itself.
+        self.usage.record_received(std::mem::take(&mut self.received));
+
+        reply
+    }
+
+    /// Post-process and encode a response, counting it in the usage of the connection; its
+    /// bytes are counted by the writer, once framed.
+    ///
+    /// # Arguments
+    /// - `response` The server message to send to the client.
+    ///
+    /// # Returns
+    /// The payload of the frame to write.
+    pub(crate) fn encode_response(&self, mut response: ServerMessage) -> Vec<u8> {
+        self.post_process(&mut response);
+        let payload = response.encode_to_vec();
+        // Counted before the client can see the response, so that the usage it is reported
+        // next includes it.
+        let is_error = matches!(response.message, Some(server_message::Message::ErrorMessage(_)));
+        self.usage.record_response(is_error);
+        payload
+    }
+
+    /// Handle a decoded request according to its type.
+    fn handle_request(&mut self, message: client_message::Message) -> Handled {
+        if log_enabled!(Level::Debug) {
+            match json::to_redacted_json(&message, &self.redacted_fields) {
+                Ok(request) => debug!("Request: {}", request),
+                Err(e) => debug!("Request could not be rendered: {}", e),
+            }
+        }
+
+        // The warning is propagated to the response along with the request metadata.
+        let name = request_name(&message);
+        if let Some(warning) = self.deprecated_requests.get(name) {
+            warn!("Client sent the deprecated {}", name);
+            self.metadata.insert(DEPRECATED.to_string(), warning.clone());
+        }
+
+        let response = match message {
+            client_message::Message::EchoMessage(echo_message) => self.handle_echo_request(echo_message),
+            client_message::Message::AddRequest(add_request) => self.handle_add_request(add_request),
+            client_message::Message::ByeMessage(bye_message) => self.handle_bye_request(bye_message),
+            client_message::Message::MyStatsRequest(my_stats_request) => {
+                self.handle_my_stats_request(my_stats_request)
+            }
+            client_message::Message::DelayedEchoRequest(delayed_echo_request) => {
+                return self.handle_delayed_echo_request(delayed_echo_request);
+            }
+            client_message::Message::DescriptorRequest(descriptor_request) => {
+                self.handle_descriptor_request(descriptor_request)
+            }
+            client_message::Message::BatchRequest(batch_request) => self.handle_batch_request(batch_request),
+            client_message::Message::EvalRequest(eval_request) => self.handle_eval_request(eval_request),
+            client_message::Message::UpgradeRequest(upgrade_request) => {
+                return self.handle_upgrade_request(upgrade_request);
+            }
+        };
+        Handled::Now(response)
+    }
+
+    /// Handle echo requests by echoing back the same message.
+    ///
+    /// # Arguments
+    /// - `echo_message` The message received from the client.
+    fn handle_echo_request(&mut self, echo_message: EchoMessage) -> ServerMessage {
+        // If the received request was simply an echo request, send the message back
+        info!("Received Echo Request");
+
+        // Create the response
+        ServerMessage {
+            message: Some(server_message::Message::EchoMessage(echo_message)),
+            ..Default::default()
+        }
+    }
+
+    /// Handle the add requests by adding the two integers within the request then sending the result.
+    ///
+    /// # Arguments
+    /// - `add_request` The client request containing the two integers to be added.
+    fn handle_add_request(&mut self, add_request: AddRequest) -> ServerMessage {
+        // If the received request is an add request, perform the operation.
+        info!("Received Add Request: {} + {}", add_request.a, add_request.b);
+
+        // Perform the request, reporting overflows rather than wrapping around.
+        let outcome = match add_request.a.checked_add(add_request.b) {
+            Some(result) => add_response::Outcome::Result(result),
+            None => add_response::Outcome::Error(AddError {
+                code: AddErrorCode::Overflow.into(),
+                detail: format!("{} + {} overflows int32", add_request.a, add_request.b),
+            }),
+        };
+        let add_response = AddResponse {
+            outcome: Some(outcome)
+        };
+
+        // Create the response.
+        ServerMessage {
+            message: Some(server_message::Message::AddResponse(add_response)),
+            ..Default::default()
+        }
+    }
+
+    /// Handle eval requests by evaluating the expression with the given variables.
+    ///
+    /// # Arguments
+    /// - `eval_request` The client request containing the expression and its variables.
+    fn handle_eval_request(&mut self, eval_request: EvalRequest) -> ServerMessage {
+        info!("Received Eval Request of {} variables", eval_request.vars.len());
+
+        let outcome = match eval::evaluate(&eval_request.expression, &eval_request.vars) {
+            Ok(result) => eval_response::Outcome::Result(result),
+            Err(error) => eval_response::Outcome::Error(error),
+        };
+
+        ServerMessage {
+            message: Some(server_message::Message::EvalResponse(EvalResponse {
+                outcome: Some(outcome),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Handle a bye request by acknowledging it, after which the connection is closed.
+    ///
+    /// # Arguments
+    /// - `bye_message` The message received from the client.
+    fn handle_bye_request(&mut self, bye_message: ByeMessage) -> ServerMessage {
+        info!("Received Bye Request");
+        self.closed = true;
+
+        // The delayed echoes not sent yet are dropped by the server before the
+        // acknowledgement is written, so that nothing else follows it.
+        ServerMessage {
+            message: Some(server_message::Message::ByeMessage(ByeMessage {
+                session_summary: Some(self.summary()),
+                ..bye_message
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Handle delayed echo requests by echoing back the content once the delay elapsed.
+    ///
+    /// The transport sends the response later on, and keeps serving the connection
+    /// meanwhile. Responses to later requests may thus arrive first. The response is
+    /// rejected with a `RESOURCE_EXHAUSTED` error when the memory budget cannot hold it.
+    ///
+    /// # Arguments
+    /// - `delayed_echo_request` The message received from the client.
+    fn handle_delayed_echo_request(&mut self, delayed_echo_request: DelayedEchoRequest) -> Handled {
+        info!("Received Delayed Echo Request after {} ms", delayed_echo_request.delay_ms);
+
+        let delay = Duration::from_millis(delayed_echo_request.delay_ms.into());
+        if delay > self.max_echo_delay {
+            return Handled::Now(error_response(format!("Delay exceeds {} ms", self.max_echo_delay.as_millis())));
+        }
+
+        let response = ServerMessage {
+            message: Some(server_message::Message::EchoMessage(EchoMessage {
+                content: delayed_echo_request.content,
+            })),
+            ..Default::default()
+        };
+        let reservation = match &self.memory_budget {
+            Some(memory_budget) => match memory_budget.reserve(response.encoded_len() as u64) {
+                Some(reservation) => Some(reservation),
+                None => {
+                    warn!("Rejected a delayed echo, {} of {} bytes are held", memory_budget.used(), memory_budget.limit());
+                    return Handled::Now(resource_exhausted_message());
+                }
+            },
+            None => None,
+        };
+        Handled::Later(delay, response, reservation)
+    }
+
+    /// Handle upgrade requests by switching the connection to the requested transport,
+    /// right after the frame of the request and of its response.
+    ///
+    /// # Arguments
+    /// - `upgrade_request` The message received from the client.
+    fn handle_upgrade_request(&mut self, upgrade_request: UpgradeRequest) -> Handled {
+        let Ok(transport) = Transport::try_from(upgrade_request.transport) else {
+            return Handled::Now(error_response(format!("Unsupported transport {}", upgrade_request.transport)));
+        };
+        info!("Upgrading the connection to {}", transport.as_str_name());
+
+        Handled::Upgrade(
+            transport.into(),
+            ServerMessage {
+                message: Some(server_message::Message::UpgradeResponse(UpgradeResponse {
+                    transport: transport.into(),
+                })),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Handle descriptor requests by sending the schema of the protocol, so that generic
+    /// tools can decode the traffic.
+    ///
+    /// # Arguments
+    /// - `_descriptor_request` The message received from the client.
+    fn handle_descriptor_request(&mut self, _descriptor_request: DescriptorRequest) -> ServerMessage {
+        info!("Received Descriptor Request");
+
+        let descriptor_response = DescriptorResponse {
+            file_descriptor_set: message::FILE_DESCRIPTOR_SET.to_vec(),
+            startup_report: self.startup_report.as_deref().unwrap_or_default().to_string(),
+        };
+
+        ServerMessage {
+            message: Some(server_message::Message::DescriptorResponse(descriptor_response)),
+            ..Default::default()
+        }
+    }
+
+    /// Handle a stats request by reporting the counters of this connection, so that the
+    /// client can diagnose its own usage.
+    ///
+    /// # Arguments
+    /// - `_my_stats_request` The message received from the client.
+    fn handle_my_stats_request(&mut self, _my_stats_request: MyStatsRequest) -> ServerMessage {
+        info!("Received My Stats Request");
+
+        let my_stats_response = MyStatsResponse {
+            requests_served: self.usage.requests(),
+            bytes_received: self.usage.bytes_received(),
+            bytes_sent: self.usage.bytes_sent(),
+            session_age_ms: self.clock.now().duration_since(self.connected_at).as_millis() as u64,
+        };
+
+        ServerMessage {
+            message: Some(server_message::Message::MyStatsResponse(my_stats_response)),
+            ..Default::default()
+        }
+    }
+
+    /// Handle batch requests by answering each request in order, so that clients save
+    /// round trips.
+    ///
+    /// Requests that close the connection or answer later cannot be batched, and get an
+    /// error message in the batch instead.
+    ///
+    /// # Arguments
+    /// - `batch_request` The requests received from the client.
+    fn handle_batch_request(&mut self, batch_request: BatchRequest) -> ServerMessage {
+        info!("Received Batch Request of {} requests", batch_request.requests.len());
+
+        // Each batched request carries its own id and metadata.
+        let batch_request_id = self.request_id.take();
+        let batch_metadata = std::mem::take(&mut self.metadata);
+        let mut responses = Vec::with_capacity(batch_request.requests.len());
+        for request in batch_request.requests {
+            self.begin_request(request.request_id, request.metadata.clone());
+            let mut response = match request.message {
+                Some(client_message::Message::ByeMessage(_))
+                | Some(client_message::Message::DelayedEchoRequest(_))
+                | Some(client_message::Message::BatchRequest(_))
+                | Some(client_message::Message::UpgradeRequest(_)) => {
+                    error_response("Request cannot be batched".to_string())
+                }
+                Some(message) => match self.handle_request(message) {
+                    Handled::Now(response) => response,
+                    Handled::Later(..) | Handled::Upgrade(..) => {
+                        unreachable!("Batched requests are answered at once")
+                    }
+                },
+                None => self.handle_bad_request(&request.encode_to_vec()),
+            };
+            self.propagate_metadata(&mut response);
+            responses.push(response);
+        }
+        self.request_id = batch_request_id;
+        self.metadata = batch_metadata;
+
+        ServerMessage {
+            message: Some(server_message::Message::BatchResponse(BatchResponse { responses })),
+            ..Default::default()
+        }
+    }
+
+    /// Handle a bad request sent by the client, giving the unknown message handler a
+    /// chance to answer it first.
+    ///
+    /// # Arguments
+    /// - `request` The raw bytes received from the client.
+    fn handle_bad_request(&mut self, request: &[u8]) -> ServerMessage {
+        self.unknown_message_handler.as_ref()
+            .and_then(|handler| handler(request))
+            .unwrap_or_else(|| error_response("Bad Request!".to_string()))
+    }
+
+    /// Take the id and the metadata of a new request, continuing its trace, if any, in a span of the
+    /// server so that the response links back to it.
+    fn begin_request(&mut self, request_id: Option<u64>, mut metadata: HashMap<String, String>) {
+        match TraceContext::from_metadata(&metadata) {
+            Some(trace) => {
+                let span = trace.child_with_id(self.id_generator.next_id());
+                debug!("Request joins trace {:032x} in span {:016x}", span.trace_id, span.parent_id);
+                metadata.insert(TRACEPARENT.to_string(), span.to_string());
+            }
+            None => {
+                // An invalid trace context is ignored rather than propagated.
+                if metadata.remove(TRACEPARENT).is_some() {
+                    debug!("Ignoring an invalid trace context");
+                }
+                metadata.remove(TRACESTATE);
+            }
+        }
+        self.request_id = request_id;
+        self.metadata = metadata;
+    }
+
+    /// Copy the id and the metadata of the request to `response`, except for what its
+    /// handler set.
+    fn propagate_metadata(&self, response: &mut ServerMessage) {
+        response.request_id = response.request_id.or(self.request_id);
+        for (key, value) in &self.metadata {
+            response.metadata.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Run the response post-processors on `response`.
+    fn post_process(&self, response: &mut ServerMessage) {
+        for post_processor in &self.response_post_processors {
+            post_processor(response);
+        }
+    }
+}
+
+/// The response to a request, before it is encoded.
+enum Handled {
+    Now(ServerMessage),
+    Later(Duration, ServerMessage, Option<MemoryReservation>),
+    Upgrade(FrameFormat, ServerMessage),
+}
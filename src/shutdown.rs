@@ -0,0 +1,96 @@
+use crate::clock::Clock;
+use crate::message::ShutdownReason;
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+// How often `ShutdownToken::sleep()` checks for the cancellation.
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+impl ShutdownReason {
+    /// The process exit status matching the reason, following the `sysexits.h` codes
+    /// so that orchestration systems can tell crashes from operator-initiated stops.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ShutdownReason::Unspecified
+            | ShutdownReason::Requested
+            | ShutdownReason::Signal
+            | ShutdownReason::AdminCommand => 0,
+            // EX_OSERR
+            ShutdownReason::FatalAcceptError => 71,
+            // EX_CONFIG
+            ShutdownReason::ConfigError => 78,
+        }
+    }
+
+    /// Whether the shut down was asked for, rather than caused by a failure.
+    pub fn is_graceful(self) -> bool {
+        self.exit_code() == 0
+    }
+}
+
+/// Tells long-running work that the server is stopping.
+///
+/// Cloning the token is cheap, and every clone observes the same cancellation.
+#[derive(Clone, Default)]
+pub struct ShutdownToken {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl ShutdownToken {
+    /// Creates a token that is not cancelled yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the server is stopping.
+    pub fn is_cancelled(&self) -> bool {
+        *self.state.0.lock().unwrap()
+    }
+
+    /// Blocks the calling thread until the token is cancelled.
+    pub fn wait(&self) {
+        let (cancelled, condvar) = &*self.state;
+        let _cancelled = condvar.wait_while(cancelled.lock().unwrap(), |cancelled| !*cancelled).unwrap();
+    }
+
+    /// Blocks the calling thread until the token is cancelled, or `timeout` elapsed.
+    ///
+    /// # Returns
+    /// - true  when the token was cancelled.
+    /// - false when the timeout elapsed first.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (cancelled, condvar) = &*self.state;
+        let (cancelled, _) = condvar
+            .wait_timeout_while(cancelled.lock().unwrap(), timeout, |cancelled| !*cancelled)
+            .unwrap();
+        *cancelled
+    }
+
+    /// Sleeps on `clock` for `duration`, returning early once the token is cancelled.
+    ///
+    /// # Returns
+    /// - true  when the token was cancelled.
+    /// - false when the duration elapsed first.
+    pub fn sleep(&self, clock: &dyn Clock, duration: Duration) -> bool {
+        let deadline = clock.now() + duration;
+        loop {
+            if self.is_cancelled() {
+                return true;
+            }
+            let now = clock.now();
+            if now >= deadline {
+                return false;
+            }
+            clock.sleep((deadline - now).min(SLEEP_SLICE));
+        }
+    }
+
+    /// Cancels the token, waking every waiting thread.
+    pub fn cancel(&self) {
+        let (cancelled, condvar) = &*self.state;
+        *cancelled.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+}
@@ -0,0 +1,98 @@
+/// A slot of the slab, either holding a value or linking to the next free slot.
+enum Entry<T> {
+    Occupied(T),
+    Vacant(usize),
+}
+
+/// Stores values in a vector and hands out their index as a key.
+///
+/// Removed slots are chained in a free list and reused by later insertions,
+/// so both insertion and removal are O(1) and keys stay small integers.
+pub struct Slab<T> {
+    entries: Vec<Entry<T>>,
+    // Index of the first vacant slot, equal to `entries.len()` when there is none.
+    next_free: usize,
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    /// Creates an empty slab.
+    pub fn new() -> Self {
+        Slab {
+            entries: Vec::new(),
+            next_free: 0,
+            len: 0,
+        }
+    }
+
+    /// Stores a value in the slab.
+    ///
+    /// # Returns
+    /// - The key under which the value is stored.
+    pub fn insert(&mut self, value: T) -> usize {
+        let key = self.next_free;
+        if key == self.entries.len() {
+            self.entries.push(Entry::Occupied(value));
+            self.next_free = self.entries.len();
+        } else {
+            match std::mem::replace(&mut self.entries[key], Entry::Occupied(value)) {
+                Entry::Vacant(next) => self.next_free = next,
+                Entry::Occupied(_) => unreachable!("free list points to an occupied slot"),
+            }
+        }
+        self.len += 1;
+        key
+    }
+
+    /// Removes the value stored under `key`.
+    ///
+    /// # Returns
+    /// - Some  with the removed value.
+    /// - None  when nothing is stored under `key`.
+    pub fn remove(&mut self, key: usize) -> Option<T> {
+        match self.entries.get(key) {
+            Some(Entry::Occupied(_)) => {}
+            _ => return None,
+        }
+
+        let entry = std::mem::replace(&mut self.entries[key], Entry::Vacant(self.next_free));
+        self.next_free = key;
+        self.len -= 1;
+        match entry {
+            Entry::Occupied(value) => Some(value),
+            Entry::Vacant(_) => unreachable!("checked above"),
+        }
+    }
+
+    /// A mutable reference to the value stored under `key`, if any.
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut T> {
+        match self.entries.get_mut(key) {
+            Some(Entry::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The number of values stored in the slab.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the slab holds no value.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over the stored values along with their keys.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.entries.iter().enumerate().filter_map(|(key, entry)| match entry {
+            Entry::Occupied(value) => Some((key, value)),
+            Entry::Vacant(_) => None,
+        })
+    }
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Serialize;
+use std::net::SocketAddr;
+
+/// The version of the crate the server was built from.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The commit the server was built from, "unknown" when built outside of a git checkout.
+pub const GIT_HASH: &str = env!("GIT_HASH");
+
+/// How the messages are encoded on the wire.
+pub const CODEC: &str = "protobuf, u32 length-prefixed";
+
+/// The cargo features the server was built with.
+pub fn enabled_features() -> Vec<String> {
+    let mut features = Vec::new();
+    if cfg!(feature = "time-compression") {
+        features.push("time-compression".to_string());
+    }
+    features
+}
+
+/// The limits a server was configured with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Limits {
+    pub workers: usize,
+    /// None when only the file descriptors limit the connections.
+    pub max_connections: Option<usize>,
+    pub read_buffer_size: usize,
+    pub max_frame_len: usize,
+    /// None when idle connections are kept open.
+    pub idle_timeout_ms: Option<u64>,
+    pub max_echo_delay_ms: u64,
+    /// The soft limit of file descriptors, None when unknown on this platform.
+    pub fd_limit: Option<u64>,
+}
+
+/// What build and configuration a server runs, logged as a single record when it starts
+/// so that support can tell exactly what a field unit is running.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupReport {
+    pub version: String,
+    pub git_hash: String,
+    pub features: Vec<String>,
+    pub bind_addr: SocketAddr,
+    pub limits: Limits,
+    pub codec: String,
+    /// Whether the connections are encrypted.
+    pub tls: bool,
+}
+
+impl StartupReport {
+    /// Creates a report of this build for a server listening on `bind_addr` with `limits`.
+    pub fn new(bind_addr: SocketAddr, limits: Limits) -> Self {
+        StartupReport {
+            version: VERSION.to_string(),
+            git_hash: GIT_HASH.to_string(),
+            features: enabled_features(),
+            bind_addr,
+            limits,
+            codec: CODEC.to_string(),
+            // The connections are plain TCP.
+            tls: false,
+        }
+    }
+
+    /// Renders the report as a single line of JSON.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
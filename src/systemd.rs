@@ -0,0 +1,102 @@
+use crate::clock::SharedClock;
+use log::{info, warn};
+use std::{
+    env,
+    io,
+    os::unix::net::UnixDatagram,
+    time::{Duration, Instant},
+};
+
+/// Sends a state update to the service manager, as `sd_notify()` does.
+///
+/// # Arguments
+/// - `state` The newline separated assignments to send, e.g. `READY=1`.
+///
+/// # Returns
+/// - Ok(true)  when the update was sent.
+/// - Ok(false) when the process does not run under systemd (`NOTIFY_SOCKET` is unset).
+/// - Err       when the notification socket could not be reached.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) if !path.is_empty() => path,
+        _ => return Ok(false),
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    if let Some(name) = path.strip_prefix('@') {
+        // Abstract socket namespace, only available on Linux.
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Abstract sockets are not supported"));
+        }
+    } else {
+        socket.send_to(state.as_bytes(), path.as_ref())?;
+    }
+    Ok(true)
+}
+
+/// Sends the periodic `WATCHDOG=1` keep-alive expected by systemd when `WatchdogSec=`
+/// is configured for the service.
+pub struct Watchdog {
+    // Half of the watchdog timeout, as recommended by `sd_watchdog_enabled()`.
+    interval: Option<Duration>,
+    last_ping: Instant,
+    clock: SharedClock,
+}
+
+impl Watchdog {
+    /// Creates a watchdog from the `WATCHDOG_USEC` variable set by systemd. It does
+    /// nothing when the variable is missing.
+    ///
+    /// # Arguments
+    /// - `clock` The clock measuring the time between two pings.
+    pub fn from_env(clock: SharedClock) -> Self {
+        let timeout = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0)
+            .map(Duration::from_micros);
+        Self::new(timeout, clock)
+    }
+
+    /// Creates a watchdog for the given timeout, or a disabled one for `None`.
+    pub fn new(timeout: Option<Duration>, clock: SharedClock) -> Self {
+        let interval = timeout.map(|timeout| timeout / 2);
+        if let Some(interval) = interval {
+            info!("Systemd watchdog enabled, pinging every {:?}", interval);
+        }
+
+        Watchdog {
+            interval,
+            last_ping: clock.now(),
+            clock,
+        }
+    }
+
+    /// Whether systemd expects pings, so that the monitored loop must wake up for them.
+    pub fn is_enabled(&self) -> bool {
+        self.interval.is_some()
+    }
+
+    /// Pings the service manager if the interval elapsed since the last ping. Must be
+    /// called from the loop whose liveness is being monitored.
+    pub fn tick(&mut self) {
+        if let Some(interval) = self.interval {
+            let now = self.clock.now();
+            if now.duration_since(self.last_ping) >= interval {
+                if let Err(e) = notify("WATCHDOG=1") {
+                    warn!("Failed to ping the systemd watchdog: {}", e);
+                }
+                self.last_ping = now;
+            }
+        }
+    }
+}
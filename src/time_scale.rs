@@ -0,0 +1,41 @@
+//! Compression of the internal durations, for tests only.
+//!
+//! With the `time-compression` feature, every delay the crate waits for (delayed echoes,
+//! accept and bind backoffs, report intervals, polling periods) is divided by a factor
+//! set at runtime, so that scenarios lasting seconds or hours run in milliseconds.
+//! Without the feature, `scale()` returns the duration as is and costs nothing.
+
+use std::time::Duration;
+
+#[cfg(feature = "time-compression")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// The bits of the `f64` factor, as there is no atomic float.
+#[cfg(feature = "time-compression")]
+static FACTOR: AtomicU64 = AtomicU64::new(1.0f64.to_bits());
+
+/// Divides every internal duration by `factor` from now on, `1.0` restoring real time.
+///
+/// # Panics
+/// When `factor` is not a positive, finite, number.
+#[cfg(feature = "time-compression")]
+pub fn set_factor(factor: f64) {
+    assert!(factor.is_finite() && factor > 0.0, "Invalid time compression factor {}", factor);
+    FACTOR.store(factor.to_bits(), Ordering::Relaxed);
+}
+
+/// The current compression factor.
+pub fn factor() -> f64 {
+    #[cfg(feature = "time-compression")]
+    return f64::from_bits(FACTOR.load(Ordering::Relaxed));
+    #[cfg(not(feature = "time-compression"))]
+    1.0
+}
+
+/// The time actually waited for `duration`.
+pub fn scale(duration: Duration) -> Duration {
+    #[cfg(feature = "time-compression")]
+    return duration.div_f64(factor());
+    #[cfg(not(feature = "time-compression"))]
+    duration
+}
@@ -0,0 +1,183 @@
+use crate::clock::SharedClock;
+use crate::shutdown::ShutdownToken;
+use log::debug;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// How often the timer thread checks the clock while entries are pending, as a
+// `ManualClock` does not wake it up when advanced.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Fires values once their delay elapsed, on a single thread however many are pending.
+///
+/// The values are fired in the order of their deadlines, and dropped once the shutdown
+/// token is cancelled.
+pub(crate) struct TimerQueue<T: Send + 'static> {
+    state: Arc<(Mutex<State<T>>, Condvar)>,
+    clock: SharedClock,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+struct State<T> {
+    entries: BinaryHeap<Reverse<Entry<T>>>,
+    // Breaks the ties between entries of the same deadline, in scheduling order.
+    sequence: u64,
+    // Whether a value is being fired, outside the lock.
+    firing: bool,
+    // Fire what is pending, then stop.
+    finishing: bool,
+    // Stop now, dropping what is pending.
+    stopped: bool,
+}
+
+struct Entry<T> {
+    deadline: Instant,
+    sequence: u64,
+    value: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deadline, self.sequence).cmp(&(other.deadline, other.sequence))
+    }
+}
+
+impl<T: Send + 'static> TimerQueue<T> {
+    /// Creates a queue and the thread firing its values.
+    ///
+    /// # Arguments
+    /// - `clock`           The clock the delays elapse on.
+    /// - `shutdown_token`  Cancelled when the pending values must be dropped.
+    /// - `fire`            Called with every value once its delay elapsed.
+    pub(crate) fn new(clock: SharedClock, shutdown_token: ShutdownToken, mut fire: impl FnMut(T) + Send + 'static) -> Self {
+        let state = Arc::new((
+            Mutex::new(State {
+                entries: BinaryHeap::new(),
+                sequence: 0,
+                firing: false,
+                finishing: false,
+                stopped: false,
+            }),
+            Condvar::new(),
+        ));
+
+        let thread = {
+            let state = state.clone();
+            let clock = clock.clone();
+            thread::spawn(move || {
+                let (lock, condvar) = &*state;
+                let mut guard = lock.lock().unwrap();
+                loop {
+                    if guard.stopped {
+                        break;
+                    }
+                    if shutdown_token.is_cancelled() {
+                        if !guard.entries.is_empty() {
+                            debug!("Dropped {} timer(s) on shut down", guard.entries.len());
+                            guard.entries.clear();
+                        }
+                        break;
+                    }
+                    let Some(Reverse(next)) = guard.entries.peek() else {
+                        if guard.finishing {
+                            break;
+                        }
+                        guard = condvar.wait(guard).unwrap();
+                        continue;
+                    };
+                    if clock.now() < next.deadline {
+                        guard = condvar.wait_timeout(guard, POLL_INTERVAL).unwrap().0;
+                        continue;
+                    }
+
+                    let Reverse(entry) = guard.entries.pop().unwrap();
+                    guard.firing = true;
+                    drop(guard);
+                    fire(entry.value);
+                    guard = lock.lock().unwrap();
+                    guard.firing = false;
+                    condvar.notify_all();
+                }
+            })
+        };
+
+        TimerQueue {
+            state,
+            clock,
+            thread: Some(thread),
+        }
+    }
+
+    /// Fires `value` once `delay` elapsed.
+    pub(crate) fn schedule(&self, delay: Duration, value: T) {
+        let deadline = self.clock.now() + delay;
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        state.sequence += 1;
+        let sequence = state.sequence;
+        state.entries.push(Reverse(Entry { deadline, sequence, value }));
+        condvar.notify_all();
+    }
+
+    /// The number of values not fired yet, including the one being fired if any.
+    pub(crate) fn pending(&self) -> usize {
+        let state = self.state.0.lock().unwrap();
+        state.entries.len() + usize::from(state.firing)
+    }
+
+    /// Drops the pending values, waiting for the one being fired if any.
+    ///
+    /// # Returns
+    /// The number of values dropped.
+    pub(crate) fn cancel(&self) -> usize {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let cancelled = state.entries.len();
+        state.entries.clear();
+        let _state = condvar.wait_while(state, |state| state.firing).unwrap();
+        cancelled
+    }
+
+    /// Waits for every pending value to be fired, or dropped on shut down, then stops
+    /// the thread.
+    pub(crate) fn finish(mut self) {
+        let (lock, condvar) = &*self.state;
+        lock.lock().unwrap().finishing = true;
+        condvar.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for TimerQueue<T> {
+    /// Stops the thread, dropping the pending values.
+    fn drop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        let (lock, condvar) = &*self.state;
+        lock.lock().unwrap().stopped = true;
+        condvar.notify_all();
+        let _ = thread.join();
+    }
+}
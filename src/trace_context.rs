@@ -0,0 +1,103 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The metadata key of the W3C trace context.
+pub const TRACEPARENT: &str = "traceparent";
+/// The metadata key of the vendor-specific trace state, propagated as is.
+pub const TRACESTATE: &str = "tracestate";
+
+// The only flag defined by version 00.
+const SAMPLED: u8 = 0x01;
+
+/// A W3C trace context (`traceparent`), linking a request to a distributed trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    /// The id of the span of the caller.
+    pub parent_id: u64,
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// Parses a `traceparent` value, such as
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    ///
+    /// # Returns
+    /// - Some  with the context, including for future versions with extra fields.
+    /// - None  when the value is invalid, in which case the trace must be ignored.
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut fields = traceparent.splitn(5, '-');
+        let version = fields.next().filter(|field| field.len() == 2).and_then(parse_hex)?;
+        let trace_id = fields.next().filter(|field| field.len() == 32).and_then(parse_hex)?;
+        let parent_id = fields.next().filter(|field| field.len() == 16).and_then(parse_hex)?;
+        let flags = fields.next().filter(|field| field.len() == 2).and_then(parse_hex)?;
+
+        // Version 00 has exactly four fields, later versions may append more.
+        let has_more = fields.next().is_some();
+        if version == 0xff || (version == 0 && has_more) || trace_id == 0 || parent_id == 0 {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id,
+            parent_id: parent_id as u64,
+            flags: flags as u8,
+        })
+    }
+
+    /// Reads the trace context from the `traceparent` entry of `metadata`.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Option<Self> {
+        metadata.get(TRACEPARENT).and_then(|traceparent| Self::parse(traceparent))
+    }
+
+    /// A context for a new span in the same trace, whose parent is this one.
+    pub fn child(&self) -> Self {
+        self.child_with_id(new_span_id())
+    }
+
+    /// A context for the new span `span_id` in the same trace, e.g. with an id from the
+    /// `IdGenerator` of the server. The id must not be zero.
+    pub fn child_with_id(&self, span_id: u64) -> Self {
+        TraceContext {
+            parent_id: span_id,
+            ..*self
+        }
+    }
+
+    /// Whether the caller may have recorded the trace.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+}
+
+impl fmt::Display for TraceContext {
+    /// Formats the context as a version 00 `traceparent` value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{:032x}-{:016x}-{:02x}", self.trace_id, self.parent_id, self.flags)
+    }
+}
+
+/// Parses lowercase hexadecimal, as upper case is invalid in a `traceparent`.
+fn parse_hex(field: &str) -> Option<u128> {
+    if !field.bytes().all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte)) {
+        return None;
+    }
+    u128::from_str_radix(field, 16).ok()
+}
+
+/// Picks a random, non-zero, span id.
+fn new_span_id() -> u64 {
+    // Hashing a counter with the randomly keyed std hasher avoids a dependency on an RNG.
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let span_id = hasher.finish();
+        if span_id != 0 {
+            return span_id;
+        }
+    }
+}
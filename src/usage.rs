@@ -0,0 +1,230 @@
+use crate::message::SessionSummary;
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Called with every scheduled usage report.
+pub type UsageReportCallback = Arc<dyn Fn(&UsageReport) + Send + Sync>;
+
+/// Where the scheduled usage reports go.
+#[derive(Clone)]
+pub enum UsageReportSink {
+    /// Appended to a CSV file, with a header line when the file is created.
+    Csv(PathBuf),
+    /// Appended to a file as JSON lines, one report per line.
+    Json(PathBuf),
+    /// Handed to the application.
+    Callback(UsageReportCallback),
+}
+
+/// The counters of a connection, updated by its worker and read by the reports.
+///
+/// Each connection has its own counters, so the workers never contend on them: the
+/// server-wide totals are only summed up when read, see `UsageTotals`.
+#[derive(Debug, Default)]
+pub struct UsageCounters {
+    requests: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    errors: AtomicU64,
+    resyncs: AtomicU64,
+}
+
+impl UsageCounters {
+    /// Counts a response, and whether it was an error.
+    pub(crate) fn record_response(&self, error: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts `bytes` written to the client, framing included.
+    pub(crate) fn record_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts `bytes` read from the client, framing included.
+    pub(crate) fn record_received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts `resyncs` more losses of the frame boundaries, see `FrameFormat::sync_marker`.
+    pub(crate) fn record_resyncs(&self, resyncs: u64) {
+        self.resyncs.fetch_add(resyncs, Ordering::Relaxed);
+    }
+
+    /// The number of responses sent.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes read from the client.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes sent to the client.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// The number of error messages sent.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// The number of times corrupted bytes were skipped up to the next frame.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs.load(Ordering::Relaxed)
+    }
+
+    /// Adds the counts of `other`, e.g. of a connection that closed.
+    pub(crate) fn absorb(&self, other: &UsageCounters) {
+        self.requests.fetch_add(other.requests(), Ordering::Relaxed);
+        self.bytes_received.fetch_add(other.bytes_received(), Ordering::Relaxed);
+        self.bytes_sent.fetch_add(other.bytes_sent(), Ordering::Relaxed);
+        self.errors.fetch_add(other.errors(), Ordering::Relaxed);
+        self.resyncs.fetch_add(other.resyncs(), Ordering::Relaxed);
+    }
+
+    /// The summary told to the client when its connection of `duration` closes.
+    pub(crate) fn summary(&self, duration: Duration) -> SessionSummary {
+        SessionSummary {
+            requests_served: self.requests(),
+            bytes_received: self.bytes_received(),
+            bytes_sent: self.bytes_sent(),
+            errors: self.errors(),
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+
+    /// The current values of the counters of the client at `addr`.
+    pub(crate) fn snapshot(&self, addr: SocketAddr, connected: bool) -> ClientUsage {
+        ClientUsage {
+            addr,
+            connected,
+            requests: self.requests(),
+            bytes_received: self.bytes_received(),
+            bytes_sent: self.bytes_sent(),
+            errors: self.errors(),
+        }
+    }
+}
+
+/// The usage of every client since the server was created, summed up from the counters
+/// of the connections when read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub errors: u64,
+    pub resyncs: u64,
+}
+
+impl UsageTotals {
+    /// Adds the current values of `counters`.
+    pub(crate) fn add(&mut self, counters: &UsageCounters) {
+        self.requests += counters.requests();
+        self.bytes_received += counters.bytes_received();
+        self.bytes_sent += counters.bytes_sent();
+        self.errors += counters.errors();
+        self.resyncs += counters.resyncs();
+    }
+}
+
+/// The usage of a client over its whole session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientUsage {
+    pub addr: SocketAddr,
+    /// Whether the client is still connected, disconnected clients are only reported once.
+    pub connected: bool,
+    pub requests: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub errors: u64,
+}
+
+/// The usage of the clients connected at the time of the report, or disconnected since
+/// the previous one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsageReport {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub clients: Vec<ClientUsage>,
+}
+
+impl UsageReport {
+    /// The header line of the CSV rendering.
+    pub const CSV_HEADER: &'static str = "timestamp,addr,connected,requests,bytes_received,bytes_sent,errors\n";
+
+    /// Creates a report dated now.
+    pub fn new(clients: Vec<ClientUsage>) -> Self {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        UsageReport { timestamp, clients }
+    }
+
+    /// Renders the report as CSV rows, one per client, without the header.
+    pub fn to_csv(&self) -> String {
+        self.clients
+            .iter()
+            .map(|client| {
+                format!(
+                    "{},{},{},{},{},{},{}\n",
+                    self.timestamp,
+                    client.addr,
+                    client.connected,
+                    client.requests,
+                    client.bytes_received,
+                    client.bytes_sent,
+                    client.errors
+                )
+            })
+            .collect()
+    }
+
+    /// Renders the report as a single line of JSON.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Sends the report to `sink`.
+    ///
+    /// # Returns
+    /// - Ok    upon writing the report, or handing it to the callback.
+    /// - Err   when the file could not be written.
+    pub fn write_to(&self, sink: &UsageReportSink) -> io::Result<()> {
+        match sink {
+            UsageReportSink::Csv(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                let mut rows = self.to_csv();
+                if file.metadata()?.len() == 0 {
+                    rows.insert_str(0, Self::CSV_HEADER);
+                }
+                file.write_all(rows.as_bytes())
+            }
+            UsageReportSink::Json(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                let mut line = self.to_json()?;
+                line.push('\n');
+                file.write_all(line.as_bytes())
+            }
+            UsageReportSink::Callback(callback) => {
+                callback(self);
+                Ok(())
+            }
+        }
+    }
+}
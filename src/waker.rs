@@ -0,0 +1,83 @@
+use std::{io, net::TcpListener, time::Duration};
+
+#[cfg(unix)]
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::{io::AsRawFd, net::UnixStream},
+};
+
+/// Lets the accept loop block until a connection is pending or it is woken up, e.g. on
+/// stop, instead of polling the listener.
+///
+/// Waking up before the loop waits is not lost: the next wait returns right away.
+pub struct AcceptWaker {
+    // Written to by `wake()`, and polled along with the listener.
+    #[cfg(unix)]
+    reader: UnixStream,
+    #[cfg(unix)]
+    writer: UnixStream,
+}
+
+impl AcceptWaker {
+    /// Creates a waker.
+    ///
+    /// # Returns
+    /// - Ok    with the waker.
+    /// - Err   when its socket pair could not be created.
+    #[cfg(unix)]
+    pub fn new() -> io::Result<Self> {
+        let (reader, writer) = UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        Ok(AcceptWaker { reader, writer })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> io::Result<Self> {
+        Ok(AcceptWaker {})
+    }
+
+    /// Wakes up the loop waiting on `wait()`.
+    #[cfg(unix)]
+    pub fn wake(&self) {
+        // A full buffer already holds a pending wake up.
+        let _ = (&self.writer).write(&[1]);
+    }
+
+    #[cfg(not(unix))]
+    pub fn wake(&self) {}
+
+    /// Blocks until `listener` has a pending connection, the waker was woken up, or
+    /// `timeout` elapsed. Spurious returns are possible, so the caller checks again.
+    ///
+    /// # Arguments
+    /// - `listener` The listener to watch.
+    /// - `timeout` How long to wait at most, `None` to wait for an event.
+    #[cfg(unix)]
+    pub fn wait(&self, listener: &TcpListener, timeout: Option<Duration>) -> io::Result<()> {
+        let mut fds = [
+            libc::pollfd { fd: listener.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.reader.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        let timeout_ms = timeout.map_or(-1, |timeout| timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int);
+        // SAFETY: `fds` is a valid array of two pollfd for poll to fill.
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+
+        // Consume the wake ups, so that the next wait blocks again.
+        let mut buffer = [0; 64];
+        while matches!((&self.reader).read(&mut buffer), Ok(len) if len > 0) {}
+        Ok(())
+    }
+
+    /// Without `poll()`, falls back to sleeping for `timeout`, or a tenth of a second.
+    #[cfg(not(unix))]
+    pub fn wait(&self, _listener: &TcpListener, timeout: Option<Duration>) -> io::Result<()> {
+        std::thread::sleep(timeout.unwrap_or(Duration::from_millis(100)));
+        Ok(())
+    }
+}
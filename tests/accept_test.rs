@@ -0,0 +1,49 @@
+use embedded_recruitment_task::accept::{AcceptErrorAction, AcceptErrorPolicy, AcceptErrors};
+use std::time::Duration;
+
+#[test]
+fn test_backoff_doubles_up_to_max() {
+    let mut errors = AcceptErrors::new(AcceptErrorPolicy::Backoff {
+        initial: Duration::from_millis(10),
+        max: Duration::from_millis(50),
+    });
+
+    let delays: Vec<_> = (0..5).map(|_| errors.on_error()).collect();
+    assert_eq!(
+        delays,
+        [10, 20, 40, 50, 50].map(|ms| AcceptErrorAction::Wait(Duration::from_millis(ms)))
+    );
+
+    // A successful accept starts over from the initial delay.
+    errors.on_success();
+    assert_eq!(errors.on_error(), AcceptErrorAction::Wait(Duration::from_millis(10)));
+
+    let stats = errors.stats();
+    assert_eq!((stats.errors, stats.backoffs, stats.consecutive), (6, 6, 1));
+}
+
+#[test]
+fn test_pause_after_consecutive_errors() {
+    let duration = Duration::from_secs(1);
+    let mut errors = AcceptErrors::new(AcceptErrorPolicy::Pause { after: 3, duration });
+
+    assert_eq!(errors.on_error(), AcceptErrorAction::Retry);
+    assert_eq!(errors.on_error(), AcceptErrorAction::Retry);
+    assert_eq!(errors.on_error(), AcceptErrorAction::Wait(duration));
+    // The pause resets the count of consecutive errors.
+    assert_eq!(errors.on_error(), AcceptErrorAction::Retry);
+
+    let stats = errors.stats();
+    assert_eq!((stats.errors, stats.pauses, stats.consecutive), (4, 1, 1));
+}
+
+#[test]
+fn test_shutdown_after_consecutive_errors() {
+    let mut errors = AcceptErrors::new(AcceptErrorPolicy::Shutdown { after: 2 });
+
+    assert_eq!(errors.on_error(), AcceptErrorAction::Retry);
+    errors.on_success();
+    assert_eq!(errors.on_error(), AcceptErrorAction::Retry);
+    assert_eq!(errors.on_error(), AcceptErrorAction::Shutdown);
+    assert_eq!(errors.stats().shutdowns, 1);
+}
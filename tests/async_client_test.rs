@@ -0,0 +1,115 @@
+#![cfg(feature = "tokio")]
+
+use embedded_recruitment_task::{
+    async_client::AsyncClient,
+    async_server::AsyncServer,
+    framing::FrameFormat,
+    message::{client_message, server_message, AddErrorCode, DelayedEchoRequest, Transport},
+};
+use std::sync::Arc;
+
+// Runs `test` on a runtime along with a server, as an async application would.
+fn with_async_server<F: std::future::Future<Output = ()>>(test: impl FnOnce(Arc<AsyncServer>) -> F) {
+    with_configured_async_server(|server| server, test);
+}
+
+// Like `with_async_server()`, with the server as set by `configure`.
+fn with_configured_async_server<F: std::future::Future<Output = ()>>(
+    configure: impl FnOnce(AsyncServer) -> AsyncServer,
+    test: impl FnOnce(Arc<AsyncServer>) -> F,
+) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build the runtime");
+    runtime.block_on(async {
+        let server = Arc::new(configure(AsyncServer::bind("localhost:0").await.expect("Failed to start server")));
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { server.run().await }
+        });
+        test(server.clone()).await;
+        server.stop();
+        assert!(running.await.unwrap().is_ok(), "Server encountered an error");
+    });
+}
+
+// The following test is aimed at checking that the typed helpers of the async client
+// decode each kind of response.
+#[test]
+fn test_async_client_helpers() {
+    with_async_server(|server| async move {
+        let mut client = AsyncClient::connect(server.local_addr().unwrap()).await.expect("Failed to connect");
+
+        assert_eq!(client.echo("Hello, async World!").await.unwrap(), "Hello, async World!");
+        assert_eq!(client.add(2, 3).await.unwrap(), Ok(5));
+        let overflow = client.add(i32::MAX, 1).await.unwrap().unwrap_err();
+        assert_eq!(overflow.code, AddErrorCode::Overflow);
+
+        let my_stats = client.my_stats().await.unwrap();
+        assert_eq!(my_stats.requests_served, 3);
+
+        assert!(client.bye().await.is_ok(), "Expected the goodbye to be acknowledged");
+    });
+}
+
+// The following test is aimed at checking that responses sent later on can be received
+// while other requests are answered.
+#[test]
+fn test_async_client_send_receive() {
+    with_async_server(|server| async move {
+        let mut client = AsyncClient::connect(server.local_addr().unwrap()).await.expect("Failed to connect");
+
+        let delayed_echo_request = DelayedEchoRequest {
+            content: "Slow".to_string(),
+            delay_ms: 100,
+        };
+        assert!(client.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).await.is_ok());
+        assert_eq!(client.echo("Fast").await.unwrap(), "Fast");
+        assert!(matches!(
+            client.receive().await.unwrap().message,
+            Some(server_message::Message::EchoMessage(echo)) if echo.content == "Slow"
+        ));
+
+        // The requests the server rejects are answered with an error message.
+        let too_long = DelayedEchoRequest {
+            content: "Never".to_string(),
+            delay_ms: 60_000,
+        };
+        assert!(matches!(
+            client.call(client_message::Message::DelayedEchoRequest(too_long)).await.unwrap().message,
+            Some(server_message::Message::ErrorMessage(_))
+        ));
+    });
+}
+
+// The following test is aimed at checking that the async client frames its requests
+// as the server expects, from the start or once upgraded.
+#[test]
+fn test_async_client_frame_format() {
+    with_configured_async_server(
+        |server| server.with_sync_marker().with_max_frame_len(64),
+        |server| async move {
+            let mut client = AsyncClient::connect_with_format(server.local_addr().unwrap(), FrameFormat::SYNCED)
+                .await
+                .expect("Failed to connect");
+            assert_eq!(client.echo("Synced").await.unwrap(), "Synced");
+
+            // Longer requests than the server accepts in a frame are fragmented.
+            let content = "x".repeat(1000);
+            client.set_fragment_len(Some(48));
+            assert_eq!(client.echo(&content).await.unwrap(), content);
+        },
+    );
+
+    with_async_server(|server| async move {
+        let mut client = AsyncClient::connect(server.local_addr().unwrap()).await.expect("Failed to connect");
+        assert_eq!(client.echo("Framed").await.unwrap(), "Framed");
+
+        // The rest of the session is framed with sync markers.
+        assert!(client.upgrade(Transport::SyncMarker).await.is_ok(), "Failed to upgrade the connection");
+        assert_eq!(client.echo("Upgraded").await.unwrap(), "Upgraded");
+        assert_eq!(client.add(2, 3).await.unwrap(), Ok(5));
+        assert!(client.bye().await.is_ok(), "Expected the goodbye to be acknowledged");
+    });
+}
@@ -0,0 +1,254 @@
+#![cfg(feature = "tokio")]
+
+use embedded_recruitment_task::{
+    async_server::AsyncServer,
+    time_scale,
+    message::{
+        client_message, eval_response, server_message, AddRequest, ByeMessage, ClientMessage, DelayedEchoRequest, EchoMessage, ErrorCode,
+        EvalRequest, MyStatsRequest, ShutdownReason, Transport,
+    },
+};
+use std::{
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use embedded_recruitment_task::client;
+
+// Runs the server, as set by `configure`, on a runtime of its own, as an async application would.
+fn setup_async_server(configure: impl FnOnce(AsyncServer) -> AsyncServer + Send + 'static) -> (Arc<AsyncServer>, JoinHandle<()>) {
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to build the runtime");
+        runtime.block_on(async {
+            let server = Arc::new(configure(AsyncServer::bind("localhost:0").await.expect("Failed to start server")));
+            sender.send(server.clone()).unwrap();
+            server.run().await.expect("Server encountered an error");
+        });
+    });
+    (receiver.recv().expect("Server did not start"), handle)
+}
+
+// The following test is aimed at checking that the async server answers the requests
+// like the threaded one, including the delayed echoes answered out of order.
+#[test]
+fn test_async_server() {
+    let (server, handle) = setup_async_server(|server| server);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let echo_message = EchoMessage {
+        content: "Hello, async World!".to_string(),
+    };
+    let response = client.call_message(client_message::Message::EchoMessage(echo_message.clone()));
+    assert!(
+        matches!(response.unwrap().message, Some(server_message::Message::EchoMessage(echo)) if echo == echo_message),
+        "Expected the echo of the message"
+    );
+    assert_eq!(client.add(2, 3).unwrap().unwrap(), 5);
+
+    // The rest of the session is framed with sync markers.
+    assert!(client.upgrade(Transport::SyncMarker).is_ok(), "Failed to upgrade the connection");
+
+    // The delayed echo comes after the response to the next request.
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Slow".to_string(),
+        delay_ms: 200,
+    };
+    assert!(client.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).is_ok());
+    assert!(client.send(client_message::Message::AddRequest(AddRequest { a: 1, b: 1 })).is_ok());
+    assert!(matches!(
+        client.receive().unwrap().message,
+        Some(server_message::Message::AddResponse(_))
+    ));
+    assert!(matches!(
+        client.receive().unwrap().message,
+        Some(server_message::Message::EchoMessage(echo)) if echo.content == "Slow"
+    ));
+
+    let bye = client.call_message(client_message::Message::ByeMessage(ByeMessage::default()));
+    assert!(matches!(bye.unwrap().message, Some(server_message::Message::ByeMessage(_))));
+
+    // A client still connected does not hold back the stop, and is told about it.
+    let mut idle_client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(idle_client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(idle_client.add(1, 1).unwrap(), Ok(2));
+
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    match idle_client.receive().expect("Failed to receive the shut down notification").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.shutdown_reason(), ShutdownReason::Requested, "Unexpected shut down reason");
+            assert!(error.session_summary.is_some(), "Notification holds no session summary");
+        }
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+}
+
+// The following test is aimed at checking that the async server drops the delayed
+// echoes still pending once it acknowledged a goodbye.
+#[test]
+fn test_async_no_delayed_echo_after_bye() {
+    let (server, handle) = setup_async_server(|server| server);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Too late".to_string(),
+        delay_ms: 200,
+    };
+    assert!(client.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).is_ok());
+    let bye = client.call_message(client_message::Message::ByeMessage(ByeMessage::default()));
+    assert!(matches!(bye.unwrap().message, Some(server_message::Message::ByeMessage(_))));
+
+    // Wait past the delay of the echo.
+    let after_bye = client.receive_timeout(Duration::from_millis(500));
+    assert!(after_bye.is_err(), "Received {:?} after the acknowledgement of the goodbye", after_bye);
+
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the async server holds the coalesced
+// replies until enough of them are, or until their window elapsed.
+#[test]
+fn test_async_write_coalescing() {
+    let window = Duration::from_millis(300);
+    let (server, handle) = setup_async_server(move |server| server.with_write_coalescing(window, 2));
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // The two replies are written together, as soon as the second one is held.
+    for content in ["One", "Two"] {
+        let echo_message = EchoMessage {
+            content: content.to_string(),
+        };
+        assert!(client.send(client_message::Message::EchoMessage(echo_message)).is_ok());
+    }
+    for content in ["One", "Two"] {
+        assert!(matches!(
+            client.receive().unwrap().message,
+            Some(server_message::Message::EchoMessage(echo)) if echo.content == content
+        ));
+    }
+
+    // A reply no other follows is written once its window elapsed.
+    let sent = Instant::now();
+    assert_eq!(client.add(2, 3).unwrap().unwrap(), 5);
+    assert!(sent.elapsed() >= time_scale::scale(window), "Reply was not held");
+
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the evaluations, run off the tasks
+// serving the connections, are answered in order, and counted in the session.
+#[test]
+fn test_async_compute_requests() {
+    let (server, handle) = setup_async_server(|server| server);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let eval = |expression: &str| ClientMessage {
+        message: Some(client_message::Message::EvalRequest(EvalRequest {
+            expression: expression.to_string(),
+            vars: [("a".to_string(), 2)].into(),
+        })),
+        ..Default::default()
+    };
+    assert!(client.send(eval("3*(a+1)").message.unwrap()).is_ok());
+    assert!(client.send(client_message::Message::AddRequest(AddRequest { a: 1, b: 1 })).is_ok());
+    assert!(matches!(
+        client.receive().unwrap().message,
+        Some(server_message::Message::EvalResponse(eval)) if eval.outcome == Some(eval_response::Outcome::Result(9))
+    ));
+    assert!(matches!(
+        client.receive().unwrap().message,
+        Some(server_message::Message::AddResponse(_))
+    ));
+
+    // A batch holding an evaluation runs off the tasks as a whole.
+    let responses = client.batch(vec![eval("a*a"), eval("a-5")]);
+    let results: Vec<_> = responses
+        .into_iter()
+        .map(|response| match response.expect("Batched evaluation failed").message {
+            Some(server_message::Message::EvalResponse(eval)) => eval.outcome,
+            message => panic!("Expected EvalResponse, but received {:?}", message),
+        })
+        .collect();
+    assert_eq!(results, [Some(eval_response::Outcome::Result(4)), Some(eval_response::Outcome::Result(-3))]);
+
+    let stats = client.call_message(client_message::Message::MyStatsRequest(MyStatsRequest {}));
+    match stats.expect("Failed to receive response for MyStatsRequest").message {
+        Some(server_message::Message::MyStatsResponse(stats)) => {
+            assert_eq!(stats.requests_served, 3, "Requests served mismatch");
+        }
+        message => panic!("Expected MyStatsResponse, but received {:?}", message),
+    }
+
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the async server refuses the connections
+// beyond its limit, sums up the usage of its clients, and tells them why it stops.
+#[test]
+fn test_async_limits_and_usage() {
+    let (server, handle) = setup_async_server(|server| server.with_max_connections(1));
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.add(1, 1).unwrap(), Ok(2));
+    assert_eq!(server.active_client_count(), 1, "Client was not registered");
+
+    // The second client is told that the server is busy.
+    let mut refused = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(refused.connect().is_ok(), "Failed to connect to the server");
+    match refused.receive().expect("Failed to receive the refusal").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.code(), ErrorCode::ServerBusy, "Expected SERVER_BUSY")
+        }
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+
+    // The totals include the clients that left.
+    assert_eq!(client.add(2, 2).unwrap(), Ok(4));
+    let totals = server.usage_totals();
+    assert_eq!(totals.requests, 2, "Requests were not counted");
+    assert!(totals.bytes_received > 0 && totals.bytes_sent > 0, "Bytes were not counted");
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(server.active_client_count(), 0, "Client was not released");
+    assert_eq!(server.usage_totals().requests, 3, "Departed client was not counted");
+
+    // The clients are told why the server stops.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.add(3, 3).unwrap(), Ok(6));
+    server.stop_with_reason(ShutdownReason::AdminCommand);
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    match client.receive().expect("Failed to receive the shut down notification").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.shutdown_reason(), ShutdownReason::AdminCommand, "Unexpected shut down reason")
+        }
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+}
@@ -1,25 +1,42 @@
 use embedded_recruitment_task::{
-    message::{client_message, server_message, AddRequest, EchoMessage, ServerMessage},
+    add::ServerError,
+    message::{client_message, server_message, AddErrorCode, AddRequest, ByeMessage, ClientMessage, DelayedEchoRequest, EchoMessage, ErrorCode, MyStatsRequest, ReconnectHint, ServerMessage},
     server::Server,
+    time_scale,
 };
 use prost::Message;
 use std::{
-    sync::Arc,
-    thread::{self, JoinHandle},
-    time::Duration
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
 };
-use std::io::{Write, Read};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 
-mod client;
+use embedded_recruitment_task::client;
 
-fn setup_server_thread(server: Arc<Server>) -> JoinHandle<()> {
-    thread::spawn(move || {
-        server.run().expect("Server encountered an error");
-    })
-}
+use common::setup_server_thread;
+mod common;
 
 fn create_server() -> Arc<Server> {
-    Arc::new(Server::new("localhost:8080").expect("Failed to start server"))
+    Arc::new(Server::new("localhost:0").expect("Failed to start server"))
+}
+
+// A port nothing listens on, until a test binds it.
+fn unused_port() -> u16 {
+    let listener = TcpListener::bind("localhost:0").expect("Failed to find an unused port");
+    listener.local_addr().unwrap().port()
+}
+
+// Wait for the server workers to register or release their clients.
+fn wait_for_active_clients(server: &Server, count: usize) -> bool {
+    for _ in 0..100 {
+        if server.active_client_count() == count {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
 }
 
 #[test]
@@ -29,7 +46,7 @@ fn test_client_connection() {
     let handle = setup_server_thread(server.clone());
 
     // Create and connect the client
-    let mut client = client::Client::new("localhost", 8080, 1000);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
     assert!(client.connect().is_ok(), "Failed to connect to the server");
 
     // Disconnect the client
@@ -44,6 +61,7 @@ fn test_client_connection() {
         handle.join().is_ok(),
         "Server thread panicked or failed to join"
     );
+    server.assert_quiesced();
 }
 
 #[test]
@@ -53,12 +71,13 @@ fn test_client_echo_message() {
     let handle = setup_server_thread(server.clone());
 
     // Create and connect the client
-    let mut client = client::Client::new("localhost", 8080, 1000);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
     assert!(client.connect().is_ok(), "Failed to connect to the server");
 
     // Prepare the message
-    let mut echo_message = EchoMessage::default();
-    echo_message.content = "Hello, World!".to_string();
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
     let message = client_message::Message::EchoMessage(echo_message.clone());
 
     // Send the message to the server
@@ -102,7 +121,7 @@ fn test_multiple_echo_messages() {
     let handle = setup_server_thread(server.clone());
 
     // Create and connect the client
-    let mut client = client::Client::new("localhost", 8080, 1000);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
     assert!(client.connect().is_ok(), "Failed to connect to the server");
 
     // Prepare multiple messages
@@ -114,8 +133,9 @@ fn test_multiple_echo_messages() {
 
     // Send and receive multiple messages
     for message_content in messages {
-        let mut echo_message = EchoMessage::default();
-        echo_message.content = message_content.clone();
+        let echo_message = EchoMessage {
+            content: message_content.clone(),
+        };
         let message = client_message::Message::EchoMessage(echo_message);
 
         // Send the message to the server
@@ -160,10 +180,10 @@ fn test_multiple_clients() {
     let handle = setup_server_thread(server.clone());
 
     // Create and connect multiple clients
-    let mut clients = vec![
-        client::Client::new("localhost", 8080, 1000),
-        client::Client::new("localhost", 8080, 1000),
-        client::Client::new("localhost", 8080, 1000),
+    let mut clients = [
+        client::Client::with_addr(server.local_addr().unwrap(), 1000),
+        client::Client::with_addr(server.local_addr().unwrap(), 1000),
+        client::Client::with_addr(server.local_addr().unwrap(), 1000),
     ];
 
     for client in clients.iter_mut() {
@@ -179,8 +199,9 @@ fn test_multiple_clients() {
 
     // Send and receive multiple messages for each client
     for message_content in messages {
-        let mut echo_message = EchoMessage::default();
-        echo_message.content = message_content.clone();
+        let echo_message = EchoMessage {
+            content: message_content.clone(),
+        };
         let message = client_message::Message::EchoMessage(echo_message.clone());
 
         for client in clients.iter_mut() {
@@ -223,6 +244,7 @@ fn test_multiple_clients() {
         handle.join().is_ok(),
         "Server thread panicked or failed to join"
     );
+    server.assert_quiesced();
 }
 
 #[test]
@@ -232,14 +254,15 @@ fn test_client_add_request() {
     let handle = setup_server_thread(server.clone());
 
     // Create and connect the client
-    let mut client = client::Client::new("localhost", 8080, 1000);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
     assert!(client.connect().is_ok(), "Failed to connect to the server");
 
     // Prepare the message
-    let mut add_request = AddRequest::default();
-    add_request.a = 10;
-    add_request.b = 20;
-    let message = client_message::Message::AddRequest(add_request.clone());
+    let add_request = AddRequest {
+        a: 10,
+        b: 20,
+    };
+    let message = client_message::Message::AddRequest(add_request);
 
     // Send the message to the server
     assert!(client.send(message).is_ok(), "Failed to send message");
@@ -254,8 +277,8 @@ fn test_client_add_request() {
     match response.unwrap().message {
         Some(server_message::Message::AddResponse(add_response)) => {
             assert_eq!(
-                add_response.result,
-                add_request.a + add_request.b,
+                add_response.to_result(),
+                Ok(add_request.a + add_request.b),
                 "AddResponse result does not match"
             );
         }
@@ -285,17 +308,19 @@ fn test_parallel_client_requests() {
     let handle = setup_server_thread(server.clone());
 
     // Spawn ten client threads.
+    let addr = server.local_addr().unwrap();
     let clients: Vec<_> = (0..10).map(|i| {
         thread::spawn(move || {
             // Create and connect the client
-            let mut client = client::Client::new("localhost", 8080, 1000);
+            let mut client = client::Client::with_addr(addr, 1000);
             assert!(client.connect().is_ok(), "Failed to connect to the server");
 
             if i%2 == 0 {
                 // Send an echo message request.
                 // Prepare the message
-                let mut echo_message = EchoMessage::default();
-                echo_message.content = format!("Hello, World From Client {}!", i);
+                let echo_message = EchoMessage {
+                    content: format!("Hello, World From Client {}!", i),
+                };
                 let message = client_message::Message::EchoMessage(echo_message.clone());
 
                 // Send the message to the server
@@ -320,10 +345,11 @@ fn test_parallel_client_requests() {
             } else {
                 // Send an add request.
                 // Prepare the message
-                let mut add_request = AddRequest::default();
-                add_request.a = i;
-                add_request.b = i;
-                let message = client_message::Message::AddRequest(add_request.clone());
+                let add_request = AddRequest {
+                    a: i,
+                    b: i,
+                };
+                let message = client_message::Message::AddRequest(add_request);
 
                 // Send the message to the server
                 assert!(client.send(message).is_ok(), "Failed to send message");
@@ -338,8 +364,8 @@ fn test_parallel_client_requests() {
                 match response.unwrap().message {
                     Some(server_message::Message::AddResponse(add_response)) => {
                         assert_eq!(
-                            add_response.result,
-                            add_request.a + add_request.b,
+                            add_response.to_result(),
+                            Ok(add_request.a + add_request.b),
                             "AddResponse result does not match"
                         );
                     }
@@ -370,21 +396,20 @@ fn test_client_bad_request() {
     let server = create_server();
     let handle = setup_server_thread(server.clone());
 
-    // Create a direct TcpStream to the server, since the client struct
-    // will not recoginze the corrupt data.
-    let mut stream = std::net::TcpStream::connect("localhost:8080").expect("Failed to connect directly to the server");
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
 
-    // Send the corrupt data 0xdeadbeef over the stream
-    let malformed_data = vec![0xde, 0xad, 0xbe, 0xef];
-    stream.write_all(&malformed_data).expect("Failed to send malformed data");
-    stream.flush().expect("Failed to flush stream");
+    // Send the corrupt data 0xdeadbeef in a frame, which the server decodes
+    // as a request.
+    let malformed_data = [0xde, 0xad, 0xbe, 0xef];
+    client.send_raw(&malformed_data).expect("Failed to send malformed data");
 
     // Read data which the server sent.
-    let mut buffer = [0; 512];
-    let bytes_read = stream.read(&mut buffer).expect("Failed to read response from the server");
+    let payload = client.receive_raw().expect("Failed to read response from the server");
 
     // Decode the received server response.
-    let server_response = ServerMessage::decode(&buffer[..bytes_read]).expect("Failed to decode server response");
+    let server_response = ServerMessage::decode(&payload[..]).expect("Failed to decode server response");
 
     // Check the incoming value.
     match server_response.message {
@@ -397,8 +422,57 @@ fn test_client_bad_request() {
         _ => panic!("Expected ErrorMessage, but received a different message type"),
     }
 
-    // Disconnect the stream.
-    stream.shutdown(std::net::Shutdown::Both).expect("Failed to shut down the stream");
+    // Disconnect the client
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    // Stop the server and wait for the thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at testing how a server would handle
+// corrupt data sent unframed, over a direct TcpStream.
+#[test]
+fn test_client_bad_request_unframed() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create a direct TcpStream to the server, since the client would frame the data.
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+    // Send the corrupt data 0xdeadbeef as it is. It does not start like an
+    // unframed message, so it is read as the length prefix of a fragment far
+    // beyond the limit, and the connection is dropped without a reply.
+    let malformed_data = [0xde, 0xad, 0xbe, 0xef];
+    stream.write_all(&malformed_data).expect("Failed to send malformed data");
+    stream.flush().expect("Failed to flush stream");
+    let mut buffer = [0; 512];
+    match stream.read(&mut buffer) {
+        Ok(0) => {}
+        Err(e) if e.kind() == ErrorKind::ConnectionReset => {}
+        other => panic!("Expected the connection to be dropped, got {:?}", other),
+    }
+
+    // The server keeps serving the other clients.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let response = client.call(client_message::Message::EchoMessage(echo_message));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
 
     // Stop the server and wait for the thread to finish
     server.stop();
@@ -417,20 +491,21 @@ fn test_server_failure() {
     let server_handle = setup_server_thread(server.clone());
 
     // Create and connect the client
-    let mut client = client::Client::new("localhost", 8080, 1000);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
     assert!(client.connect().is_ok(), "Failed to connect to the server");
 
     // Spawn a thread to stop the server after 2 seconds.
     let stop_thread = thread::spawn(move || {
-        thread::sleep(Duration::from_secs(2));
+        thread::sleep(time_scale::scale(Duration::from_secs(2)));
         server.stop();
     });
 
     // Iterate indefinetly until the server stops.
     for i in 0.. {
         // Prepare the message
-        let mut echo_message = EchoMessage::default();
-        echo_message.content = format!("Message #{}", i);
+        let echo_message = EchoMessage {
+            content: format!("Message #{}", i),
+        };
         let message = client_message::Message::EchoMessage(echo_message.clone());
 
         // Send the message to the server
@@ -477,3 +552,941 @@ fn test_server_failure() {
     // Ensure the client detects the disconnection
     assert!(client.disconnect().is_ok(), "Client failed to disconnect properly");
 }
+
+// The following test is aimed at checking the counters and the call hook
+// exposed by the client.
+#[test]
+fn test_client_stats() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Record every completed call.
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let hook_records = records.clone();
+    client.set_call_hook(Box::new(move |record| {
+        hook_records.lock().unwrap().push(*record);
+    }));
+
+    // Perform two calls.
+    for i in 0..2 {
+        let echo_message = EchoMessage {
+            content: format!("Call #{}", i),
+        };
+        let response = client.call(client_message::Message::EchoMessage(echo_message));
+        assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    }
+
+    let stats = client.stats();
+    assert_eq!(stats.requests, 2, "Unexpected number of requests");
+    assert_eq!(stats.retries, 0, "Unexpected number of retries");
+    assert_eq!(stats.timeouts, 0, "Unexpected number of timeouts");
+    assert!(stats.bytes_sent > 0, "No bytes were counted as sent");
+    assert!(stats.bytes_received > 0, "No bytes were counted as received");
+
+    let records = records.lock().unwrap();
+    assert_eq!(records.len(), 2, "The call hook was not invoked for every call");
+    assert!(
+        records.iter().all(|record| record.succeeded && record.attempts == 1),
+        "Unexpected call record"
+    );
+
+    // Reconnecting must be counted.
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    assert!(client.connect().is_ok(), "Failed to reconnect to the server");
+    assert_eq!(client.stats().reconnects, 1, "Reconnect was not counted");
+
+    // Disconnect the client
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the late response to a
+// call re-sent after a timeout is not taken for the response to the next call.
+#[test]
+fn test_client_retry_drops_late_response() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 200);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client.set_max_retries(1);
+
+    // The first attempt times out, and the response to either attempt answers the call.
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Slow".to_string(),
+        delay_ms: 300,
+    };
+    match client.call_message(client_message::Message::DelayedEchoRequest(delayed_echo_request)) {
+        Ok(ServerMessage { message: Some(server_message::Message::EchoMessage(echo)), .. }) => {
+            assert_eq!(echo.content, "Slow")
+        }
+        response => panic!("Expected EchoMessage, but received {:?}", response),
+    }
+    assert_eq!(client.stats().retries, 1, "The call was not re-sent");
+
+    // Wait for the response to the second attempt, which comes first on the next call.
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(client.echo("Next").expect("Failed to echo"), "Next", "Received the response to an earlier call");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that once the server closed
+// the connection, receiving fails immediately instead of waiting.
+#[test]
+fn test_client_receive_after_disconnect() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Make sure the server registered the client before stopping it.
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let message = client_message::Message::EchoMessage(echo_message);
+    assert!(client.call(message).is_ok(), "Failed to receive response for EchoMessage");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+
+    // Drain the shut down notification until the connection is seen closed.
+    loop {
+        if let Err(e) = client.receive_timeout(Duration::from_secs(1)) {
+            assert_eq!(e.kind(), ErrorKind::ConnectionAborted, "Unexpected error kind");
+            break;
+        }
+    }
+
+    // The next receive must not wait for the timeout.
+    let start = Instant::now();
+    let response = client.receive_timeout(Duration::from_secs(5));
+    assert!(response.is_err(), "Received a message from a closed connection");
+    assert!(start.elapsed() < Duration::from_secs(1), "Receive waited on a closed connection");
+
+    assert!(client.disconnect().is_ok(), "Client failed to disconnect properly");
+}
+
+// The following test is aimed at checking that the server acknowledges
+// a bye message and then closes the connection.
+#[test]
+fn test_client_bye_handshake() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Say goodbye.
+    let message = client_message::Message::ByeMessage(ByeMessage::default());
+    assert!(client.send(message).is_ok(), "Failed to send message");
+
+    let response = client.receive();
+    assert!(response.is_ok(), "Failed to receive response for ByeMessage");
+    match response.unwrap().message {
+        Some(server_message::Message::ByeMessage(bye)) => {
+            let summary = bye.session_summary.expect("Acknowledgement holds no session summary");
+            assert_eq!(summary.requests_served, 0, "Client made no request before the goodbye");
+            assert_eq!(summary.errors, 0, "Client made no request before the goodbye");
+        }
+        _ => panic!("Expected ByeMessage, but received a different message"),
+    }
+
+    // The server must close the connection right after the acknowledgement.
+    let response = client.receive();
+    assert!(
+        matches!(response, Err(ref e) if e.kind() == ErrorKind::ConnectionAborted),
+        "Server did not close the connection"
+    );
+    assert!(wait_for_active_clients(&server, 0), "Server still tracks the client");
+
+    // Disconnect the client
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    server.assert_quiesced();
+}
+
+// The following test is aimed at checking that the server keeps track
+// of connecting and leaving clients.
+#[test]
+fn test_active_client_count() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Connect clients and make sure the server handled each of them.
+    let mut clients: Vec<_> = (0..3).map(|_| client::Client::with_addr(server.local_addr().unwrap(), 1000)).collect();
+    for client in clients.iter_mut() {
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+        let echo_message = EchoMessage {
+            content: "Hello, World!".to_string(),
+        };
+        let response = client.call(client_message::Message::EchoMessage(echo_message));
+        assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    }
+    assert!(wait_for_active_clients(&server, 3), "Unexpected number of active clients");
+
+    // Released slots must be reused by the next clients.
+    assert!(clients[1].disconnect().is_ok(), "Failed to disconnect from the server");
+    assert!(wait_for_active_clients(&server, 2), "Unexpected number of active clients");
+    assert!(clients[1].connect().is_ok(), "Failed to reconnect to the server");
+    let echo_message = EchoMessage {
+        content: "Hello again!".to_string(),
+    };
+    let response = clients[1].call(client_message::Message::EchoMessage(echo_message));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    assert!(wait_for_active_clients(&server, 3), "Unexpected number of active clients");
+
+    // Disconnect the clients
+    for client in clients.iter_mut() {
+        assert!(
+            client.disconnect().is_ok(),
+            "Failed to disconnect from the server"
+        );
+    }
+    assert!(wait_for_active_clients(&server, 0), "Unexpected number of active clients");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at sending the same request to several
+// servers, one of which is unreachable.
+#[test]
+fn test_multi_client_fan_out() {
+    // Set up the server in a separate thread, nothing listens on the other port.
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    let port = server.local_addr().unwrap().port() as u32;
+    let multi_client = client::MultiClient::new(&[("localhost", port), ("localhost", unused_port() as u32)], 1000);
+    let message = client_message::Message::AddRequest(AddRequest { a: 1, b: 2 });
+
+    // A single answer is enough for first-success and a quorum of one.
+    for mode in [client::FanOut::FirstSuccess, client::FanOut::Quorum(1)] {
+        let responses = multi_client.call(message.clone(), mode);
+        assert!(responses.is_ok(), "Fan-out call failed");
+        match &responses.unwrap()[0].message {
+            Some(server_message::Message::AddResponse(add_response)) => {
+                assert_eq!(add_response.to_result(), Ok(3), "AddResponse result does not match");
+            }
+            _ => panic!("Expected AddResponse, but received a different message"),
+        }
+    }
+
+    // Waiting for all servers must fail.
+    assert!(
+        multi_client.call(message, client::FanOut::All).is_err(),
+        "Fan-out call succeeded without every server"
+    );
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that connecting races the
+// resolved addresses instead of giving up on the first one.
+#[test]
+fn test_client_happy_eyeballs() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Nothing listens on the first address.
+    let addrs: Vec<SocketAddr> = vec![
+        SocketAddr::from(([127, 0, 0, 1], unused_port())),
+        server.local_addr().unwrap(),
+    ];
+    let start = Instant::now();
+    let stream = client::connect_happy_eyeballs(&addrs, Duration::from_secs(1));
+    assert!(stream.is_ok(), "Failed to connect to the server");
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "Connecting waited for the unreachable address"
+    );
+    assert_eq!(
+        stream.unwrap().peer_addr().unwrap(),
+        addrs[1],
+        "Connected to an unexpected address"
+    );
+
+    // Without any reachable address, connecting fails.
+    assert!(
+        client::connect_happy_eyeballs(&addrs[..1], Duration::from_secs(1)).is_err(),
+        "Connected to an unreachable address"
+    );
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that responses rejected by
+// a validator are reported as typed errors.
+#[test]
+fn test_client_response_validators() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Check add results against a locally computed expectation.
+    client.add_validator(Box::new(|request, response| {
+        match (request, &response.message) {
+            (
+                client_message::Message::AddRequest(add_request),
+                Some(server_message::Message::AddResponse(add_response)),
+            ) if add_response.to_result() != Ok(add_request.a + add_request.b) => {
+                Err(format!("{} + {} != {:?}", add_request.a, add_request.b, add_response.to_result()))
+            }
+            _ => Ok(()),
+        }
+    }));
+    // Reject every echo, to make sure violations are surfaced.
+    client.add_validator(Box::new(|_, response| {
+        match response.message {
+            Some(server_message::Message::EchoMessage(_)) => Err("Echo is not allowed".to_string()),
+            _ => Ok(()),
+        }
+    }));
+
+    let message = client_message::Message::AddRequest(AddRequest { a: 10, b: 20 });
+    assert!(client.call(message).is_ok(), "Valid response was rejected");
+
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let error = client
+        .call(client_message::Message::EchoMessage(echo_message))
+        .expect_err("Invalid response was accepted");
+    assert_eq!(error.kind(), ErrorKind::InvalidData, "Unexpected error kind");
+    let violation = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<client::ValidationError>())
+        .expect("Expected a ValidationError");
+    assert_eq!(violation.reason, "Echo is not allowed", "Unexpected rejection reason");
+
+    // Disconnect the client
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_client_batch() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let requests = vec![
+        ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: "Hello, World!".to_string(),
+            })),
+            ..Default::default()
+        },
+        ClientMessage {
+            message: Some(client_message::Message::AddRequest(AddRequest { a: 10, b: 20 })),
+            ..Default::default()
+        },
+        // Closing the connection in the middle of a batch is refused.
+        ClientMessage {
+            message: Some(client_message::Message::ByeMessage(ByeMessage::default())),
+            ..Default::default()
+        },
+        ClientMessage {
+            message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 })),
+            ..Default::default()
+        },
+    ];
+
+    let results = client.batch(requests);
+    assert_eq!(results.len(), 4, "Expected a result per request");
+    match &results[0] {
+        Ok(ServerMessage { message: Some(server_message::Message::EchoMessage(echo)), .. }) => {
+            assert_eq!(echo.content, "Hello, World!", "Echoed message content does not match")
+        }
+        result => panic!("Expected EchoMessage, but received {:?}", result),
+    }
+    match &results[1] {
+        Ok(ServerMessage { message: Some(server_message::Message::AddResponse(add)), .. }) => {
+            assert_eq!(add.to_result(), Ok(30), "AddResponse result does not match")
+        }
+        result => panic!("Expected AddResponse, but received {:?}", result),
+    }
+    assert!(results[2].is_err(), "Batched ByeMessage should fail");
+    match &results[3] {
+        Ok(ServerMessage { message: Some(server_message::Message::AddResponse(add)), .. }) => {
+            assert_eq!(add.to_result(), Ok(3), "AddResponse result does not match")
+        }
+        result => panic!("Expected AddResponse, but received {:?}", result),
+    }
+    assert_eq!(client.stats().requests, 1, "A batch should take a single request");
+
+    // Disconnect the client
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_chaos_client() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    let schedule = client::ChaosSchedule {
+        seed: 42,
+        min_latency: Duration::from_millis(5),
+        max_latency: Duration::from_millis(20),
+        duplicate_probability: 0.5,
+        reorder: true,
+    };
+    let mut chaos = client::ChaosClient::new(client::Client::with_addr(server.local_addr().unwrap(), 1000), schedule);
+    assert!(chaos.client().connect().is_ok(), "Failed to connect to the server");
+
+    // Results come back in the order of the requests, whatever the order of the calls.
+    let start = Instant::now();
+    let messages: Vec<_> = (0..8)
+        .map(|i| client_message::Message::AddRequest(AddRequest { a: i, b: 1 }))
+        .collect();
+    for (i, result) in chaos.call_all(messages).into_iter().enumerate() {
+        match result.expect("Failed to receive response for AddRequest").message {
+            Some(server_message::Message::AddResponse(add)) => {
+                assert_eq!(add.to_result(), Ok(i as i32 + 1), "AddResponse result does not match")
+            }
+            _ => panic!("Expected AddResponse, but received a different message"),
+        }
+    }
+    assert!(start.elapsed() >= Duration::from_millis(5 * 8), "Latency was not injected");
+
+    let stats = chaos.stats();
+    assert_eq!(stats.delayed, 8, "Every call should be delayed");
+    assert!(stats.duplicated > 0, "Some requests should be duplicated");
+    assert!(stats.reordered > 0, "Some calls should be reordered");
+    assert_eq!(stats.failed_duplicates, 0, "Every duplicate should be answered");
+
+    // The server saw the duplicates too.
+    let response = chaos.client().call_message(client_message::Message::MyStatsRequest(MyStatsRequest {}));
+    match response.expect("Failed to receive response for MyStatsRequest").message {
+        Some(server_message::Message::MyStatsResponse(my_stats)) => {
+            assert_eq!(my_stats.requests_served, 8 + stats.duplicated, "Duplicates did not reach the server")
+        }
+        _ => panic!("Expected MyStatsResponse, but received a different message"),
+    }
+
+    // The calls are in flight together.
+    let start = Instant::now();
+    let messages: Vec<_> = (0..4)
+        .map(|i| {
+            client_message::Message::DelayedEchoRequest(DelayedEchoRequest { content: i.to_string(), delay_ms: 100 })
+        })
+        .collect();
+    for (i, result) in chaos.call_all(messages).into_iter().enumerate() {
+        match result.expect("Failed to receive response for DelayedEchoRequest").message {
+            Some(server_message::Message::EchoMessage(echo)) => {
+                assert_eq!(echo.content, i.to_string(), "Response went to the wrong call")
+            }
+            _ => panic!("Expected EchoMessage, but received a different message"),
+        }
+    }
+    assert!(start.elapsed() < Duration::from_millis(300), "Calls were not pipelined");
+
+    // The same seed gives the same schedule.
+    let mut replay = client::ChaosClient::new(client::Client::with_addr(server.local_addr().unwrap(), 1000), schedule);
+    assert!(replay.client().connect().is_ok(), "Failed to connect to the server");
+    let messages: Vec<_> = (0..8)
+        .map(|i| client_message::Message::AddRequest(AddRequest { a: i, b: 1 }))
+        .collect();
+    assert!(replay.call_all(messages).iter().all(|result| result.is_ok()), "Replayed calls failed");
+    assert_eq!(replay.stats(), stats, "Schedules differ for the same seed");
+
+    assert!(chaos.client().disconnect().is_ok(), "Failed to disconnect from the server");
+    assert!(replay.client().disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_client_echo() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    assert_eq!(client.echo("Hello, World!").expect("Failed to call the server"), "Hello, World!");
+    assert_eq!(client.echo("").expect("Failed to call the server"), "");
+
+    // Disconnect the client
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_client_add_overflow() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    assert_eq!(client.add(10, 20).expect("Failed to call the server"), Ok(30));
+
+    // Overflows are reported rather than wrapped around, and the connection stays usable.
+    let result = client.add(i32::MAX, 1).expect("Failed to call the server");
+    assert!(
+        matches!(result, Err(ServerError { code: AddErrorCode::Overflow, .. })),
+        "Expected an overflow error, but received {:?}",
+        result
+    );
+    assert_eq!(client.add(i32::MIN, -1).expect("Failed to call the server").map_err(|e| e.code), Err(AddErrorCode::Overflow));
+    assert_eq!(client.add(-1, 1).expect("Failed to call the server"), Ok(0));
+
+    // Disconnect the client
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_client_responses() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Create and connect the client
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 100);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Make sure that the connection is served before pipelining requests.
+    let echo_message = EchoMessage {
+        content: "Ready".to_string(),
+    };
+    assert!(client.call(client_message::Message::EchoMessage(echo_message)).is_ok(), "Failed to receive response");
+
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Slow".to_string(),
+        delay_ms: 300,
+    };
+    assert!(
+        client.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).is_ok(),
+        "Failed to send message"
+    );
+    let echo_message = EchoMessage {
+        content: "Fast".to_string(),
+    };
+    assert!(client.send(client_message::Message::EchoMessage(echo_message)).is_ok(), "Failed to send message");
+
+    // Stop the server once the delayed echo was sent.
+    let stopper = {
+        let server = server.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(600));
+            server.stop();
+        })
+    };
+
+    // The iteration outlasts the read timeout of the client, and ends on disconnect.
+    let received: Vec<String> = client
+        .responses()
+        .map(|response| match response.message {
+            Some(server_message::Message::EchoMessage(echo)) => echo.content,
+            Some(server_message::Message::ErrorMessage(error)) => error.content,
+            message => panic!("Unexpected message {:?}", message),
+        })
+        .collect();
+    assert_eq!(received, ["Fast", "Slow", "Server is shutting down."], "Responses do not match");
+
+    assert!(stopper.join().is_ok(), "Stopping thread panicked");
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_shared_client() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Share a single connection between the threads.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 200);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let shared = client::SharedClient::new(client);
+
+    let threads: Vec<_> = (0..5)
+        .map(|i| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for j in 0..10 {
+                    let content = format!("Hello from thread {} call {}", i, j);
+                    let echo_message = EchoMessage { content: content.clone() };
+                    match shared.call(client_message::Message::EchoMessage(echo_message)) {
+                        Ok(ServerMessage { message: Some(server_message::Message::EchoMessage(echo)), .. }) => {
+                            assert_eq!(echo.content, content, "Response went to the wrong call")
+                        }
+                        response => panic!("Expected EchoMessage, but received {:?}", response),
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        assert!(thread.join().is_ok(), "Client thread panicked");
+    }
+    assert_eq!(server.active_client_count(), 1, "Threads did not share the connection");
+    assert_eq!(shared.with_client(|client| client.stats().requests), 50, "Calls were not counted");
+
+    // A call does not wait for the one in flight before it.
+    let delayed_echo = |content: &str, delay_ms| {
+        client_message::Message::DelayedEchoRequest(DelayedEchoRequest { content: content.to_string(), delay_ms })
+    };
+    let slow = {
+        let shared = shared.clone();
+        let message = delayed_echo("Slow", 150);
+        thread::spawn(move || shared.call(message))
+    };
+    thread::sleep(Duration::from_millis(20));
+    let start = Instant::now();
+    match shared.call(client_message::Message::EchoMessage(EchoMessage { content: "Fast".to_string() })) {
+        Ok(ServerMessage { message: Some(server_message::Message::EchoMessage(echo)), .. }) => {
+            assert_eq!(echo.content, "Fast", "Response went to the wrong call")
+        }
+        response => panic!("Expected EchoMessage, but received {:?}", response),
+    }
+    assert!(start.elapsed() < Duration::from_millis(100), "Call waited for the one in flight");
+    match slow.join().expect("Client thread panicked") {
+        Ok(ServerMessage { message: Some(server_message::Message::EchoMessage(echo)), .. }) => {
+            assert_eq!(echo.content, "Slow", "Response went to the wrong call")
+        }
+        response => panic!("Expected EchoMessage, but received {:?}", response),
+    }
+
+    // The late response to a timed-out call is not taken for the next one's.
+    let response = shared.call(delayed_echo("Late", 300));
+    assert!(response.is_err(), "Expected the call to time out");
+    match shared.call(delayed_echo("On time", 100)) {
+        Ok(ServerMessage { message: Some(server_message::Message::EchoMessage(echo)), .. }) => {
+            assert_eq!(echo.content, "On time", "Late response was taken for the next one")
+        }
+        response => panic!("Expected EchoMessage, but received {:?}", response),
+    }
+
+    assert!(shared.with_client(|client| client.disconnect()).is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_client_reconnect_hint() {
+    // Set up the server in a separate thread, and the one clients are steered to
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+    let alternate = create_server();
+    let alternate_handle = setup_server_thread(alternate.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let echo = |content: &str| client_message::Message::EchoMessage(EchoMessage { content: content.to_string() });
+    assert!(client.call(echo("Before")).is_ok(), "Failed to receive response for EchoMessage");
+
+    // The server sends the client elsewhere while it is idle.
+    let hint = ReconnectHint {
+        retry_after_ms: 200,
+        alternate_addr: alternate.local_addr().unwrap().to_string(),
+    };
+    let addr = client.local_addr().expect("Failed to read the client address");
+    let drained_at = Instant::now();
+    assert!(server.drain(addr, hint), "Client was not found");
+    thread::sleep(Duration::from_millis(50));
+
+    // The next call follows the hint without the application noticing.
+    match client.call(echo("After")) {
+        Ok(client::ServerReply::Echo(content)) => assert_eq!(content, "After", "Echoed content does not match"),
+        reply => panic!("Expected EchoMessage, but received {:?}", reply),
+    }
+    assert!(drained_at.elapsed() >= Duration::from_millis(200), "Retry-after delay was not honored");
+    assert_eq!(client.stats().reconnects, 1, "Client did not reconnect");
+    assert!(wait_for_active_clients(&server, 0), "Client is still connected to the drained server");
+    assert_eq!(alternate.active_client_count(), 1, "Client was not steered to the alternate server");
+
+    // A delay longer than the client accepts is capped.
+    client.set_max_reconnect_delay(Duration::from_millis(100));
+    let hint = ReconnectHint {
+        retry_after_ms: u32::MAX,
+        alternate_addr: server.local_addr().unwrap().to_string(),
+    };
+    let addr = client.local_addr().expect("Failed to read the client address");
+    assert!(alternate.drain(addr, hint), "Client was not found");
+    thread::sleep(Duration::from_millis(50));
+    let drained_at = Instant::now();
+    match client.call(echo("Capped")) {
+        Ok(client::ServerReply::Echo(content)) => assert_eq!(content, "Capped", "Echoed content does not match"),
+        reply => panic!("Expected EchoMessage, but received {:?}", reply),
+    }
+    assert!(drained_at.elapsed() < Duration::from_secs(1), "Retry-after delay was not capped");
+    assert_eq!(client.stats().reconnects, 2, "Client did not reconnect");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the servers and wait for threads to finish
+    server.stop();
+    alternate.stop();
+    assert!(
+        handle.join().is_ok() && alternate_handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_client_failover() {
+    // Set up the alternate server in a separate thread, the primary is down for now
+    let alternate = create_server();
+    let alternate_handle = setup_server_thread(alternate.clone());
+
+    let primary_port = unused_port();
+    let mut client = client::Client::new("localhost", primary_port as u32, 1000);
+    client.set_failover_servers(&[("localhost", alternate.local_addr().unwrap().port() as u32)]);
+    client.set_failback_interval(Duration::from_millis(300));
+    assert!(client.connect().is_ok(), "Failed to fail over to the alternate server");
+    let echo = |content: &str| client_message::Message::EchoMessage(EchoMessage { content: content.to_string() });
+    assert!(client.call(echo("Alternate")).is_ok(), "Failed to receive response for EchoMessage");
+    assert_eq!(alternate.active_client_count(), 1, "Client is not on the alternate server");
+
+    // The client falls back to the primary once it is up and the interval elapsed.
+    let primary = Arc::new(Server::new(&format!("localhost:{}", primary_port)).expect("Failed to start server"));
+    let primary_handle = setup_server_thread(primary.clone());
+    thread::sleep(Duration::from_millis(350));
+    assert!(client.call(echo("Primary")).is_ok(), "Failed to receive response for EchoMessage");
+    assert_eq!(primary.active_client_count(), 1, "Client did not fall back to the primary server");
+    assert!(wait_for_active_clients(&alternate, 0), "Client is still on the alternate server");
+
+    // A redirect without an address moves the client to the next server.
+    let hint = ReconnectHint::default();
+    let addr = client.local_addr().expect("Failed to read the client address");
+    assert!(primary.drain(addr, hint), "Client was not found");
+    thread::sleep(Duration::from_millis(50));
+    assert!(client.call(echo("Redirected")).is_ok(), "Failed to receive response for EchoMessage");
+    assert!(wait_for_active_clients(&alternate, 1), "Client was not moved to the alternate server");
+    assert_eq!(client.stats().reconnects, 2, "Client did not reconnect on each failover");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the servers and wait for threads to finish
+    primary.stop();
+    alternate.stop();
+    assert!(
+        primary_handle.join().is_ok() && alternate_handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_client_keep_warm() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    // Preconnecting opens the connection once, ahead of the first request.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.preconnect().is_ok(), "Failed to preconnect to the server");
+    assert!(client.is_connected(), "Client is not connected");
+    assert!(client.preconnect().is_ok(), "Failed to preconnect to the server");
+    assert_eq!(client.stats().reconnects, 0, "Open connection was reconnected");
+    assert!(wait_for_active_clients(&server, 1), "Client was not registered");
+
+    // The idle connection is pinged.
+    let shared = client::SharedClient::new(client);
+    shared.keep_warm(Duration::from_millis(100));
+    thread::sleep(Duration::from_millis(350));
+    let report = server.usage_report();
+    assert_eq!(report.clients.len(), 1, "Expected a single client");
+    assert!(report.clients[0].requests >= 2, "Idle connection was not pinged");
+
+    // A connection closed by the server is reopened before the next request.
+    let addr = shared.with_client(|client| client.local_addr()).expect("Failed to read the client address");
+    assert!(server.drain(addr, ReconnectHint::default()), "Client was not found");
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(shared.with_client(|client| client.stats().reconnects), 1, "Client did not reconnect");
+    assert!(wait_for_active_clients(&server, 1), "Reconnected client was not registered");
+
+    assert!(shared.with_client(|client| client.disconnect()).is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+#[test]
+fn test_client_server_reply() {
+    // Set up the server in a separate thread
+    let server = create_server();
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let reply = client.call(client_message::Message::EchoMessage(echo_message));
+    assert_eq!(reply.expect("Failed to receive response"), client::ServerReply::Echo("Hello, World!".to_string()));
+
+    let reply = client.call(client_message::Message::AddRequest(AddRequest { a: 2, b: 3 }));
+    assert_eq!(reply.expect("Failed to receive response"), client::ServerReply::Add(Ok(5)));
+
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Too slow".to_string(),
+        delay_ms: 60_000,
+    };
+    match client.call(client_message::Message::DelayedEchoRequest(delayed_echo_request)) {
+        Ok(client::ServerReply::Error { code, content }) => {
+            assert_eq!(code, ErrorCode::Unspecified, "Error code does not match");
+            assert!(content.starts_with("Delay exceeds"), "Error content does not match");
+        }
+        reply => panic!("Expected an error, but received {:?}", reply),
+    }
+
+    // Responses without a variant of their own are kept whole.
+    match client.call(client_message::Message::MyStatsRequest(MyStatsRequest {})) {
+        Ok(client::ServerReply::Unknown(ServerMessage {
+            message: Some(server_message::Message::MyStatsResponse(stats)),
+            ..
+        })) => assert_eq!(stats.requests_served, 3, "Requests served mismatch"),
+        reply => panic!("Expected MyStatsResponse, but received {:?}", reply),
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that a demo client is connected
+// to a demo server right away.
+#[test]
+fn test_demo_client() {
+    let server = Arc::new(Server::demo().expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::demo(server.local_addr().unwrap()).expect("Failed to connect to the server");
+    assert_eq!(client.echo("Hello, demo!").expect("Failed to echo"), "Hello, demo!");
+    assert_eq!(client.add(2, 40).expect("Failed to receive response for AddRequest"), Ok(42));
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // A server that cannot be reached is reported right away.
+    let unreachable = SocketAddr::from(([127, 0, 0, 1], unused_port()));
+    assert!(client::Client::demo(unreachable).is_err(), "Connected to nothing");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
@@ -0,0 +1,80 @@
+use embedded_recruitment_task::{
+    clock::{Clock, ManualClock},
+    shutdown::ShutdownToken,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn test_manual_clock_only_moves_when_advanced() {
+    let clock = ManualClock::new();
+    let start = clock.now();
+
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(clock.now(), start, "Manual clock moved on its own");
+
+    clock.advance(Duration::from_secs(3600));
+    assert_eq!(clock.now() - start, Duration::from_secs(3600), "Manual clock did not advance");
+}
+
+#[test]
+fn test_manual_clock_sleep_wakes_on_advance() {
+    let clock = Arc::new(ManualClock::new());
+    let woke = Arc::new(AtomicBool::new(false));
+
+    let sleeper = {
+        let clock = clock.clone();
+        let woke = woke.clone();
+        thread::spawn(move || {
+            clock.sleep(Duration::from_secs(10));
+            woke.store(true, Ordering::SeqCst);
+        })
+    };
+
+    // Let the sleeper compute its deadline, then advance not far enough yet.
+    thread::sleep(Duration::from_millis(50));
+    clock.advance(Duration::from_secs(5));
+    thread::sleep(Duration::from_millis(50));
+    assert!(!woke.load(Ordering::SeqCst), "Sleeper woke before its deadline");
+
+    // Past the deadline.
+    clock.advance(Duration::from_secs(5));
+    sleeper.join().unwrap();
+    assert!(woke.load(Ordering::SeqCst), "Sleeper did not wake up");
+}
+
+#[test]
+fn test_shutdown_token_interrupts_sleep() {
+    let clock = Arc::new(ManualClock::new());
+    let token = ShutdownToken::new();
+
+    // The sleep ends when the clock reaches the deadline.
+    let sleeper = {
+        let clock = clock.clone();
+        let token = token.clone();
+        thread::spawn(move || token.sleep(&*clock, Duration::from_secs(60)))
+    };
+    thread::sleep(Duration::from_millis(20));
+    clock.advance(Duration::from_secs(60));
+    assert!(!sleeper.join().unwrap(), "Sleep was reported as cancelled");
+
+    // It ends early once the token is cancelled, although the clock does not move.
+    let sleeper = {
+        let clock = clock.clone();
+        let token = token.clone();
+        thread::spawn(move || token.sleep(&*clock, Duration::from_secs(60)))
+    };
+    thread::sleep(Duration::from_millis(20));
+    token.cancel();
+    assert!(token.is_cancelled(), "Token was not cancelled");
+    assert!(token.wait_timeout(Duration::ZERO), "Cancelled token did not stop the wait");
+    // The sleeper wakes up on its next check, once the clock moved.
+    clock.advance(Duration::from_millis(10));
+    assert!(sleeper.join().unwrap(), "Sleep was not cancelled");
+}
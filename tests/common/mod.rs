@@ -0,0 +1,13 @@
+// Shared by the test binaries that run a server on a thread of their own.
+
+use embedded_recruitment_task::server::Server;
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+pub fn setup_server_thread(server: Arc<Server>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        server.run().expect("Server encountered an error");
+    })
+}
@@ -0,0 +1,53 @@
+use embedded_recruitment_task::{
+    eval::{self, MAX_DEPTH},
+    message::EvalErrorCode,
+};
+use std::collections::HashMap;
+
+fn vars() -> HashMap<String, i64> {
+    [("a".to_string(), 2), ("b".to_string(), 5), ("big_n".to_string(), i64::MAX)].into()
+}
+
+fn error(expression: &str) -> (EvalErrorCode, u32) {
+    let error = eval::evaluate(expression, &vars()).unwrap_err();
+    (error.code(), error.position)
+}
+
+#[test]
+fn test_evaluate() {
+    let cases = [
+        ("3*(a+b)", 21),
+        ("1 + 2 * 3", 7),
+        ("(1 + 2) * 3", 9),
+        ("10 - 4 - 3", 3),
+        ("17 / 5 % 2", 1),
+        ("-a * -b", 10),
+        (" --7 ", 7),
+        ("big_n - big_n", 0),
+    ];
+    for (expression, expected) in cases {
+        assert_eq!(eval::evaluate(expression, &vars()), Ok(expected), "{}", expression);
+    }
+}
+
+#[test]
+fn test_evaluate_errors() {
+    assert_eq!(error(""), (EvalErrorCode::Parse, 0));
+    assert_eq!(error("1 +"), (EvalErrorCode::Parse, 3));
+    assert_eq!(error("(1 + 2"), (EvalErrorCode::Parse, 6));
+    assert_eq!(error("1 2"), (EvalErrorCode::Parse, 2));
+    assert_eq!(error("1 $ 2"), (EvalErrorCode::Parse, 2));
+    assert_eq!(error("a + c"), (EvalErrorCode::UnknownVariable, 4));
+    assert_eq!(error("b / (a - 2)"), (EvalErrorCode::DivisionByZero, 2));
+    assert_eq!(error("b % 0"), (EvalErrorCode::DivisionByZero, 2));
+    assert_eq!(error("big_n + 1"), (EvalErrorCode::Overflow, 6));
+    assert_eq!(error("99999999999999999999"), (EvalErrorCode::Overflow, 0));
+}
+
+#[test]
+fn test_evaluate_depth_limit() {
+    let nested = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+    assert_eq!(eval::evaluate(&nested(MAX_DEPTH), &vars()), Ok(1));
+    assert_eq!(error(&nested(MAX_DEPTH + 1)).0, EvalErrorCode::Parse);
+    assert_eq!(error(&"-".repeat(10_000)).0, EvalErrorCode::Parse);
+}
@@ -0,0 +1,170 @@
+use embedded_recruitment_task::{
+    accept::AcceptErrorPolicy,
+    fd_limit::{self, FdBudget},
+    message::{server_message, ErrorCode},
+    server::Server,
+};
+use std::{
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use embedded_recruitment_task::client;
+
+// Held by the tests lowering the limit of the whole process, one at a time.
+static LIMIT_LOCK: Mutex<()> = Mutex::new(());
+
+use common::setup_server_thread;
+mod common;
+
+#[test]
+fn test_fd_budget() {
+    let budget = FdBudget::new(100, 10);
+    assert!(!budget.is_exhausted_at(89));
+    assert!(budget.is_exhausted_at(90));
+    assert!(budget.is_exhausted_at(u64::MAX));
+
+    // Each connection counts for its descriptors until its reservation is dropped.
+    let mut reservations: Vec<_> = std::iter::from_fn(|| budget.reserve()).collect();
+    assert_eq!(reservations.len() as u64, 90 / fd_limit::FDS_PER_CONNECTION, "Unexpected number of connections");
+    assert!(budget.is_exhausted());
+    reservations.pop();
+    assert!(!budget.is_exhausted());
+    assert!(budget.reserve().is_some(), "Released descriptors were not given back");
+}
+
+// The following test is aimed at checking that a detected budget reads the
+// open descriptors again rather than refusing on its estimate alone.
+#[cfg(unix)]
+#[test]
+fn test_fd_budget_recount() {
+    let _limit = LIMIT_LOCK.lock().unwrap();
+    let limit = fd_limit::soft_limit().expect("Failed to read the file descriptor limit");
+    let open = fd_limit::open_count().expect("Failed to count open file descriptors");
+    if limit == u64::MAX {
+        return;
+    }
+
+    // The estimate leaves room for a single connection, while no descriptor is opened.
+    let budget = FdBudget::detect(limit - open - 2 * fd_limit::FDS_PER_CONNECTION).expect("Failed to detect the budget");
+    let reservations: Vec<_> = (0..3).map_while(|_| budget.reserve()).collect();
+    assert_eq!(reservations.len(), 3, "Connections were refused on the estimate");
+    assert!(budget.is_exhausted(), "Recounted connections were not counted");
+}
+
+// The following test is aimed at checking that connections are refused
+// with SERVER_BUSY once few file descriptors are left. It lowers the
+// limit of the whole process, hence its own test binary.
+#[cfg(unix)]
+#[test]
+fn test_refuse_connections_near_fd_limit() {
+    let _limit = LIMIT_LOCK.lock().unwrap();
+    let open = fd_limit::open_count().expect("Failed to count open file descriptors");
+    let mut previous = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: both rlimits are valid for the calls.
+    unsafe {
+        assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut previous), 0);
+        let limit = libc::rlimit {
+            rlim_cur: (open + 40) as libc::rlim_t,
+            rlim_max: previous.rlim_max,
+        };
+        assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &limit), 0);
+    }
+
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_fd_headroom(20),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    // Each connection takes a descriptor on the client side and up to four on the server
+    // side, so a few connections exhaust the budget.
+    let mut clients = Vec::new();
+    let busy = loop {
+        assert!(clients.len() < 20, "Connections were never refused");
+        let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+        // Accepted connections get no message.
+        if let Ok(response) = client.receive_timeout(Duration::from_millis(200)) {
+            break response;
+        }
+        clients.push(client);
+    };
+    assert!(!clients.is_empty(), "The first connections should be accepted");
+    match busy.message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.code, ErrorCode::ServerBusy as i32, "Expected SERVER_BUSY");
+        }
+        _ => panic!("Expected ErrorMessage, but received a different message"),
+    }
+
+    drop(clients);
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+
+    // SAFETY: `previous` is the limit read above.
+    unsafe {
+        libc::setrlimit(libc::RLIMIT_NOFILE, &previous);
+    }
+}
+
+// The following test is aimed at checking that stopping the server is not
+// held back by the accept loop pausing after accepts failed for lack of
+// file descriptors.
+#[cfg(unix)]
+#[test]
+fn test_stop_during_accept_pause() {
+    let _limit = LIMIT_LOCK.lock().unwrap();
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_accept_error_policy(AcceptErrorPolicy::Pause {
+                after: 1,
+                duration: Duration::from_secs(60),
+            }),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    // Leave a single descriptor, taken by the client, so that the server fails to accept.
+    let open = fd_limit::open_count().expect("Failed to count open file descriptors");
+    let mut previous = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: both rlimits are valid for the calls.
+    unsafe {
+        assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut previous), 0);
+        let limit = libc::rlimit {
+            rlim_cur: (open + 1) as libc::rlim_t,
+            rlim_max: previous.rlim_max,
+        };
+        assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &limit), 0);
+    }
+    let stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.accept_error_stats().pauses == 0 {
+        assert!(Instant::now() < deadline, "The accept loop did not pause");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // SAFETY: `previous` is the limit read above.
+    unsafe {
+        libc::setrlimit(libc::RLIMIT_NOFILE, &previous);
+    }
+
+    // Stop the server and wait for thread to finish, well before the end of the pause.
+    let start = Instant::now();
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    assert!(start.elapsed() < Duration::from_secs(5), "Stop waited for the accept pause");
+    drop(stream);
+}
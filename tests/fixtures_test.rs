@@ -0,0 +1,28 @@
+use embedded_recruitment_task::{
+    fixtures::{self, Fixture, WireMessage},
+    message::{client_message, AddRequest, ClientMessage},
+};
+
+#[test]
+fn test_fixtures_compatible() {
+    let mismatches = fixtures::verify();
+    assert!(mismatches.is_empty(), "Incompatible fixtures: {:#?}", mismatches);
+}
+
+#[test]
+fn test_fixture_mismatch_detected() {
+    // As if the operands of AddRequest had been swapped in the proto file.
+    let fixture = Fixture {
+        name: "swapped add",
+        encoded: &[0x12, 0x04, 0x10, 0x01, 0x08, 0x02],
+        message: || {
+            WireMessage::Client(ClientMessage {
+                message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 })),
+                ..Default::default()
+            })
+        },
+    };
+
+    let error = fixture.check().unwrap_err();
+    assert!(error.starts_with("swapped add encodes to"), "Unexpected error: {}", error);
+}
@@ -0,0 +1,265 @@
+use embedded_recruitment_task::framing::{
+    encode_frame, is_unframed_start, read_frame, write_frame, DecoderState, FrameDecoder, FrameFormat, DEFAULT_MAX_FRAME_LEN,
+    FRAGMENT_FLAG, FRAGMENT_HEADER_LEN, HEADER_LEN, KEEPALIVE_FRAME, SYNC_MARKER,
+};
+
+// A few payloads of various sizes, including an empty one.
+fn sample_payloads() -> Vec<Vec<u8>> {
+    vec![
+        b"Hello, World!".to_vec(),
+        Vec::new(),
+        vec![0xde, 0xad, 0xbe, 0xef],
+        (0..1000).map(|i| (i % 251) as u8).collect(),
+    ]
+}
+
+fn encode_all(payloads: &[Vec<u8>]) -> Vec<u8> {
+    payloads.iter().flat_map(|payload| encode_frame(payload)).collect()
+}
+
+#[test]
+fn test_decode_whole_stream() {
+    let payloads = sample_payloads();
+    let mut decoder = FrameDecoder::new();
+
+    let frames = decoder.feed(&encode_all(&payloads)).expect("Failed to decode frames");
+    assert_eq!(frames, payloads, "Decoded frames do not match");
+    assert!(decoder.is_idle(), "Decoder holds a partial frame");
+}
+
+#[test]
+fn test_decode_byte_at_a_time() {
+    let payloads = sample_payloads();
+    let mut decoder = FrameDecoder::new();
+
+    let mut frames = Vec::new();
+    for byte in encode_all(&payloads) {
+        frames.extend(decoder.feed(&[byte]).expect("Failed to decode frames"));
+    }
+    assert_eq!(frames, payloads, "Decoded frames do not match");
+    assert!(decoder.is_idle(), "Decoder holds a partial frame");
+}
+
+#[test]
+fn test_decode_random_splits() {
+    let payloads = sample_payloads();
+    let stream = encode_all(&payloads);
+
+    // A small linear congruential generator keeps the splits reproducible.
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    for _ in 0..100 {
+        let mut decoder = FrameDecoder::new();
+        let mut frames = Vec::new();
+        let mut rest = &stream[..];
+        while !rest.is_empty() {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let len = ((seed >> 33) as usize % 64 + 1).min(rest.len());
+            frames.extend(decoder.feed(&rest[..len]).expect("Failed to decode frames"));
+            rest = &rest[len..];
+        }
+        assert_eq!(frames, payloads, "Decoded frames do not match");
+    }
+}
+
+#[test]
+fn test_decoder_states() {
+    let mut decoder = FrameDecoder::new();
+    let frame = encode_frame(b"abc");
+
+    assert!(decoder.feed(&frame[..2]).unwrap().is_empty());
+    assert_eq!(decoder.state(), DecoderState::ReadingHeader { filled: 2 });
+
+    assert!(decoder.feed(&frame[2..HEADER_LEN + 1]).unwrap().is_empty());
+    assert_eq!(decoder.state(), DecoderState::ReadingBody { remaining: 2 });
+
+    assert_eq!(decoder.feed(&frame[HEADER_LEN + 1..]).unwrap(), vec![b"abc".to_vec()]);
+    assert_eq!(decoder.state(), DecoderState::ReadingHeader { filled: 0 });
+}
+
+#[test]
+fn test_oversized_frame_rejected() {
+    let mut decoder = FrameDecoder::with_max_frame_len(16);
+    let frame = encode_frame(&[0; 17]);
+
+    assert!(decoder.feed(&frame).is_err(), "Oversized frame was accepted");
+}
+
+#[test]
+fn test_write_and_read_frames() {
+    let payloads = sample_payloads();
+    let mut stream = Vec::new();
+    for payload in &payloads {
+        let written = write_frame(&mut stream, payload).expect("Failed to write frame");
+        assert_eq!(written, HEADER_LEN + payload.len(), "Written length mismatch");
+    }
+
+    let mut reader = stream.as_slice();
+    for payload in &payloads {
+        let frame = read_frame(&mut reader, DEFAULT_MAX_FRAME_LEN).expect("Failed to read frame");
+        assert_eq!(frame.as_ref(), Some(payload), "Read frame does not match");
+    }
+    assert_eq!(read_frame(&mut reader, DEFAULT_MAX_FRAME_LEN).unwrap(), None, "Expected the end of the stream");
+
+    let oversized = encode_frame(&[0; 17]);
+    assert!(read_frame(oversized.as_slice(), 16).is_err(), "Oversized frame was accepted");
+}
+
+#[test]
+fn test_sync_marker_resynchronization() {
+    let payloads = sample_payloads();
+    let format = FrameFormat::SYNCED;
+
+    // Garbage before the first frame, between two frames, and a marker cut short.
+    let garbage: [&[u8]; 3] = [b"noise", &[SYNC_MARKER[0], SYNC_MARKER[1], 0x00], &[SYNC_MARKER[0]; 3]];
+    let mut stream = Vec::new();
+    let mut skipped = 0;
+    for (i, payload) in payloads.iter().enumerate() {
+        if let Some(garbage) = garbage.get(i) {
+            stream.extend_from_slice(garbage);
+            skipped += garbage.len() as u64;
+        }
+        stream.extend(format.encode(payload));
+    }
+
+    for chunk_len in [1, 7, stream.len()] {
+        let mut decoder = format.decoder(DEFAULT_MAX_FRAME_LEN);
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(chunk_len) {
+            frames.extend(decoder.feed(chunk).expect("Failed to decode frames"));
+        }
+        assert_eq!(frames, payloads, "Decoded frames do not match");
+        assert_eq!(decoder.resyncs(), 3, "Unexpected number of resyncs");
+        assert_eq!(decoder.skipped_bytes(), skipped, "Unexpected number of skipped bytes");
+        assert!(decoder.is_idle(), "Decoder holds a partial frame");
+    }
+}
+
+#[test]
+fn test_sync_marker_oversized_frame_skipped() {
+    let format = FrameFormat::SYNCED;
+    let mut decoder = format.decoder(16);
+    assert_eq!(decoder.state(), DecoderState::ReadingMarker { matched: 0 });
+
+    // Without markers the stream would be lost, with them the next frame is found.
+    let mut stream = format.encode(&[0; 17]);
+    stream.extend(format.encode(b"abc"));
+    assert_eq!(decoder.feed(&stream).unwrap(), vec![b"abc".to_vec()]);
+    assert_eq!(decoder.resyncs(), 1, "Oversized frame was not skipped");
+
+    let mut written = Vec::new();
+    let len = format.write(&mut written, b"abc").expect("Failed to write frame");
+    assert_eq!(len, SYNC_MARKER.len() + HEADER_LEN + 3, "Written length mismatch");
+    assert_eq!(written, [&SYNC_MARKER[..], &encode_frame(b"abc")].concat());
+}
+
+#[test]
+fn test_keepalives() {
+    // Keepalives are counted rather than returned, and an empty frame is an empty message.
+    let mut decoder = FrameDecoder::new();
+    let stream = [&KEEPALIVE_FRAME[..], &encode_frame(b"abc"), &encode_frame(&[]), &KEEPALIVE_FRAME].concat();
+    assert_eq!(decoder.feed(&stream).unwrap(), vec![b"abc".to_vec(), Vec::new()]);
+    assert_eq!(decoder.keepalives(), 2, "Keepalives were not counted");
+    assert!(decoder.is_idle());
+
+    // Decoded one at a time, up to the keepalive only.
+    let mut decoder = FrameDecoder::new();
+    assert_eq!(decoder.feed_frame(&stream).unwrap(), (None, HEADER_LEN));
+    assert_eq!(decoder.keepalives(), 1);
+
+    // With sync markers, and never starting like an unframed message.
+    let keepalive = FrameFormat::SYNCED.encode_keepalive();
+    assert_eq!(keepalive, [&SYNC_MARKER[..], &KEEPALIVE_FRAME].concat());
+    let mut decoder = FrameFormat::SYNCED.decoder(DEFAULT_MAX_FRAME_LEN);
+    assert!(decoder.feed(&keepalive).unwrap().is_empty());
+    assert_eq!((decoder.keepalives(), decoder.resyncs()), (1, 0));
+    assert!(!is_unframed_start(KEEPALIVE_FRAME[0]));
+    assert!(FrameFormat::UNFRAMED.encode_keepalive().is_empty(), "Unframed keepalive cannot be told from a message");
+
+    // Skipped by the blocking reads.
+    let mut written = Vec::new();
+    FrameFormat::default().write_keepalive(&mut written).expect("Failed to write keepalive");
+    written.extend(encode_frame(b"abc"));
+    assert_eq!(read_frame(written.as_slice(), DEFAULT_MAX_FRAME_LEN).unwrap(), Some(b"abc".to_vec()));
+}
+
+#[test]
+fn test_unframed_format() {
+    // Framed streams never start like an unframed message.
+    assert!(!is_unframed_start(encode_frame(b"Hello")[0]));
+    assert!(!is_unframed_start(FrameFormat::SYNCED.encode(b"Hello")[0]));
+    assert!(is_unframed_start(0x0a), "The tag of the first field was not recognized");
+
+    assert_eq!(FrameFormat::UNFRAMED.encode(b"Hello"), b"Hello");
+    let mut decoder = FrameFormat::UNFRAMED.decoder(DEFAULT_MAX_FRAME_LEN);
+    assert_eq!(decoder.feed(b"Hello").unwrap(), vec![b"Hello".to_vec()], "Each input is a whole message");
+    assert!(decoder.feed(b"").unwrap().is_empty());
+    assert!(decoder.is_idle());
+}
+
+#[test]
+fn test_fragmentation() {
+    let payloads = sample_payloads();
+    for sync_marker in [false, true] {
+        let format = FrameFormat {
+            sync_marker,
+            fragment_len: Some(100),
+            ..FrameFormat::default()
+        };
+        let stream: Vec<u8> = payloads.iter().flat_map(|payload| format.encode(payload)).collect();
+
+        // Each fragment fits a frame of 100 bytes and its header.
+        let max_frame_len = 100 + FRAGMENT_HEADER_LEN;
+        for chunk_len in [1, 7, stream.len()] {
+            let mut decoder = format.decoder(max_frame_len);
+            let mut frames = Vec::new();
+            for chunk in stream.chunks(chunk_len) {
+                frames.extend(decoder.feed(chunk).expect("Failed to decode frames"));
+            }
+            assert_eq!(frames, payloads, "Reassembled messages do not match");
+            assert!(decoder.is_idle(), "Decoder holds a partial message");
+        }
+    }
+
+    // The short payloads are not fragmented, the long ones are.
+    let format = FrameFormat {
+        fragment_len: Some(100),
+        ..FrameFormat::default()
+    };
+    assert_eq!(format.encode(b"abc"), encode_frame(b"abc"));
+    let fragments = format.encode(&[0; 250]);
+    assert_eq!(fragments.len(), 250 + 3 * (HEADER_LEN + FRAGMENT_HEADER_LEN), "Expected 3 fragments");
+    let prefix = u32::from_be_bytes(fragments[..HEADER_LEN].try_into().unwrap());
+    assert_eq!(prefix, FRAGMENT_FLAG | (FRAGMENT_HEADER_LEN + 100) as u32);
+}
+
+#[test]
+fn test_fragmentation_errors() {
+    let format = FrameFormat {
+        fragment_len: Some(10),
+        ..FrameFormat::default()
+    };
+    let first_len = HEADER_LEN + FRAGMENT_HEADER_LEN + 10;
+
+    // A fragment without the start of its message.
+    let fragments = format.encode(&[0; 30]);
+    assert!(FrameDecoder::new().feed(&fragments[first_len..]).is_err(), "Fragment out of order was accepted");
+
+    // The fragments of two messages interleaved.
+    let other = format.encode(&[1; 30]);
+    let mut stream = fragments[..first_len].to_vec();
+    stream.extend_from_slice(&other[first_len..]);
+    assert!(FrameDecoder::new().feed(&stream).is_err(), "Fragments of another message were accepted");
+
+    // A message larger than the limit, however small its fragments.
+    let mut decoder = FrameDecoder::new().with_max_message_len(20);
+    assert!(decoder.feed(&fragments).is_err(), "Oversized message was accepted");
+
+    // A fragment too short for its header, skipped up to the next frame with sync markers.
+    let short = (FRAGMENT_FLAG | (FRAGMENT_HEADER_LEN - 1) as u32).to_be_bytes();
+    assert!(FrameDecoder::new().feed(&short).is_err(), "Fragment without header was accepted");
+    let format = FrameFormat::SYNCED;
+    let mut decoder = format.decoder(DEFAULT_MAX_FRAME_LEN);
+    let stream = [&SYNC_MARKER[..], &short, &format.encode(b"abc")].concat();
+    assert_eq!(decoder.feed(&stream).unwrap(), vec![b"abc".to_vec()]);
+    assert_eq!(decoder.resyncs(), 1, "Fragment without header was not skipped");
+}
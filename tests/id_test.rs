@@ -0,0 +1,93 @@
+use embedded_recruitment_task::{
+    id::{IdGenerator, SnowflakeIdGenerator, MAX_NODE_ID},
+    message::{client_message, EchoMessage},
+    server::Server,
+    trace_context::{TraceContext, TRACEPARENT},
+};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use embedded_recruitment_task::client;
+
+use common::setup_server_thread;
+mod common;
+
+// Hands out consecutive ids, so that tests can tell where each one went.
+struct SequentialIds(AtomicU64);
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+#[test]
+fn test_snowflake_ids() {
+    let generator = Arc::new(SnowflakeIdGenerator::new(MAX_NODE_ID));
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+    // Ids are unique across threads, and increase within each thread.
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let generator = generator.clone();
+            thread::spawn(move || {
+                let ids: Vec<u64> = (0..10_000).map(|_| generator.next_id()).collect();
+                assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "Ids are not time-ordered");
+                ids
+            })
+        })
+        .collect();
+    let ids: Vec<u64> = threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect();
+    assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len(), "Ids are not unique");
+
+    // Ids tell when and where they were generated.
+    let id = ids[0];
+    assert_eq!(SnowflakeIdGenerator::node_id(id), MAX_NODE_ID);
+    assert!(SnowflakeIdGenerator::timestamp_ms(id).abs_diff(now_ms) < 1000, "Timestamp is off");
+}
+
+#[test]
+fn test_server_id_generator() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_id_generator(Arc::new(SequentialIds(AtomicU64::new(1000)))),
+    );
+    let job_id = server.spawn_background("idle", |token| token.wait()).expect("Failed to schedule the job");
+    assert_eq!(job_id, 1000, "Job id was not generated");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client.set_metadata(
+        [(TRACEPARENT.to_string(), "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string())].into(),
+    );
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let response = client
+        .call_message(client_message::Message::EchoMessage(echo_message))
+        .expect("Failed to receive response for EchoMessage");
+
+    // The session, then the span of the server, took the next ids.
+    let addr = client.local_addr().expect("Failed to read the client address");
+    assert_eq!(server.active_sessions(), vec![(1001, addr)], "Session id was not generated");
+    let trace = TraceContext::from_metadata(&response.metadata).expect("Trace context was not propagated");
+    assert_eq!(trace.parent_id, 1002, "Span id was not generated");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
@@ -0,0 +1,111 @@
+use embedded_recruitment_task::{
+    json::{self, Json},
+    message::{client_message, server_message, AddErrorCode, BatchRequest, ClientMessage, EchoMessage, ErrorCode, ErrorMessage, ServerMessage, ShutdownReason},
+};
+use std::io::ErrorKind;
+
+#[test]
+fn test_json_round_trip() {
+    let message = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "Hello, World!".to_string(),
+        })),
+        ..Default::default()
+    };
+
+    let json = message.to_json().expect("Failed to render the message");
+    assert_eq!(json, r#"{"echoMessage":{"content":"Hello, World!"}}"#);
+    assert_eq!(ClientMessage::from_json(&json).expect("Failed to parse the message"), message);
+
+    // Metadata is only rendered when set.
+    let message = ClientMessage {
+        metadata: [("tenant".to_string(), "acme".to_string())].into(),
+        ..message
+    };
+    let json = message.to_json().expect("Failed to render the message");
+    assert_eq!(json, r#"{"metadata":{"tenant":"acme"},"echoMessage":{"content":"Hello, World!"}}"#);
+    assert_eq!(ClientMessage::from_json(&json).expect("Failed to parse the message"), message);
+
+    // So is the request id.
+    let message = ClientMessage {
+        request_id: Some(7),
+        metadata: Default::default(),
+        ..message
+    };
+    let json = message.to_json().expect("Failed to render the message");
+    assert_eq!(json, r#"{"requestId":7,"echoMessage":{"content":"Hello, World!"}}"#);
+    assert_eq!(ClientMessage::from_json(&json).expect("Failed to parse the message"), message);
+}
+
+#[test]
+fn test_json_enum_by_name() {
+    let message = ServerMessage {
+        message: Some(server_message::Message::ErrorMessage(ErrorMessage {
+            content: "Server is shutting down.".to_string(),
+            shutdown_reason: ShutdownReason::Signal as i32,
+            code: ErrorCode::ServerBusy as i32,
+            session_summary: None,
+        })),
+        ..Default::default()
+    };
+
+    let json = message.to_json().expect("Failed to render the message");
+    assert_eq!(
+        json,
+        r#"{"errorMessage":{"content":"Server is shutting down.","shutdownReason":"SHUTDOWN_REASON_SIGNAL","code":"ERROR_CODE_SERVER_BUSY"}}"#
+    );
+    assert_eq!(ServerMessage::from_json(&json).expect("Failed to parse the message"), message);
+
+    // Values unknown to this version are kept as numbers.
+    let json = r#"{"errorMessage":{"shutdownReason":42}}"#;
+    let message = ServerMessage::from_json(json).expect("Failed to parse the message");
+    assert_eq!(message.to_json().expect("Failed to render the message"), r#"{"errorMessage":{"content":"","shutdownReason":42,"code":"ERROR_CODE_UNSPECIFIED"}}"#);
+}
+
+#[test]
+fn test_json_defaults_and_errors() {
+    // Missing fields take their default value.
+    let message = ClientMessage::from_json(r#"{"addRequest":{"a":1}}"#).expect("Failed to parse the message");
+    match message.message {
+        Some(client_message::Message::AddRequest(add_request)) => {
+            assert_eq!(add_request.a, 1);
+            assert_eq!(add_request.b, 0);
+        }
+        _ => panic!("Expected AddRequest, but parsed a different message"),
+    }
+
+    let error = ClientMessage::from_json(r#"{"addRequest":{"a":"one"}}"#).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_redacted_json() {
+    let echo = client_message::Message::EchoMessage(EchoMessage {
+        content: "secret".to_string(),
+    });
+    let message = client_message::Message::BatchRequest(BatchRequest {
+        requests: vec![ClientMessage { message: Some(echo), ..Default::default() }],
+    });
+
+    // Fields are masked at any depth.
+    let json = json::to_redacted_json(&message, &["content".to_string()]).expect("Failed to render the message");
+    assert_eq!(json, r#"{"batchRequest":{"requests":[{"echoMessage":{"content":"[REDACTED]"}}]}}"#);
+
+    let json = json::to_redacted_json(&message, &[]).expect("Failed to render the message");
+    assert!(json.contains("secret"), "Fields should only be masked when configured");
+}
+
+#[test]
+fn test_json_add_response() {
+    let message = ServerMessage::from_json(r#"{"addResponse":{"error":{"code":"ADD_ERROR_CODE_OVERFLOW","detail":"overflow"}}}"#)
+        .expect("Failed to parse the message");
+    match message.message {
+        Some(server_message::Message::AddResponse(add_response)) => {
+            assert_eq!(add_response.to_result().unwrap_err().code, AddErrorCode::Overflow);
+        }
+        _ => panic!("Expected AddResponse, but parsed a different message"),
+    }
+
+    let message = ServerMessage::from_json(r#"{"addResponse":{"result":3}}"#).expect("Failed to parse the message");
+    assert_eq!(message.to_json().expect("Failed to render the message"), r#"{"addResponse":{"result":3}}"#);
+}
@@ -0,0 +1,137 @@
+use embedded_recruitment_task::{
+    memory::MemoryBudget,
+    message::{client_message, server_message, DelayedEchoRequest, EchoMessage, ErrorCode, ServerMessage},
+    server::{Server, DEFAULT_READ_BUFFER_SIZE},
+};
+use prost::Message;
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use embedded_recruitment_task::client;
+
+use common::setup_server_thread;
+mod common;
+
+// The memory the server holds for a delayed echo of `content` until it is sent.
+fn echo_len(content: &str) -> u64 {
+    let response = ServerMessage {
+        message: Some(server_message::Message::EchoMessage(EchoMessage {
+            content: content.to_string(),
+        })),
+        ..Default::default()
+    };
+    response.encoded_len() as u64
+}
+
+fn delayed_echo(content: &str, delay_ms: u32) -> client_message::Message {
+    client_message::Message::DelayedEchoRequest(DelayedEchoRequest {
+        content: content.to_string(),
+        delay_ms,
+    })
+}
+
+#[test]
+fn test_memory_budget() {
+    let budget = MemoryBudget::new(100);
+    let mut first = budget.reserve(60).expect("Failed to reserve within the budget");
+    assert!(budget.reserve(41).is_none(), "Reserved beyond the budget");
+    first.merge(budget.reserve(40).expect("Failed to reserve the rest of the budget"));
+    assert_eq!((first.bytes(), budget.used()), (100, 100));
+    assert!(budget.is_exhausted());
+
+    // Each reservation gives its bytes back once dropped, merged ones included.
+    drop(first);
+    assert_eq!(budget.used(), 0, "Released bytes were not given back");
+    assert!(!budget.is_exhausted());
+}
+
+// The following test is aimed at checking that the connections are refused
+// with SERVER_BUSY once the memory budget cannot hold their read buffer.
+#[test]
+fn test_memory_budget_refuses_connections() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_memory_budget(DEFAULT_READ_BUFFER_SIZE as u64 + 10),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut accepted = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(accepted.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(accepted.echo("Hello, World!").unwrap(), "Hello, World!");
+
+    let mut refused = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(refused.connect().is_ok(), "Failed to connect to the server");
+    match refused.receive_timeout(Duration::from_secs(1)).expect("Expected a refusal").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.code, ErrorCode::ServerBusy as i32, "Expected SERVER_BUSY");
+        }
+        _ => panic!("Expected ErrorMessage, but received a different message"),
+    }
+
+    assert!(accepted.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    assert!(server.leaks().is_empty(), "Memory was not given back: {:?}", server.leaks());
+}
+
+// The following test is aimed at checking that, once the memory budget is
+// exhausted, the delayed echoes of the other clients are rejected with
+// RESOURCE_EXHAUSTED, and the client holding the memory is not read until
+// its echo is sent.
+#[test]
+fn test_memory_budget_exhausted() {
+    let read_buffers = 2 * DEFAULT_READ_BUFFER_SIZE as u64;
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_memory_budget(read_buffers + echo_len("Slow")),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut holder = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(holder.connect().is_ok(), "Failed to connect to the server");
+    let mut other = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(other.connect().is_ok(), "Failed to connect to the server");
+
+    // The delayed echo takes what is left of the budget until it is sent.
+    let start = Instant::now();
+    assert!(holder.send(delayed_echo("Slow", 200)).is_ok(), "Failed to send DelayedEchoRequest");
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(server.memory_used(), Some(read_buffers + echo_len("Slow")));
+
+    match other.call_message(delayed_echo("Never", 10)).expect("Failed to receive the rejection").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.code, ErrorCode::ResourceExhausted as i32, "Expected RESOURCE_EXHAUSTED");
+        }
+        _ => panic!("Expected ErrorMessage, but received a different message"),
+    }
+
+    // The echo sent meanwhile is only read once the delayed one went out.
+    assert!(holder.send(client_message::Message::EchoMessage(EchoMessage {
+        content: "Fast".to_string(),
+    })).is_ok(), "Failed to send EchoMessage");
+    for content in ["Slow", "Fast"] {
+        match holder.receive().expect("Failed to receive the echo").message {
+            Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, content, "Echoes out of order"),
+            _ => panic!("Expected EchoMessage, but received a different message"),
+        }
+    }
+    assert!(start.elapsed() >= Duration::from_millis(200), "Delayed echo was sent early");
+    assert_eq!(server.memory_used(), Some(read_buffers), "The delayed echo did not give its memory back");
+
+    assert!(holder.disconnect().is_ok(), "Failed to disconnect from the server");
+    assert!(other.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    assert!(server.leaks().is_empty(), "Memory was not given back: {:?}", server.leaks());
+}
@@ -0,0 +1,208 @@
+// Not every test binary uses every part of the DSL.
+#![allow(dead_code)]
+
+use crate::client::Client;
+use embedded_recruitment_task::{
+    message::{client_message, server_message, AddRequest, EchoMessage, ServerMessage},
+    server::Server,
+};
+use std::{collections::HashMap, sync::Arc, thread};
+
+// checks a response, panicking with a descriptive message when it is not the expected one
+type Expectation = Box<dyn Fn(&ServerMessage) + Send>;
+
+pub enum Action {
+    Send(client_message::Message),
+    Expect(Expectation),
+}
+
+// the requests sent and the responses expected by one client, in order
+pub struct Script {
+    client: String,
+    actions: Vec<Action>,
+}
+
+// a script for the client named `client`, connected on first use and kept across steps
+pub fn script(client: &str) -> Script {
+    Script {
+        client: client.to_string(),
+        actions: Vec::new(),
+    }
+}
+
+// builds the actions of a script, shared by `Script` and `Scenario`
+pub trait Steps: Sized {
+    fn action(self, action: Action) -> Self;
+
+    fn send(self, message: client_message::Message) -> Self {
+        self.action(Action::Send(message))
+    }
+
+    fn send_echo(self, content: &str) -> Self {
+        self.send(client_message::Message::EchoMessage(EchoMessage {
+            content: content.to_string(),
+        }))
+    }
+
+    fn send_add(self, a: i32, b: i32) -> Self {
+        self.send(client_message::Message::AddRequest(AddRequest { a, b }))
+    }
+
+    fn expect(self, expectation: impl Fn(&ServerMessage) + Send + 'static) -> Self {
+        self.action(Action::Expect(Box::new(expectation)))
+    }
+
+    fn expect_echo(self, content: &str) -> Self {
+        let content = content.to_string();
+        self.expect(move |response| match &response.message {
+            Some(server_message::Message::EchoMessage(echo)) => {
+                assert_eq!(echo.content, content, "Echoed message content does not match")
+            }
+            message => panic!("Expected EchoMessage, but received {:?}", message),
+        })
+    }
+
+    fn expect_add(self, result: i32) -> Self {
+        self.expect(move |response| match &response.message {
+            Some(server_message::Message::AddResponse(add_response)) => {
+                assert_eq!(add_response.to_result(), Ok(result), "AddResponse result does not match")
+            }
+            message => panic!("Expected AddResponse, but received {:?}", message),
+        })
+    }
+
+    fn expect_error(self) -> Self {
+        self.expect(|response| {
+            assert!(
+                matches!(response.message, Some(server_message::Message::ErrorMessage(_))),
+                "Expected ErrorMessage, but received {:?}",
+                response.message
+            )
+        })
+    }
+
+    // send an echo request and expect it back
+    fn echo(self, content: &str) -> Self {
+        self.send_echo(content).expect_echo(content)
+    }
+
+    // send an add request and expect the sum back
+    fn add(self, a: i32, b: i32) -> Self {
+        self.send_add(a, b).expect_add(a + b)
+    }
+}
+
+impl Steps for Script {
+    fn action(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+}
+
+impl Script {
+    fn run(self, client: &mut Client) {
+        for action in self.actions {
+            match action {
+                Action::Send(message) => {
+                    assert!(client.send(message).is_ok(), "{}: failed to send message", self.client)
+                }
+                Action::Expect(expectation) => {
+                    let response = client.receive();
+                    assert!(response.is_ok(), "{}: failed to receive response", self.client);
+                    expectation(&response.unwrap());
+                }
+            }
+        }
+    }
+}
+
+// drives a real server and clients through phases of scripts, each phase running its
+// scripts concurrently once the previous phase completed
+pub struct Scenario {
+    configure: Box<dyn FnOnce(Server) -> Server>,
+    phases: Vec<Vec<Script>>,
+}
+
+pub fn scenario() -> Scenario {
+    Scenario {
+        configure: Box::new(|server| server),
+        phases: Vec::new(),
+    }
+}
+
+impl Steps for Scenario {
+    // extends the script started by the last `client()`
+    fn action(mut self, action: Action) -> Self {
+        let script = self
+            .phases
+            .last_mut()
+            .and_then(|phase| phase.pop())
+            .expect("Call client() before adding steps");
+        self.phases.last_mut().unwrap().push(script.action(action));
+        self
+    }
+}
+
+impl Scenario {
+    // configure the server before it runs
+    pub fn server(mut self, configure: impl FnOnce(Server) -> Server + 'static) -> Self {
+        self.configure = Box::new(configure);
+        self
+    }
+
+    // start a phase in which the client named `client` runs the following steps
+    pub fn client(mut self, client: &str) -> Self {
+        self.phases.push(vec![script(client)]);
+        self
+    }
+
+    // start a phase running `scripts` at the same time, each on its own thread
+    pub fn concurrently(mut self, scripts: Vec<Script>) -> Self {
+        self.phases.push(scripts);
+        self
+    }
+
+    // run the scenario, then disconnect the clients and check that the server released
+    // everything once stopped
+    pub fn run(self) {
+        let server = Arc::new((self.configure)(Server::new("localhost:0").expect("Failed to start server")));
+        let handle = {
+            let server = server.clone();
+            thread::spawn(move || server.run().expect("Server encountered an error"))
+        };
+
+        let addr = server.local_addr().expect("Failed to read the server address");
+        let mut clients: HashMap<String, Client> = HashMap::new();
+        for phase in self.phases {
+            let threads: Vec<_> = phase
+                .into_iter()
+                .map(|script| {
+                    let mut client = clients.remove(&script.client).unwrap_or_else(|| {
+                        let mut client = Client::with_addr(addr, 1000);
+                        assert!(client.connect().is_ok(), "{}: failed to connect to the server", script.client);
+                        client
+                    });
+                    thread::spawn(move || {
+                        let name = script.client.clone();
+                        script.run(&mut client);
+                        (name, client)
+                    })
+                })
+                .collect();
+
+            for thread in threads {
+                let (name, client) = thread.join().expect("Client script failed");
+                clients.insert(name, client);
+            }
+        }
+
+        for (name, mut client) in clients {
+            assert!(client.disconnect().is_ok(), "{}: failed to disconnect from the server", name);
+        }
+
+        // Stop the server and wait for thread to finish
+        server.stop();
+        assert!(handle.join().is_ok(), "Server thread panicked or failed to join");
+        server.assert_quiesced();
+    }
+}
@@ -0,0 +1,91 @@
+use embedded_recruitment_task::message::{
+    client_message, eval_response, server_message, DelayedEchoRequest, EvalErrorCode, EvalRequest, MyStatsRequest,
+};
+use scenario::{scenario, script, Steps};
+use std::time::Duration;
+
+use embedded_recruitment_task::client;
+mod scenario;
+
+#[test]
+fn test_scenario_sequential() {
+    scenario()
+        .client("a")
+        .echo("Hello, World!")
+        .add(10, 20)
+        .client("b")
+        .echo("How are you?")
+        // Clients keep their connection across phases.
+        .client("a")
+        .send(client_message::Message::MyStatsRequest(MyStatsRequest {}))
+        .expect(|response| match &response.message {
+            Some(server_message::Message::MyStatsResponse(stats)) => {
+                assert_eq!(stats.requests_served, 2, "Requests served mismatch")
+            }
+            message => panic!("Expected MyStatsResponse, but received {:?}", message),
+        })
+        .run();
+}
+
+#[test]
+fn test_scenario_concurrent() {
+    scenario()
+        .concurrently(
+            (0..10)
+                .map(|i| {
+                    let client = format!("client-{}", i);
+                    if i % 2 == 0 {
+                        script(&client).echo(&format!("Hello, World From Client {}!", i))
+                    } else {
+                        script(&client).add(i, i * 2)
+                    }
+                })
+                .collect(),
+        )
+        .run();
+}
+
+#[test]
+fn test_scenario_server_configuration() {
+    scenario()
+        .server(|server| server.with_max_echo_delay(Duration::from_millis(10)))
+        .client("a")
+        .send(client_message::Message::DelayedEchoRequest(DelayedEchoRequest {
+            content: "Too slow".to_string(),
+            delay_ms: 100,
+        }))
+        .expect_error()
+        .echo("Still served")
+        .run();
+}
+
+#[test]
+fn test_scenario_eval() {
+    let eval = |expression: &str| {
+        client_message::Message::EvalRequest(EvalRequest {
+            expression: expression.to_string(),
+            vars: [("a".to_string(), 1), ("b".to_string(), 2)].into(),
+        })
+    };
+
+    scenario()
+        .client("a")
+        .send(eval("3*(a+b)"))
+        .expect(|response| match &response.message {
+            Some(server_message::Message::EvalResponse(eval)) => {
+                assert_eq!(eval.outcome, Some(eval_response::Outcome::Result(9)), "Eval result does not match")
+            }
+            message => panic!("Expected EvalResponse, but received {:?}", message),
+        })
+        .send(eval("a / (b - 2)"))
+        .expect(|response| match &response.message {
+            Some(server_message::Message::EvalResponse(eval)) => match &eval.outcome {
+                Some(eval_response::Outcome::Error(error)) => {
+                    assert_eq!(error.code(), EvalErrorCode::DivisionByZero, "Eval error does not match")
+                }
+                outcome => panic!("Expected an eval error, but received {:?}", outcome),
+            },
+            message => panic!("Expected EvalResponse, but received {:?}", message),
+        })
+        .run();
+}
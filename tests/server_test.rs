@@ -0,0 +1,1897 @@
+use embedded_recruitment_task::{
+    bind::BindPolicy,
+    clock::ManualClock,
+    framing::{encode_frame, read_frame, FrameFormat, DEFAULT_MAX_FRAME_LEN, FRAGMENT_FLAG, HEADER_LEN, KEEPALIVE_FRAME},
+    message::{client_message, server_message, AddRequest, BatchRequest, ByeMessage, ClientMessage, DelayedEchoRequest, DescriptorRequest, EchoMessage, ErrorCode, ErrorMessage, MyStatsRequest, ReconnectHint, ServerMessage, ShutdownReason, Transport, UpgradeRequest},
+    server::{ResponsePostProcessor, Server, DEPRECATED, PROXY_HEADER_TIMEOUT},
+    time_scale,
+    trace_context::{TraceContext, TRACEPARENT, TRACESTATE},
+};
+use prost::Message;
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use embedded_recruitment_task::client;
+
+use common::setup_server_thread;
+mod common;
+
+// The following test is aimed at checking that the server falls back
+// to an alternate address when its address is busy.
+#[test]
+fn test_bind_fallback_address() {
+    // Occupy the preferred address.
+    let busy = TcpListener::bind("localhost:0").expect("Failed to occupy the address");
+    let busy_addr = busy.local_addr().unwrap();
+
+    let policy = BindPolicy {
+        fallback_addrs: vec!["localhost:0".to_string()],
+        ..BindPolicy::default()
+    };
+    let server = Arc::new(
+        Server::with_bind_policy(&busy_addr.to_string(), &policy).expect("Failed to start server"),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    // The server must be reachable on the fallback address.
+    assert_ne!(server.local_addr().unwrap(), busy_addr, "Server did not fall back");
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the server keeps retrying
+// a busy address until it is released.
+#[test]
+fn test_bind_retry() {
+    // Occupy the address for a short while.
+    let busy = TcpListener::bind("localhost:0").expect("Failed to occupy the address");
+    let addr = busy.local_addr().unwrap().to_string();
+    let release_thread = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        drop(busy);
+    });
+
+    let policy = BindPolicy {
+        retry_for: Duration::from_secs(5),
+        backoff: Duration::from_millis(50),
+        ..BindPolicy::default()
+    };
+    let server = Arc::new(
+        Server::with_bind_policy(&addr, &policy).expect("Failed to start server"),
+    );
+    release_thread.join().unwrap();
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    // Without retrying, a busy address fails right away.
+    let busy = TcpListener::bind("localhost:0").expect("Failed to occupy the address");
+    assert!(
+        Server::new(&busy.local_addr().unwrap().to_string()).is_err(),
+        "Server bound an address that is in use"
+    );
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// Send an echo request over a raw stream, after the given prefix, and wait for the reply.
+// The stream is returned so that the connection stays open.
+fn echo_after_prefix(addr: SocketAddr, prefix: &[u8]) -> (TcpStream, Option<ServerMessage>) {
+    let mut stream = TcpStream::connect(addr).expect("Failed to connect directly to the server");
+    let request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "Hello, World!".to_string(),
+        })),
+        ..Default::default()
+    };
+
+    let mut payload = prefix.to_vec();
+    payload.extend(encode_frame(&request.encode_to_vec()));
+    stream.write_all(&payload).expect("Failed to send the request");
+
+    let Ok(Some(frame)) = read_frame(&stream, DEFAULT_MAX_FRAME_LEN) else {
+        return (stream, None);
+    };
+    let response = ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response");
+    (stream, Some(response))
+}
+
+// The following test is aimed at checking that the clients predating the
+// framing, which send their requests as is, are still served.
+#[test]
+fn test_unframed_client() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    for content in ["Hello", "World"] {
+        let request = ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: content.to_string(),
+            })),
+            ..Default::default()
+        };
+        stream.write_all(&request.encode_to_vec()).expect("Failed to send the request");
+
+        // The response is not framed either.
+        let mut buffer = [0; 512];
+        let bytes_read = stream.read(&mut buffer).expect("Failed to read the response");
+        let response = ServerMessage::decode(&buffer[..bytes_read]).expect("Failed to decode server response");
+        match response.message {
+            Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, content),
+            message => panic!("Expected EchoMessage, but received {:?}", message),
+        }
+    }
+    drop(stream);
+
+    // Framed clients are served as before.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.echo("Framed").expect("Failed to echo"), "Framed");
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that messages larger than the
+// frames the peers accept are fragmented and reassembled.
+#[test]
+fn test_fragmented_messages() {
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_max_frame_len(64)
+            .with_fragment_len(48)
+            .build()
+            .expect("Failed to start server"),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let content = "x".repeat(1000);
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    client.set_fragment_len(Some(48));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.echo(&content).expect("Failed to echo"), content, "Reassembled echo does not match");
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // The reply is fragmented too.
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    let request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage { content })),
+        ..Default::default()
+    };
+    let format = FrameFormat {
+        fragment_len: Some(48),
+        ..FrameFormat::default()
+    };
+    stream.write_all(&format.encode(&request.encode_to_vec())).expect("Failed to send the request");
+    let mut prefix = [0; HEADER_LEN];
+    stream.read_exact(&mut prefix).expect("Failed to read the response");
+    assert_ne!(u32::from_be_bytes(prefix) & FRAGMENT_FLAG, 0, "Reply was not fragmented");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the coalesced replies are held
+// until enough of them are, or until their window elapsed.
+#[test]
+fn test_write_coalescing() {
+    let window = Duration::from_millis(500);
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_write_coalescing(window, 3)
+            .build()
+            .expect("Failed to start server"),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    let echo_frame = |content: &str| {
+        let request = ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: content.to_string(),
+            })),
+            ..Default::default()
+        };
+        encode_frame(&request.encode_to_vec())
+    };
+    let receive_echo = |stream: &TcpStream| {
+        let frame = read_frame(stream, DEFAULT_MAX_FRAME_LEN)
+            .expect("Failed to receive response")
+            .expect("Server disconnected");
+        match ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response").message {
+            Some(server_message::Message::EchoMessage(echo)) => echo.content,
+            message => panic!("Expected EchoMessage, but received {:?}", message),
+        }
+    };
+
+    // The replies to the first two requests are held...
+    (&stream).write_all(&[echo_frame("One"), echo_frame("Two")].concat()).expect("Failed to send the requests");
+    stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    let e = stream.peek(&mut [0; 1]).expect_err("Replies were not held");
+    assert!(matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut), "Unexpected error {:?}", e);
+
+    // ...until the third one is.
+    (&stream).write_all(&echo_frame("Three")).expect("Failed to send the request");
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    for expected in ["One", "Two", "Three"] {
+        assert_eq!(receive_echo(&stream), expected);
+    }
+
+    // A reply no other follows is written once its window elapsed.
+    let sent = Instant::now();
+    (&stream).write_all(&echo_frame("Four")).expect("Failed to send the request");
+    assert_eq!(receive_echo(&stream), "Four");
+    assert!(sent.elapsed() >= time_scale::scale(window), "Reply was not held");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that requests are reassembled
+// whatever the way TCP splits or merges them.
+#[test]
+fn test_request_framing() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let echo_frame = |content: &str| {
+        let request = ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: content.to_string(),
+            })),
+            ..Default::default()
+        };
+        encode_frame(&request.encode_to_vec())
+    };
+    let receive_echo = |stream: &TcpStream| {
+        let frame = read_frame(stream, DEFAULT_MAX_FRAME_LEN)
+            .expect("Failed to receive response")
+            .expect("Server disconnected");
+        match ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response").message {
+            Some(server_message::Message::EchoMessage(echo)) => echo.content,
+            message => panic!("Expected EchoMessage, but received {:?}", message),
+        }
+    };
+
+    // A request split across several writes is answered once complete.
+    let split = echo_frame("Split");
+    for part in split.chunks(3) {
+        stream.write_all(part).expect("Failed to send the request");
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(receive_echo(&stream), "Split");
+
+    // Requests merged in a single write are answered one by one.
+    let mut merged = echo_frame("First");
+    merged.extend(echo_frame("Second"));
+    stream.write_all(&merged).expect("Failed to send the requests");
+    assert_eq!(receive_echo(&stream), "First");
+    assert_eq!(receive_echo(&stream), "Second");
+
+    drop(stream);
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that keepalives are answered
+// without reaching the handlers.
+#[test]
+fn test_keepalive_frames() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    // The keepalive is answered in kind.
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    stream.write_all(&KEEPALIVE_FRAME).expect("Failed to send the keepalive");
+    let mut answer = [0; HEADER_LEN];
+    stream.read_exact(&mut answer).expect("Failed to receive the keepalive");
+    assert_eq!(answer, KEEPALIVE_FRAME, "Keepalive was not answered in kind");
+
+    // An empty frame is an empty request, rather than a keepalive.
+    stream.write_all(&encode_frame(&[])).expect("Failed to send the empty request");
+    let frame = read_frame(&stream, DEFAULT_MAX_FRAME_LEN)
+        .expect("Failed to receive the response")
+        .expect("Server disconnected");
+    match ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response").message {
+        Some(server_message::Message::ErrorMessage(error)) => assert_eq!(error.content, "Bad Request!"),
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+    drop(stream);
+
+    // Neither the keepalives nor their answers count as requests.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.send_keepalive().is_ok(), "Failed to send the keepalive");
+    assert!(client.send_keepalive().is_ok(), "Failed to send the keepalive");
+    let stats = client.call_message(client_message::Message::MyStatsRequest(MyStatsRequest {}));
+    match stats.expect("Failed to receive response for MyStatsRequest").message {
+        Some(server_message::Message::MyStatsResponse(stats)) => {
+            assert_eq!(stats.requests_served, 0, "Keepalives were counted as requests");
+            assert_eq!(stats.bytes_received, 0, "Keepalives were counted as traffic");
+        }
+        message => panic!("Expected MyStatsResponse, but received {:?}", message),
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that, with sync markers, the
+// server skips corrupted bytes up to the next request instead of dropping
+// the connection.
+#[test]
+fn test_sync_marker_resynchronization() {
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_sync_marker()
+            .build()
+            .expect("Failed to start server"),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let format = FrameFormat::SYNCED;
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "Hello, World!".to_string(),
+        })),
+        ..Default::default()
+    };
+    let mut bytes = b"corrupted".to_vec();
+    bytes.extend(format.encode(&request.encode_to_vec()));
+    stream.write_all(&bytes).expect("Failed to send the request");
+
+    // The reply is framed with a marker too.
+    let mut decoder = format.decoder(DEFAULT_MAX_FRAME_LEN);
+    let mut buffer = [0; 512];
+    let frame = loop {
+        let bytes_read = stream.read(&mut buffer).expect("Failed to receive response");
+        assert!(bytes_read > 0, "Server disconnected");
+        if let Some(frame) = decoder.feed(&buffer[..bytes_read]).unwrap().pop() {
+            break frame;
+        }
+    };
+    let response = ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response");
+    assert!(
+        matches!(response.message, Some(server_message::Message::EchoMessage(echo)) if echo.content == "Hello, World!"),
+        "Expected the echo of the request"
+    );
+    assert_eq!(decoder.resyncs(), 0, "Reply was corrupted");
+    assert_eq!(server.usage_totals().resyncs, 1, "Resync was not counted");
+    drop(stream);
+
+    // The client frames its requests the same way once told to.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    client.set_sync_marker(true);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.send_keepalive().is_ok(), "Failed to send the keepalive");
+    assert_eq!(client.add(2, 3).unwrap(), Ok(5));
+    assert_eq!(client.stats().resyncs, 0, "Responses were corrupted");
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that a connection switches to
+// another transport exactly after the upgrade request and its response.
+#[test]
+fn test_transport_upgrade() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    // The request that follows the upgrade request in the same write is of the new transport.
+    let synced = FrameFormat::SYNCED;
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let upgrade_request = ClientMessage {
+        message: Some(client_message::Message::UpgradeRequest(UpgradeRequest {
+            transport: Transport::SyncMarker.into(),
+        })),
+        ..Default::default()
+    };
+    let echo_request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "Upgraded".to_string(),
+        })),
+        ..Default::default()
+    };
+    let mut bytes = encode_frame(&upgrade_request.encode_to_vec());
+    bytes.extend(synced.encode(&echo_request.encode_to_vec()));
+    stream.write_all(&bytes).expect("Failed to send the requests");
+
+    let frame = read_frame(&stream, DEFAULT_MAX_FRAME_LEN)
+        .expect("Failed to receive response")
+        .expect("Server disconnected");
+    match ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response").message {
+        Some(server_message::Message::UpgradeResponse(upgrade)) => {
+            assert_eq!(upgrade.transport(), Transport::SyncMarker, "Unexpected transport")
+        }
+        message => panic!("Expected UpgradeResponse, but received {:?}", message),
+    }
+    let mut decoder = synced.decoder(DEFAULT_MAX_FRAME_LEN);
+    let mut buffer = [0; 512];
+    let frame = loop {
+        let bytes_read = stream.read(&mut buffer).expect("Failed to receive response");
+        assert!(bytes_read > 0, "Server disconnected");
+        if let Some(frame) = decoder.feed(&buffer[..bytes_read]).unwrap().pop() {
+            break frame;
+        }
+    };
+    let response = ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response");
+    assert!(
+        matches!(response.message, Some(server_message::Message::EchoMessage(echo)) if echo.content == "Upgraded"),
+        "Expected the echo of the request"
+    );
+    drop(stream);
+
+    // An unsupported transport leaves the connection as it was.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let unsupported = client.call_message(client_message::Message::UpgradeRequest(UpgradeRequest { transport: 42 }));
+    assert!(
+        matches!(unsupported.unwrap().message, Some(server_message::Message::ErrorMessage(_))),
+        "Unsupported transport was accepted"
+    );
+    assert_eq!(client.add(1, 1).unwrap(), Ok(2));
+
+    // The client follows the upgrade, and so do the notifications of the server.
+    assert!(client.upgrade(Transport::SyncMarker).is_ok(), "Failed to upgrade the connection");
+    assert!(client.send_keepalive().is_ok(), "Failed to send the keepalive");
+    assert_eq!(client.echo("Upgraded").unwrap(), "Upgraded");
+
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    match client.receive().expect("Failed to receive the shut down notification").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.shutdown_reason(), ShutdownReason::Requested, "Unexpected shut down reason")
+        }
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+    assert_eq!(client.stats().resyncs, 0, "Transports were mixed up");
+}
+
+// The following test is aimed at checking that the settings of the
+// builder are applied to the server and its connections.
+#[test]
+fn test_server_builder() {
+    let invalid = Server::builder("localhost:0").with_workers(0).build();
+    assert_eq!(invalid.err().map(|e| e.kind()), Some(ErrorKind::InvalidInput), "Expected no worker to be refused");
+
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_workers(2)
+            .with_read_buffer_size(16)
+            .with_max_connections(1)
+            .with_idle_timeout(Duration::from_millis(300))
+            .with_accept_poll_interval(Duration::from_millis(10))
+            .build()
+            .expect("Failed to start server"),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    // Requests larger than the read buffer take several reads.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let content = "x".repeat(200);
+    let response = client.call_message(client_message::Message::EchoMessage(EchoMessage {
+        content: content.clone(),
+    }));
+    match response.expect("Failed to receive response for EchoMessage").message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, content, "Echoed message content does not match"),
+        message => panic!("Expected EchoMessage, but received {:?}", message),
+    }
+
+    // Connections beyond the limit are refused.
+    let refused = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    refused.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let frame = read_frame(&refused, DEFAULT_MAX_FRAME_LEN)
+        .expect("Failed to receive the refusal")
+        .expect("Server disconnected");
+    match ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.code, ErrorCode::ServerBusy as i32, "Expected SERVER_BUSY")
+        }
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    thread::sleep(Duration::from_millis(100));
+
+    // Idle connections are closed, with a goodbye summing up the session.
+    let idle = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    idle.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let frame = read_frame(&idle, DEFAULT_MAX_FRAME_LEN)
+        .expect("Failed to wait for the server")
+        .expect("Idle connection was closed without a goodbye");
+    match ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response").message {
+        Some(server_message::Message::ByeMessage(bye)) => {
+            let summary = bye.session_summary.expect("Goodbye holds no session summary");
+            assert_eq!(summary.requests_served, 0, "Idle client made no request");
+            assert!(summary.duration_ms >= 300, "Session ended before the idle timeout");
+        }
+        message => panic!("Expected ByeMessage, but received {:?}", message),
+    }
+    let closed = read_frame(&idle, DEFAULT_MAX_FRAME_LEN).expect("Failed to wait for the server");
+    assert!(closed.is_none(), "Idle connection was not closed");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that connections wait for a
+// worker when every worker is busy.
+#[test]
+fn test_worker_backpressure() {
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_workers(1)
+            .build()
+            .expect("Failed to start server"),
+    );
+    let handle = setup_server_thread(server.clone());
+    let echo = |content: &str| {
+        client_message::Message::EchoMessage(EchoMessage {
+            content: content.to_string(),
+        })
+    };
+
+    let mut first = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(first.connect().is_ok(), "Failed to connect to the server");
+    assert!(first.call_message(echo("First")).is_ok(), "Failed to receive response for EchoMessage");
+
+    // The second connection is accepted, but not served while the first holds the worker.
+    let mut second = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(second.connect().is_ok(), "Failed to connect to the server");
+    assert!(second.send(echo("Second")).is_ok(), "Failed to send message");
+    assert!(
+        second.receive_timeout(Duration::from_millis(300)).is_err(),
+        "Queued connection was served"
+    );
+    assert_eq!(server.busy_workers(), 1);
+    assert_eq!(server.queued_connections(), 1);
+
+    // It is served once the worker is released.
+    assert!(first.disconnect().is_ok(), "Failed to disconnect from the server");
+    match second.receive().expect("Failed to receive response for EchoMessage").message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, "Second"),
+        message => panic!("Expected EchoMessage, but received {:?}", message),
+    }
+    assert_eq!(server.queued_connections(), 0);
+    assert!(second.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the real client address
+// is taken from PROXY protocol headers.
+#[test]
+fn test_proxy_protocol() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_proxy_protocol(true),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    // Version 1 header.
+    let (_v1_stream, response) = echo_after_prefix(server.local_addr().unwrap(), b"PROXY TCP4 192.0.2.1 192.0.2.2 5000 8080\r\n");
+    match response.and_then(|response| response.message) {
+        Some(server_message::Message::EchoMessage(echo)) => {
+            assert_eq!(echo.content, "Hello, World!", "Echoed message content does not match");
+        }
+        _ => panic!("Expected EchoMessage, but received a different message"),
+    }
+    let proxied: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+    assert!(server.active_client_addrs().contains(&proxied), "Client address was not taken from the header");
+
+    // Version 2 header, PROXY command over TCP/IPv4.
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    header.extend([0x21, 0x11, 0, 12]);
+    header.extend([198, 51, 100, 7, 127, 0, 0, 1]);
+    header.extend(6000u16.to_be_bytes());
+    header.extend(8080u16.to_be_bytes());
+    let (_v2_stream, response) = echo_after_prefix(server.local_addr().unwrap(), &header);
+    assert!(
+        matches!(response.and_then(|response| response.message), Some(server_message::Message::EchoMessage(_))),
+        "Expected EchoMessage, but received a different message"
+    );
+    let proxied: SocketAddr = "198.51.100.7:6000".parse().unwrap();
+    assert!(server.active_client_addrs().contains(&proxied), "Client address was not taken from the header");
+
+    // Connections without a header are dropped.
+    assert!(echo_after_prefix(server.local_addr().unwrap(), b"").1.is_none(), "Connection without a header was served");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that a connection staying silent
+// instead of sending its PROXY protocol header is dropped.
+#[test]
+fn test_proxy_protocol_silent_connection() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_proxy_protocol(true),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let start = Instant::now();
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    stream
+        .set_read_timeout(Some(time_scale::scale(PROXY_HEADER_TIMEOUT * 5)))
+        .unwrap();
+    match stream.read(&mut [0; 1]) {
+        Ok(0) => {}
+        Err(e) if e.kind() == ErrorKind::ConnectionReset => {}
+        result => panic!("Expected the connection to be closed, but got {:?}", result),
+    }
+    assert!(start.elapsed() >= time_scale::scale(PROXY_HEADER_TIMEOUT), "Connection was dropped before the deadline");
+    assert_eq!(server.active_client_count(), 0, "Silent connection was not released");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that requests the server does
+// not understand can be answered by a custom handler.
+#[test]
+fn test_unknown_message_handler() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_unknown_message_handler(Arc::new(|request: &[u8]| {
+                // Only answer the experimental 0xcafe request.
+                (request == [0xca, 0xfe]).then(|| ServerMessage {
+                    message: Some(server_message::Message::ErrorMessage(ErrorMessage {
+                        content: "Experimental".to_string(),
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                })
+            })),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // The handler answers what it knows, and the server falls back to its
+    // default error for the rest.
+    for (request, expected) in [(&[0xca, 0xfe], "Experimental"), (&[0xde, 0xad], "Bad Request!")] {
+        client.send_raw(request).expect("Failed to send the request");
+        let payload = client.receive_raw().expect("Failed to read response from the server");
+        match ServerMessage::decode(&payload[..]).expect("Failed to decode server response").message {
+            Some(server_message::Message::ErrorMessage(error_message)) => {
+                assert_eq!(error_message.content, expected, "Unexpected error message content");
+            }
+            _ => panic!("Expected ErrorMessage, but received a different message type"),
+        }
+    }
+
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the shut down reason is
+// reported to the clients and to the embedding application.
+#[test]
+fn test_shutdown_reason() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let response = client.call(client_message::Message::EchoMessage(echo_message));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    assert_eq!(server.shutdown_reason(), None, "Running server has a shut down reason");
+
+    // Stop the server and wait for thread to finish
+    server.stop_with_reason(ShutdownReason::AdminCommand);
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+
+    let response = client.receive();
+    assert!(response.is_ok(), "Failed to receive the shut down notification");
+    match response.unwrap().message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.content, "Server is shutting down.", "Unexpected error message content");
+            assert_eq!(error.shutdown_reason(), ShutdownReason::AdminCommand, "Unexpected shut down reason");
+            let summary = error.session_summary.expect("Notification holds no session summary");
+            assert_eq!(summary.requests_served, 1, "Expected the echo to be counted");
+            assert!(summary.bytes_sent > 0 && summary.bytes_received > 0, "Expected the echo to be counted");
+        }
+        _ => panic!("Expected ErrorMessage, but received a different message"),
+    }
+    assert_eq!(server.shutdown_reason(), Some(ShutdownReason::AdminCommand), "Unexpected shut down reason");
+
+    // Operator-initiated stops exit cleanly, failures do not.
+    assert_eq!(ShutdownReason::AdminCommand.exit_code(), 0);
+    assert_eq!(ShutdownReason::Signal.exit_code(), 0);
+    assert_ne!(ShutdownReason::FatalAcceptError.exit_code(), 0);
+    assert_ne!(ShutdownReason::ConfigError.exit_code(), 0);
+
+    assert!(client.disconnect().is_ok(), "Client failed to disconnect properly");
+}
+
+// The following test is aimed at checking that the PID file tells
+// whether an instance is running, and that stale files are replaced.
+#[test]
+fn test_pid_file() {
+    use embedded_recruitment_task::pid_file;
+
+    let path = std::env::temp_dir().join(format!("server-{}.pid", std::process::id()));
+
+    // A file left behind by a process that no longer exists is stale.
+    std::fs::write(&path, "2147483646\n").expect("Failed to write a stale PID file");
+    assert_eq!(pid_file::running_pid(&path).unwrap(), None, "Stale PID file was reported as running");
+
+    let server = Server::new("localhost:0")
+        .expect("Failed to start server")
+        .with_pid_file(&path)
+        .expect("Failed to replace the stale PID file");
+    assert_eq!(server.pid_file(), Some(path.as_path()));
+    assert_eq!(
+        pid_file::running_pid(&path).unwrap(),
+        Some(std::process::id()),
+        "PID file does not point to the running server"
+    );
+
+    // A second instance must refuse to start.
+    let second = Server::new("localhost:0").expect("Failed to start server").with_pid_file(&path);
+    assert!(second.is_err(), "Second instance took over the PID file");
+
+    // Dropping the server removes the file.
+    drop(server);
+    assert!(!path.exists(), "PID file was not removed");
+}
+
+// The following test is aimed at driving the server step by step from a
+// single thread, without running its accept loop.
+#[test]
+fn test_poll_once() {
+    let server = Server::new("localhost:0").expect("Failed to start server");
+
+    // Nothing happens without clients.
+    assert_eq!(server.poll_once().unwrap(), 0, "Unexpected event");
+
+    // The connection is accepted on the next step.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(server.poll_once().unwrap(), 1, "Connection was not accepted");
+    assert_eq!(server.active_client_count(), 1, "Client was not registered");
+
+    // Idle connections do not block the step.
+    assert_eq!(server.poll_once().unwrap(), 0, "Unexpected event");
+
+    // A request is handled by exactly one step.
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    assert!(client.send(client_message::Message::EchoMessage(echo_message)).is_ok(), "Failed to send message");
+    assert_eq!(server.poll_once().unwrap(), 1, "Request was not handled");
+    match client.receive().expect("Failed to receive response for EchoMessage").message {
+        Some(server_message::Message::EchoMessage(echo)) => {
+            assert_eq!(echo.content, "Hello, World!", "Echoed message content does not match");
+        }
+        _ => panic!("Expected EchoMessage, but received a different message"),
+    }
+
+    // Stopping releases the remaining connections.
+    server.stop();
+    assert_eq!(server.poll_once().unwrap(), 1, "Connection was not released");
+    server.assert_quiesced();
+    match client.receive().expect("Failed to receive the shut down notification").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.content, "Server is shutting down.", "Unexpected error message content");
+        }
+        _ => panic!("Expected ErrorMessage, but received a different message"),
+    }
+}
+
+// The following test is aimed at checking that the single-threaded mode
+// keeps partial requests until the rest arrives.
+#[test]
+fn test_poll_once_partial_request() {
+    let server = Server::new("localhost:0").expect("Failed to start server");
+
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(server.poll_once().unwrap(), 1, "Connection was not accepted");
+
+    let request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "Hello, World!".to_string(),
+        })),
+        ..Default::default()
+    };
+    let frame = encode_frame(&request.encode_to_vec());
+    let (head, tail) = frame.split_at(frame.len() / 2);
+
+    // The first segment is buffered without answering.
+    stream.write_all(head).expect("Failed to send the request");
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(server.poll_once().unwrap(), 1, "Partial request was not read");
+    assert_eq!(server.active_client_count(), 1, "Client was dropped on a partial request");
+
+    // The last segment completes the request, which is answered.
+    stream.write_all(tail).expect("Failed to send the request");
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(server.poll_once().unwrap(), 1, "Request was not handled");
+    let frame = read_frame(&stream, DEFAULT_MAX_FRAME_LEN)
+        .expect("Failed to receive response")
+        .expect("Server disconnected");
+    match ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response").message {
+        Some(server_message::Message::EchoMessage(echo)) => {
+            assert_eq!(echo.content, "Hello, World!", "Echoed message content does not match");
+        }
+        message => panic!("Expected EchoMessage, but received {:?}", message),
+    }
+
+    server.stop();
+    assert_eq!(server.poll_once().unwrap(), 1, "Connection was not released");
+    server.assert_quiesced();
+}
+
+// The following test is aimed at checking that resources still held by
+// the server are reported.
+#[test]
+fn test_leaks_reported() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    assert!(server.leaks().is_empty(), "Server that never ran was reported: {:?}", server.leaks());
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let response = client.call(client_message::Message::EchoMessage(echo_message));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+
+    // While running with a client, the server holds a connection and a worker.
+    let leaks = server.leaks();
+    assert!(leaks.iter().any(|leak| leak.contains("still running")), "Running server was not reported");
+    assert!(leaks.iter().any(|leak| leak.contains("still registered")), "Connection was not reported");
+    assert!(leaks.iter().any(|leak| leak.contains("still busy")), "Worker was not reported");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    server.assert_quiesced();
+
+    assert!(client.disconnect().is_ok(), "Client failed to disconnect properly");
+}
+
+// The following test is aimed at checking that a client can ask for
+// the server's view of its own connection.
+#[test]
+fn test_my_stats() {
+    let clock = Arc::new(ManualClock::new());
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_clock(clock.clone()),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    // The calls of the client are tagged with a request id, copied into the response.
+    let request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(echo_message.clone())),
+        request_id: Some(1),
+        ..Default::default()
+    };
+    let response = client.call(client_message::Message::EchoMessage(echo_message.clone()));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    let response = ServerMessage {
+        message: Some(server_message::Message::EchoMessage(echo_message)),
+        request_id: Some(1),
+        ..Default::default()
+    };
+
+    clock.advance(Duration::from_secs(5));
+
+    let stats = client.call_message(client_message::Message::MyStatsRequest(MyStatsRequest {}));
+    match stats.expect("Failed to receive response for MyStatsRequest").message {
+        Some(server_message::Message::MyStatsResponse(stats)) => {
+            assert_eq!(stats.requests_served, 1, "Requests served mismatch");
+            // Both include the length prefix of the frame.
+            assert_eq!(stats.bytes_received, (HEADER_LEN + request.encoded_len()) as u64, "Bytes received mismatch");
+            assert_eq!(stats.bytes_sent, (HEADER_LEN + response.encoded_len()) as u64, "Bytes sent mismatch");
+            assert_eq!(stats.session_age_ms, 5000, "Session age mismatch");
+        }
+        _ => panic!("Expected MyStatsResponse, but received a different message"),
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that delayed echoes are
+// answered after their delay, without holding back other requests.
+#[test]
+fn test_delayed_echo() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_max_echo_delay(Duration::from_millis(500)),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Make sure that the connection is served before pipelining requests.
+    let echo_message = EchoMessage {
+        content: "Ready".to_string(),
+    };
+    let response = client.call(client_message::Message::EchoMessage(echo_message));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+
+    // The echo sent after the delayed one is answered first.
+    let start = Instant::now();
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Slow".to_string(),
+        delay_ms: 300,
+    };
+    assert!(
+        client.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).is_ok(),
+        "Failed to send message"
+    );
+    let echo_message = EchoMessage {
+        content: "Fast".to_string(),
+    };
+    assert!(client.send(client_message::Message::EchoMessage(echo_message)).is_ok(), "Failed to send message");
+
+    for (content, delayed) in [("Fast", false), ("Slow", true)] {
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::EchoMessage(echo)) => {
+                assert_eq!(echo.content, content, "Echo arrived out of order");
+                assert_eq!(start.elapsed() >= Duration::from_millis(300), delayed, "Echo was not delayed as requested");
+            }
+            _ => panic!("Expected EchoMessage, but received a different message"),
+        }
+    }
+
+    // Delays above the configured bound are rejected.
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Too slow".to_string(),
+        delay_ms: 1000,
+    };
+    let response = client.call(client_message::Message::DelayedEchoRequest(delayed_echo_request));
+    assert!(
+        matches!(response, Ok(client::ServerReply::Error { .. })),
+        "Expected ErrorMessage for a delay above the bound"
+    );
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that nothing follows the
+// acknowledgement of a goodbye, not even a pending delayed echo.
+#[test]
+fn test_no_delayed_echo_after_bye() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Too late".to_string(),
+        delay_ms: 200,
+    };
+    assert!(
+        client.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).is_ok(),
+        "Failed to send message"
+    );
+    let bye = client.call_message(client_message::Message::ByeMessage(ByeMessage::default()));
+    assert!(
+        matches!(bye.expect("Failed to receive the acknowledgement").message, Some(server_message::Message::ByeMessage(_))),
+        "Expected the acknowledgement of the goodbye"
+    );
+
+    // Wait past the delay of the echo.
+    let after_bye = client.receive_timeout(Duration::from_millis(500));
+    assert!(after_bye.is_err(), "Received {:?} after the acknowledgement of the goodbye", after_bye);
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the server describes
+// its protocol to generic tooling.
+#[test]
+fn test_descriptor_request() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    // The descriptor is larger than a single read of the test client.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let response = client
+        .call_message(client_message::Message::DescriptorRequest(DescriptorRequest {}))
+        .expect("Failed to receive response");
+
+    match response.message {
+        Some(server_message::Message::DescriptorResponse(descriptor)) => {
+            let set = prost_types::FileDescriptorSet::decode(descriptor.file_descriptor_set.as_slice())
+                .expect("Failed to decode the descriptor set");
+            let file = &set.file[0];
+            assert_eq!(file.package(), "messages");
+            assert!(
+                file.message_type.iter().any(|message| message.name() == "ClientMessage"),
+                "ClientMessage is not described"
+            );
+            // Along with what the server runs, as logged when it started.
+            let report = server.startup_report().expect("Failed to build the startup report");
+            assert_eq!(descriptor.startup_report, report.to_json().unwrap(), "Startup report mismatch");
+        }
+        _ => panic!("Expected DescriptorResponse, but received a different message"),
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that every response goes
+// through the post-processors, in order.
+#[test]
+fn test_response_post_processors() {
+    let upper_case: ResponsePostProcessor = Arc::new(|response: &mut ServerMessage| {
+        if let Some(server_message::Message::EchoMessage(echo)) = response.message.as_mut() {
+            echo.content = echo.content.to_uppercase();
+        }
+    });
+    let exclaim: ResponsePostProcessor = Arc::new(|response: &mut ServerMessage| {
+        match response.message.as_mut() {
+            Some(server_message::Message::EchoMessage(echo)) => echo.content.push('!'),
+            Some(server_message::Message::ErrorMessage(error)) => error.content.insert_str(0, "server-1: "),
+            _ => {}
+        }
+    });
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_max_connections(1)
+            .build()
+            .expect("Failed to start server")
+            .with_response_post_processor(upper_case)
+            .with_response_post_processor(exclaim),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let echo_message = EchoMessage {
+        content: "Hello".to_string(),
+    };
+    let response = client.call(client_message::Message::EchoMessage(echo_message));
+    match response.expect("Failed to receive response for EchoMessage") {
+        client::ServerReply::Echo(content) => {
+            assert_eq!(content, "HELLO!", "Post-processors were not applied in order");
+        }
+        _ => panic!("Expected EchoMessage, but received a different message"),
+    }
+
+    assert!(client.send_raw(&[0xff, 0xff]).is_ok(), "Failed to send message");
+    let response = ServerMessage::decode(client.receive_raw().expect("Failed to receive response").as_slice());
+    match response.expect("Failed to decode response").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.content, "server-1: Bad Request!", "Error was not post-processed");
+        }
+        _ => panic!("Expected ErrorMessage, but received a different message"),
+    }
+
+    // So are the messages sent outside of a session, like refusals...
+    let refused = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    refused.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let frame = read_frame(&refused, DEFAULT_MAX_FRAME_LEN)
+        .expect("Failed to receive the refusal")
+        .expect("Server disconnected");
+    match ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.content, "server-1: Server busy", "Refusal was not post-processed");
+        }
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+
+    // ...and shut down notifications.
+    server.stop();
+    let response = ServerMessage::decode(client.receive_raw().expect("Failed to receive the notification").as_slice());
+    match response.expect("Failed to decode response").message {
+        Some(server_message::Message::ErrorMessage(error)) => {
+            assert_eq!(error.content, "server-1: Server is shutting down.", "Notification was not post-processed");
+        }
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+
+    // Wait for thread to finish
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that a client reading none of its
+// responses does not hold back the shut down.
+#[test]
+fn test_stop_with_client_not_reading() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    // The echoes fill the socket buffers, until the worker is blocked writing them.
+    let stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    let request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "x".repeat(60_000),
+        })),
+        ..Default::default()
+    };
+    let frame = encode_frame(&request.encode_to_vec());
+    let writer = thread::spawn(move || while (&stream).write_all(&frame).is_ok() {});
+    thread::sleep(Duration::from_millis(500));
+
+    let start = Instant::now();
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    assert!(start.elapsed() < Duration::from_secs(5), "Client not reading held back the shut down");
+    assert!(writer.join().is_ok(), "Writer thread panicked");
+}
+
+// The following test is aimed at checking that background tasks run with
+// the server and are stopped along with it.
+#[test]
+fn test_background_tasks() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let ticks = Arc::new(AtomicUsize::new(0));
+
+    // Registered before the server runs, the task waits for `run()`.
+    let task_ticks = ticks.clone();
+    server
+        .spawn_background("ticker", move |token| {
+            while !token.wait_timeout(Duration::from_millis(10)) {
+                task_ticks.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+        .expect("Failed to register the background task");
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(ticks.load(Ordering::SeqCst), 0, "Background task started before the server");
+
+    let handle = setup_server_thread(server.clone());
+
+    // Registered while the server runs, the task starts right away.
+    let (started, started_rx) = mpsc::channel();
+    server
+        .spawn_background("waiter", move |token| {
+            started.send(()).unwrap();
+            token.wait();
+        })
+        .expect("Failed to start the background task");
+    started_rx
+        .recv_timeout(Duration::from_secs(1))
+        .expect("Background task did not start");
+
+    thread::sleep(Duration::from_millis(100));
+    assert!(ticks.load(Ordering::SeqCst) > 0, "Background task did not run");
+    assert!(
+        server.leaks().iter().any(|leak| leak.contains("background task ticker")),
+        "Running background task was not reported"
+    );
+
+    // Stopping the server waits for the tasks to return.
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    server.assert_quiesced();
+    let stopped_at = ticks.load(Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(ticks.load(Ordering::SeqCst), stopped_at, "Background task outlived the server");
+
+    // No task can be started once stopped.
+    assert!(
+        server.spawn_background("late", |_| {}).is_err(),
+        "Background task was started on a stopped server"
+    );
+}
+
+// The following test is aimed at checking that pending delayed echoes are
+// dropped when the server stops, rather than holding the connection open.
+#[test]
+fn test_delayed_echo_dropped_on_stop() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Make sure that the connection is served before stopping the server.
+    let echo_message = EchoMessage {
+        content: "Ready".to_string(),
+    };
+    let response = client.call(client_message::Message::EchoMessage(echo_message));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Never".to_string(),
+        delay_ms: 5000,
+    };
+    assert!(
+        client.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).is_ok(),
+        "Failed to send message"
+    );
+    thread::sleep(Duration::from_millis(50));
+
+    // Stop the server and wait for thread to finish
+    let start = Instant::now();
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+
+    // The client is told about the shut down, then disconnected without the echo.
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorMessage(_)) => {}
+        message => panic!("Expected ErrorMessage, but received {:?}", message),
+    }
+    let error = client.receive().expect_err("Received a response after the shut down");
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionAborted, "Connection was not closed");
+    assert!(start.elapsed() < Duration::from_secs(1), "Delayed echo held the connection open");
+}
+
+// The following test is aimed at checking that request metadata reaches
+// the response post-processors and the response.
+#[test]
+fn test_metadata_propagation() {
+    // The post-processor sees the metadata of the request on the response.
+    let post_processor: ResponsePostProcessor = Arc::new(|response: &mut ServerMessage| {
+        if let Some(tenant) = response.metadata.get("tenant").cloned() {
+            response.metadata.insert("served-for".to_string(), tenant);
+        }
+    });
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_response_post_processor(post_processor),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client.set_metadata([("tenant".to_string(), "acme".to_string())].into());
+
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let response = client
+        .call_message(client_message::Message::EchoMessage(echo_message.clone()))
+        .expect("Failed to receive response for EchoMessage");
+    assert_eq!(response.metadata.get("tenant").map(String::as_str), Some("acme"), "Metadata was not propagated");
+    assert_eq!(response.metadata.get("served-for").map(String::as_str), Some("acme"), "Metadata was not post-processed");
+
+    // Batched requests carry their own metadata.
+    let request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(echo_message)),
+        metadata: [("locale".to_string(), "fr".to_string())].into(),
+        ..Default::default()
+    };
+    let response = client
+        .call_message(client_message::Message::BatchRequest(BatchRequest { requests: vec![request] }))
+        .expect("Failed to receive response for BatchRequest");
+    match response.message {
+        Some(server_message::Message::BatchResponse(batch)) => {
+            let metadata = &batch.responses[0].metadata;
+            assert_eq!(metadata.get("locale").map(String::as_str), Some("fr"), "Batched metadata was not propagated");
+            assert!(!metadata.contains_key("tenant"), "Batch metadata leaked into a batched response");
+        }
+        message => panic!("Expected BatchResponse, but received {:?}", message),
+    }
+    assert_eq!(response.metadata.get("tenant").map(String::as_str), Some("acme"), "Batch metadata was not propagated");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that every response carries the
+// id of its request, whatever the order the responses come in.
+#[test]
+fn test_request_ids() {
+    let post_processor: ResponsePostProcessor = Arc::new(|response: &mut ServerMessage| {
+        response.metadata.insert("post-processed".to_string(), "yes".to_string());
+    });
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_response_post_processor(post_processor),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).expect("Failed to connect directly to the server");
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let request = |request_id: Option<u64>, message: Option<client_message::Message>| ClientMessage {
+        message,
+        request_id,
+        ..Default::default()
+    };
+    let requests = [
+        request(
+            Some(1),
+            Some(client_message::Message::DelayedEchoRequest(DelayedEchoRequest {
+                content: "Slow".to_string(),
+                delay_ms: 100,
+            })),
+        ),
+        request(Some(2), Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 }))),
+        request(
+            Some(3),
+            Some(client_message::Message::BatchRequest(BatchRequest {
+                requests: vec![
+                    request(Some(4), Some(client_message::Message::AddRequest(AddRequest { a: 2, b: 2 }))),
+                    request(None, Some(client_message::Message::AddRequest(AddRequest { a: 3, b: 3 }))),
+                ],
+            })),
+        ),
+        // Even a request the server does not understand.
+        request(Some(5), None),
+        request(None, Some(client_message::Message::AddRequest(AddRequest { a: 4, b: 4 }))),
+    ];
+    for request in &requests {
+        stream.write_all(&encode_frame(&request.encode_to_vec())).expect("Failed to send the request");
+    }
+
+    let mut request_ids = Vec::new();
+    for _ in 0..requests.len() {
+        let frame = read_frame(&stream, DEFAULT_MAX_FRAME_LEN)
+            .expect("Failed to receive response")
+            .expect("Server disconnected");
+        let response = ServerMessage::decode(frame.as_slice()).expect("Failed to decode server response");
+        assert!(response.metadata.contains_key("post-processed"), "Response was not post-processed");
+        if let Some(server_message::Message::BatchResponse(batch)) = &response.message {
+            let batched_ids: Vec<_> = batch.responses.iter().map(|response| response.request_id).collect();
+            assert_eq!(batched_ids, vec![Some(4), None], "Batched responses do not match their requests");
+        }
+        request_ids.push(response.request_id);
+    }
+    // The delayed echo comes last.
+    assert_eq!(request_ids, vec![Some(2), Some(3), Some(5), None, Some(1)], "Responses do not match their requests");
+    drop(stream);
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the responses to deprecated
+// requests carry a warning.
+#[test]
+fn test_deprecated_requests() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_deprecated_request("AddRequest", "Use EvalRequest instead"),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let add_request = client_message::Message::AddRequest(AddRequest { a: 1, b: 2 });
+    let response = client.call_message(add_request.clone()).expect("Failed to receive response for AddRequest");
+    assert_eq!(
+        response.metadata.get(DEPRECATED).map(String::as_str),
+        Some("Use EvalRequest instead"),
+        "Deprecated request was not flagged"
+    );
+
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let response = client
+        .call_message(client_message::Message::EchoMessage(echo_message))
+        .expect("Failed to receive response for EchoMessage");
+    assert!(!response.metadata.contains_key(DEPRECATED), "Supported request was flagged");
+
+    // Only the deprecated requests of a batch are flagged.
+    let request = ClientMessage {
+        message: Some(add_request),
+        ..Default::default()
+    };
+    let response = client
+        .call_message(client_message::Message::BatchRequest(BatchRequest { requests: vec![request] }))
+        .expect("Failed to receive response for BatchRequest");
+    match response.message {
+        Some(server_message::Message::BatchResponse(ref batch)) => {
+            assert!(batch.responses[0].metadata.contains_key(DEPRECATED), "Batched request was not flagged")
+        }
+        ref message => panic!("Expected BatchResponse, but received {:?}", message),
+    }
+    assert!(!response.metadata.contains_key(DEPRECATED), "Batch was flagged");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that responses continue the
+// W3C trace of their request.
+#[test]
+fn test_trace_context_propagation() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    client.set_metadata(
+        [
+            (TRACEPARENT.to_string(), traceparent.to_string()),
+            (TRACESTATE.to_string(), "vendor=value".to_string()),
+        ]
+        .into(),
+    );
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let response = client
+        .call_message(client_message::Message::EchoMessage(echo_message.clone()))
+        .expect("Failed to receive response for EchoMessage");
+
+    // The response comes from a span of the server in the same trace.
+    let request_trace = TraceContext::parse(traceparent).unwrap();
+    let response_trace = TraceContext::from_metadata(&response.metadata).expect("Trace context was not propagated");
+    assert_eq!(response_trace.trace_id, request_trace.trace_id, "Response left the trace");
+    assert_ne!(response_trace.parent_id, request_trace.parent_id, "Server did not start its own span");
+    assert_eq!(response.metadata.get(TRACESTATE).map(String::as_str), Some("vendor=value"), "Trace state was not propagated");
+
+    // An invalid trace context is dropped along with its state.
+    client.set_metadata(
+        [
+            (TRACEPARENT.to_string(), "invalid".to_string()),
+            (TRACESTATE.to_string(), "vendor=value".to_string()),
+        ]
+        .into(),
+    );
+    let response = client
+        .call_message(client_message::Message::EchoMessage(echo_message))
+        .expect("Failed to receive response for EchoMessage");
+    assert!(response.metadata.is_empty(), "Invalid trace context was propagated");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that the demo server runs on an
+// ephemeral port without any configuration.
+#[test]
+fn test_demo_server() {
+    let server = Arc::new(Server::demo().expect("Failed to start server"));
+    let addr = server.local_addr().expect("Failed to read the server address");
+    assert!(addr.ip().is_loopback(), "Demo server is reachable from the network");
+    assert_ne!(addr.port(), 0, "Demo server did not pick a port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new(&addr.ip().to_string(), addr.port().into(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.add(2, 40).expect("Failed to receive response for AddRequest"), Ok(42));
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that a single client can be
+// drained while the others are still served.
+#[test]
+fn test_drain_client() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut drained = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(drained.connect().is_ok(), "Failed to connect to the server");
+    let mut other = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(other.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(other.add(1, 2).expect("Failed to receive response for AddRequest"), Ok(3));
+
+    // Leave a response in flight on the drained connection.
+    let echo_message = EchoMessage {
+        content: "Ready".to_string(),
+    };
+    let response = drained.call(client_message::Message::EchoMessage(echo_message));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "In flight".to_string(),
+        delay_ms: 200,
+    };
+    assert!(
+        drained.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).is_ok(),
+        "Failed to send message"
+    );
+    thread::sleep(Duration::from_millis(50));
+
+    let hint = ReconnectHint {
+        retry_after_ms: 500,
+        alternate_addr: "localhost:8081".to_string(),
+    };
+    let addr = drained.local_addr().expect("Failed to read the client address");
+    assert!(server.drain(addr, hint.clone()), "Client was not found");
+    assert!(!server.drain("127.0.0.1:1".parse().unwrap(), hint.clone()), "Unknown client was drained");
+
+    // The response in flight comes first, then the goodbye, then the connection is closed.
+    match drained.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, "In flight"),
+        message => panic!("Expected EchoMessage, but received {:?}", message),
+    }
+    match drained.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ByeMessage(bye)) => {
+            assert_eq!(bye.reconnect_hint, Some(hint), "Reconnect hint does not match");
+            let summary = bye.session_summary.expect("Goodbye holds no session summary");
+            assert_eq!(summary.requests_served, 2, "Expected every response, including the one in flight");
+        }
+        message => panic!("Expected ByeMessage, but received {:?}", message),
+    }
+    let error = drained.receive().expect_err("Drained connection is still open");
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionAborted, "Connection was not closed");
+
+    // The other client is still served.
+    assert_eq!(other.add(3, 4).expect("Failed to receive response for AddRequest"), Ok(7));
+    assert_eq!(server.active_client_addrs().len(), 1, "Drained client is still registered");
+    assert!(other.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that a draining server answers
+// the requests in flight before notifying and closing its clients.
+#[test]
+fn test_drain_and_stop() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = setup_server_thread(server.clone());
+
+    let mut busy = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(busy.connect().is_ok(), "Failed to connect to the server");
+    let mut idle = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(idle.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(idle.add(1, 2).expect("Failed to receive response for AddRequest"), Ok(3));
+
+    // Leave a response in flight on the busy connection.
+    assert_eq!(busy.add(3, 4).expect("Failed to receive response for AddRequest"), Ok(7));
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "In flight".to_string(),
+        delay_ms: 200,
+    };
+    assert!(
+        busy.send(client_message::Message::DelayedEchoRequest(delayed_echo_request)).is_ok(),
+        "Failed to send message"
+    );
+    thread::sleep(Duration::from_millis(50));
+
+    let stopper = {
+        let server = server.clone();
+        thread::spawn(move || server.drain_and_stop(ShutdownReason::AdminCommand, Duration::from_secs(2)))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(server.is_draining(), "Server is not draining");
+
+    let expect_shutdown = |client: &mut client::Client| {
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::ErrorMessage(error)) => {
+                assert_eq!(error.shutdown_reason, ShutdownReason::AdminCommand as i32, "Shut down reason does not match")
+            }
+            message => panic!("Expected ErrorMessage, but received {:?}", message),
+        }
+        let error = client.receive().expect_err("Connection is still open");
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionAborted, "Connection was not closed");
+    };
+
+    // The response in flight comes first, then the notification, then the connection is closed.
+    match busy.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, "In flight"),
+        message => panic!("Expected EchoMessage, but received {:?}", message),
+    }
+    expect_shutdown(&mut busy);
+    expect_shutdown(&mut idle);
+
+    // The server stops once every client was closed, without waiting for the grace period.
+    assert!(stopper.join().is_ok(), "Draining thread panicked");
+    assert_eq!(server.shutdown_reason(), Some(ShutdownReason::AdminCommand));
+    assert!(server.leaks().is_empty(), "Server leaked {:?}", server.leaks());
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+}
+
+// The following test is aimed at checking that a stopped server can be
+// run again.
+#[test]
+fn test_server_restart() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+
+    for run in 0..2 {
+        let handle = setup_server_thread(server.clone());
+
+        let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+        assert!(client.connect().is_ok(), "Failed to connect to the server in run {}", run);
+        assert_eq!(client.add(run, 1).expect("Failed to receive response for AddRequest"), Ok(run + 1));
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+        thread::sleep(Duration::from_millis(50));
+
+        // Stop the server and wait for thread to finish
+        server.stop();
+        assert!(
+            handle.join().is_ok(),
+            "Server thread panicked or failed to join"
+        );
+        assert_eq!(server.shutdown_reason(), Some(ShutdownReason::Requested));
+        assert!(server.leaks().is_empty(), "Server leaked {:?}", server.leaks());
+    }
+}
+
+// The following test is aimed at checking that a server stopped before it runs
+// returns from `run()` right away.
+#[test]
+fn test_stop_before_run() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    server.stop();
+    assert!(server.run().is_ok(), "Server stopped before running failed to run");
+    assert_eq!(server.shutdown_reason(), Some(ShutdownReason::Requested));
+    assert!(server.leaks().is_empty(), "Server leaked {:?}", server.leaks());
+}
+
+// The following test is aimed at checking that a restarted server runs again the
+// background tasks registered for every run, but not the ones registered once.
+#[test]
+fn test_server_restart_background_tasks() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let once_runs = Arc::new(AtomicUsize::new(0));
+    let each_runs = Arc::new(AtomicUsize::new(0));
+
+    let task_runs = once_runs.clone();
+    server
+        .spawn_background("once", move |token| {
+            task_runs.fetch_add(1, Ordering::SeqCst);
+            token.wait();
+        })
+        .expect("Failed to register the background task");
+    let task_runs = each_runs.clone();
+    server
+        .spawn_background_on_each_run("each-run", move |token| {
+            task_runs.fetch_add(1, Ordering::SeqCst);
+            token.wait();
+        })
+        .expect("Failed to register the recurring background task");
+
+    for run in 1..=2 {
+        let handle = setup_server_thread(server.clone());
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(each_runs.load(Ordering::SeqCst), run, "Recurring task not started in run {}", run);
+        assert_eq!(once_runs.load(Ordering::SeqCst), 1, "Task started again in run {}", run);
+
+        server.stop();
+        assert!(
+            handle.join().is_ok(),
+            "Server thread panicked or failed to join"
+        );
+        assert!(server.leaks().is_empty(), "Server leaked {:?}", server.leaks());
+    }
+}
+
+// The following test is aimed at checking that the startup report
+// describes the build and the configuration of the server.
+#[test]
+fn test_startup_report() {
+    let server = Server::builder("localhost:0")
+        .with_workers(3)
+        .with_max_connections(5)
+        .with_idle_timeout(Duration::from_secs(30))
+        .build()
+        .expect("Failed to start server");
+
+    let report = server.startup_report().expect("Failed to build the startup report");
+    assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+    assert!(!report.git_hash.is_empty(), "Git hash is missing");
+    assert_eq!(report.bind_addr, server.local_addr().unwrap());
+    assert_eq!(report.limits.workers, 3);
+    assert_eq!(report.limits.max_connections, Some(5));
+    assert_eq!(report.limits.read_buffer_size, 512);
+    assert_eq!(report.limits.idle_timeout_ms, Some(30_000));
+    assert!(!report.tls, "Connections are not encrypted");
+
+    let json = report.to_json().expect("Failed to render the startup report");
+    assert!(json.contains("\"gitHash\""), "Unexpected JSON rendering: {}", json);
+    assert!(json.contains("\"maxConnections\":5"), "Unexpected JSON rendering: {}", json);
+}
+
+// The following test is aimed at checking that the accept loop wakes up
+// on connections and on stop rather than polling the listener.
+#[test]
+fn test_event_driven_accept() {
+    // Polling that slowly would outlast the timeouts of the test.
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_accept_poll_interval(Duration::from_secs(10))
+            .build()
+            .expect("Failed to start server"),
+    );
+    let handle = setup_server_thread(server.clone());
+    thread::sleep(Duration::from_millis(100));
+
+    for _ in 0..3 {
+        let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+        assert_eq!(client.add(1, 2).expect("Failed to receive response for AddRequest"), Ok(3));
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    }
+
+    // Stop the server and wait for thread to finish
+    let stopped_at = Instant::now();
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    assert!(stopped_at.elapsed() < Duration::from_secs(1), "Stop did not wake up the accept loop");
+}
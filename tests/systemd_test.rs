@@ -0,0 +1,58 @@
+#![cfg(unix)]
+// The notifications are configured through the environment, shared by the whole test
+// binary, so they are tested apart from the other servers.
+
+use embedded_recruitment_task::{clock::ManualClock, server::Server};
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use common::setup_server_thread;
+mod common;
+
+// The following test is aimed at checking the readiness and watchdog
+// notifications sent to systemd.
+#[test]
+fn test_systemd_notifications() {
+    use std::os::unix::net::UnixDatagram;
+
+    // Pretend to be systemd.
+    let path = std::env::temp_dir().join(format!("notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).expect("Failed to bind the notification socket");
+    std::env::set_var("NOTIFY_SOCKET", &path);
+    std::env::set_var("WATCHDOG_USEC", "200000");
+
+    // The watchdog only pings when the clock is advanced.
+    let clock = Arc::new(ManualClock::new());
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_clock(clock.clone()),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    let mut buffer = [0; 64];
+    let mut receive = |timeout| {
+        socket.set_read_timeout(Some(timeout)).unwrap();
+        socket.recv(&mut buffer).ok().map(|len| String::from_utf8_lossy(&buffer[..len]).to_string())
+    };
+    assert_eq!(receive(Duration::from_secs(2)).as_deref(), Some("READY=1"), "Unexpected first notification");
+    assert_eq!(receive(Duration::from_millis(300)), None, "Watchdog was pinged before its interval elapsed");
+
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(receive(Duration::from_secs(2)).as_deref(), Some("WATCHDOG=1"), "Watchdog was not pinged");
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+    assert_eq!(receive(Duration::from_secs(2)).as_deref(), Some("STOPPING=1"), "Stop was not notified");
+
+    std::env::remove_var("NOTIFY_SOCKET");
+    std::env::remove_var("WATCHDOG_USEC");
+    let _ = std::fs::remove_file(&path);
+}
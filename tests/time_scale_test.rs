@@ -0,0 +1,103 @@
+// Only built with the feature, e.g. `cargo test --features time-compression`.
+#![cfg(feature = "time-compression")]
+
+use embedded_recruitment_task::{
+    message::{client_message, server_message, DelayedEchoRequest},
+    server::Server,
+    time_scale,
+};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use embedded_recruitment_task::client;
+
+use common::setup_server_thread;
+mod common;
+
+#[test]
+fn test_time_compression() {
+    time_scale::set_factor(100.0);
+    assert_eq!(time_scale::scale(Duration::from_secs(1)), Duration::from_millis(10));
+
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_idle_timeout(Duration::from_secs(20))
+            .build()
+            .expect("Failed to start server"),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    // A 5 s delayed echo is answered in 50 ms.
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let delayed_echo_request = DelayedEchoRequest {
+        content: "Soon".to_string(),
+        delay_ms: 5000,
+    };
+    let start = Instant::now();
+    let response = client.call_message(client_message::Message::DelayedEchoRequest(delayed_echo_request));
+    match response.expect("Failed to receive response for DelayedEchoRequest").message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, "Soon"),
+        message => panic!("Expected EchoMessage, but received {:?}", message),
+    }
+    assert!(start.elapsed() < Duration::from_millis(500), "Delay was not compressed");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // A client idle for 20 s is said goodbye to after 200 ms.
+    assert_idle_timeout_compressed(server.local_addr().unwrap());
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(
+        handle.join().is_ok(),
+        "Server thread panicked or failed to join"
+    );
+
+    #[cfg(feature = "tokio")]
+    {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build the runtime");
+            runtime.block_on(async {
+                let server = Arc::new(
+                    embedded_recruitment_task::async_server::AsyncServer::bind("localhost:0")
+                        .await
+                        .expect("Failed to start server")
+                        .with_idle_timeout(Duration::from_secs(20)),
+                );
+                sender.send(server.clone()).unwrap();
+                server.run().await.expect("Server encountered an error");
+            });
+        });
+        let server = receiver.recv().expect("Server did not start");
+        assert_idle_timeout_compressed(server.local_addr().unwrap());
+        server.stop();
+        assert!(
+            handle.join().is_ok(),
+            "Server thread panicked or failed to join"
+        );
+    }
+
+    time_scale::set_factor(1.0);
+}
+
+/// Checks that the server at `addr`, with an idle timeout of 20 s, closes an idle
+/// connection well within a second.
+fn assert_idle_timeout_compressed(addr: SocketAddr) {
+    let mut idle_client = client::Client::with_addr(addr, 1000);
+    assert!(idle_client.connect().is_ok(), "Failed to connect to the server");
+    let start = Instant::now();
+    match idle_client.receive().expect("Idle timeout was not compressed").message {
+        Some(server_message::Message::ByeMessage(_)) => {}
+        message => panic!("Expected ByeMessage, but received {:?}", message),
+    }
+    assert!(start.elapsed() < Duration::from_millis(1000), "Idle timeout was not compressed");
+}
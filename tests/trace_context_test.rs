@@ -0,0 +1,50 @@
+use embedded_recruitment_task::trace_context::{TraceContext, TRACEPARENT};
+use std::collections::HashMap;
+
+const TRACEPARENT_VALUE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+#[test]
+fn test_parse_traceparent() {
+    let trace = TraceContext::parse(TRACEPARENT_VALUE).expect("Failed to parse the trace context");
+    assert_eq!(trace.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+    assert_eq!(trace.parent_id, 0x00f067aa0ba902b7);
+    assert!(trace.is_sampled());
+    assert_eq!(trace.to_string(), TRACEPARENT_VALUE);
+
+    // Later versions may append fields, which are dropped when propagating.
+    let future = TraceContext::parse("cc-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra")
+        .expect("Failed to parse a future version");
+    assert!(!future.is_sampled());
+    assert_eq!(future.to_string(), "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00");
+
+    let metadata: HashMap<String, String> = [(TRACEPARENT.to_string(), TRACEPARENT_VALUE.to_string())].into();
+    assert_eq!(TraceContext::from_metadata(&metadata), Some(trace));
+    assert_eq!(TraceContext::from_metadata(&HashMap::new()), None);
+}
+
+#[test]
+fn test_invalid_traceparent() {
+    for traceparent in [
+        "",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        "00-+bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+    ] {
+        assert_eq!(TraceContext::parse(traceparent), None, "Accepted {:?}", traceparent);
+    }
+}
+
+#[test]
+fn test_child_span() {
+    let trace = TraceContext::parse(TRACEPARENT_VALUE).unwrap();
+    let child = trace.child();
+    assert_eq!(child.trace_id, trace.trace_id, "Child left the trace");
+    assert_eq!(child.flags, trace.flags, "Child changed the flags");
+    assert_ne!(child.parent_id, trace.parent_id, "Child reused the span id");
+    assert_ne!(child.parent_id, trace.child().parent_id, "Children share a span id");
+}
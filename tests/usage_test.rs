@@ -0,0 +1,211 @@
+use embedded_recruitment_task::{
+    message::{client_message, server_message, EchoMessage, MyStatsRequest},
+    server::Server,
+    usage::{ClientUsage, UsageReport, UsageReportSink},
+};
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use embedded_recruitment_task::client;
+
+fn usage(port: u16, connected: bool, requests: u64, errors: u64) -> ClientUsage {
+    ClientUsage {
+        addr: ([127, 0, 0, 1], port).into(),
+        connected,
+        requests,
+        bytes_received: 10,
+        bytes_sent: 20,
+        errors,
+    }
+}
+
+#[test]
+fn test_usage_report_rendering() {
+    let report = UsageReport {
+        timestamp: 1700000000,
+        clients: vec![usage(5000, true, 2, 0), usage(5001, false, 3, 1)],
+    };
+
+    assert_eq!(
+        report.to_csv(),
+        "1700000000,127.0.0.1:5000,true,2,10,20,0\n1700000000,127.0.0.1:5001,false,3,10,20,1\n"
+    );
+    assert_eq!(
+        report.to_json().unwrap(),
+        concat!(
+            r#"{"timestamp":1700000000,"clients":["#,
+            r#"{"addr":"127.0.0.1:5000","connected":true,"requests":2,"bytesReceived":10,"bytesSent":20,"errors":0},"#,
+            r#"{"addr":"127.0.0.1:5001","connected":false,"requests":3,"bytesReceived":10,"bytesSent":20,"errors":1}]}"#
+        )
+    );
+
+    // Reports are appended to the files, with a single CSV header.
+    let csv = std::env::temp_dir().join(format!("usage-{}.csv", std::process::id()));
+    let json = std::env::temp_dir().join(format!("usage-{}.jsonl", std::process::id()));
+    let _ = fs::remove_file(&csv);
+    let _ = fs::remove_file(&json);
+    for _ in 0..2 {
+        report.write_to(&UsageReportSink::Csv(csv.clone())).expect("Failed to write CSV report");
+        report.write_to(&UsageReportSink::Json(json.clone())).expect("Failed to write JSON report");
+    }
+    let written = fs::read_to_string(&csv).unwrap();
+    assert_eq!(written, format!("{}{}{}", UsageReport::CSV_HEADER, report.to_csv(), report.to_csv()));
+    let written = fs::read_to_string(&json).unwrap();
+    assert_eq!(written.lines().count(), 2, "Expected one JSON line per report");
+    fs::remove_file(&csv).unwrap();
+    fs::remove_file(&json).unwrap();
+}
+
+#[test]
+fn test_scheduled_usage_report() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = {
+        let reports = reports.clone();
+        UsageReportSink::Callback(Arc::new(move |report: &UsageReport| {
+            reports.lock().unwrap().push(report.clone())
+        }))
+    };
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .with_usage_report(Duration::from_millis(50), sink)
+            .expect("Failed to schedule usage reports"),
+    );
+    let handle = {
+        let server = server.clone();
+        thread::spawn(move || server.run().expect("Server encountered an error"))
+    };
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
+    let response = client.call(client_message::Message::EchoMessage(echo_message));
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    thread::sleep(Duration::from_millis(50));
+    assert!(client.send_raw(&[0xff]).is_ok(), "Failed to send message");
+    assert!(client.receive().is_ok(), "Failed to receive response for the bad request");
+    let stats = client.stats();
+
+    // Connected clients are reported with their counters so far.
+    let report = server.usage_report();
+    assert_eq!(report.clients.len(), 1, "Expected a single client");
+    let connected = &report.clients[0];
+    assert!(connected.connected, "Client was not reported as connected");
+    assert_eq!((connected.requests, connected.errors), (2, 1), "Client usage does not match");
+    assert_eq!(connected.bytes_sent, stats.bytes_received, "Sent bytes do not match");
+
+    // Scheduled reports keep coming while the server runs.
+    thread::sleep(Duration::from_millis(150));
+    assert!(reports.lock().unwrap().len() >= 2, "Usage reports were not scheduled");
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    thread::sleep(Duration::from_millis(100));
+
+    // Stop the server and wait for thread to finish
+    server.stop();
+    assert!(handle.join().is_ok(), "Server thread panicked or failed to join");
+
+    // The departed client is reported once, after it disconnected.
+    let reports = reports.lock().unwrap();
+    let departed: Vec<_> = reports
+        .iter()
+        .flat_map(|report| report.clients.iter())
+        .filter(|client| !client.connected)
+        .collect();
+    assert_eq!(departed.len(), 1, "Departed client was not reported exactly once");
+    // Saying goodbye on disconnect is one more request.
+    assert_eq!((departed[0].requests, departed[0].errors), (3, 1), "Departed client usage does not match");
+    assert!(reports.last().unwrap().clients.is_empty(), "Final report still lists clients");
+}
+
+#[test]
+fn test_usage_totals() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let handle = {
+        let server = server.clone();
+        thread::spawn(move || server.run().expect("Server encountered an error"))
+    };
+
+    // Clients served concurrently, some of which leave before the totals are read.
+    let addr = server.local_addr().unwrap();
+    let clients: Vec<_> = (0..4)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut client = client::Client::with_addr(addr, 1000);
+                assert!(client.connect().is_ok(), "Failed to connect to the server");
+                for _ in 0..10 {
+                    let echo_message = EchoMessage {
+                        content: "Hello, World!".to_string(),
+                    };
+                    let response = client.call(client_message::Message::EchoMessage(echo_message));
+                    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+                }
+                if i % 2 == 0 {
+                    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+                    None
+                } else {
+                    Some(client)
+                }
+            })
+        })
+        .collect();
+    let remaining: Vec<_> = clients.into_iter().filter_map(|client| client.join().unwrap()).collect();
+    thread::sleep(Duration::from_millis(100));
+
+    // Departed and connected clients alike are counted, goodbyes included.
+    let totals = server.usage_totals();
+    assert_eq!(totals.requests, 42, "Total requests do not match");
+    assert_eq!(totals.errors, 0, "Total errors do not match");
+    assert!(totals.bytes_received > 0 && totals.bytes_sent > 0, "Traffic was not counted");
+
+    drop(remaining);
+    server.stop();
+    assert!(handle.join().is_ok(), "Server thread panicked or failed to join");
+}
+
+#[test]
+fn test_usage_counts_framing() {
+    let server = Arc::new(
+        Server::builder("localhost:0")
+            .with_sync_marker()
+            .with_max_frame_len(64)
+            .with_fragment_len(48)
+            .build()
+            .expect("Failed to start server"),
+    );
+    let handle = {
+        let server = server.clone();
+        thread::spawn(move || server.run().expect("Server encountered an error"))
+    };
+
+    let mut client = client::Client::with_addr(server.local_addr().unwrap(), 1000);
+    client.set_sync_marker(true);
+    client.set_fragment_len(Some(48));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    // Fragmented both ways, each fragment with its own marker and headers.
+    for content in ["Hello, World!".to_string(), "x".repeat(200)] {
+        let response = client.call(client_message::Message::EchoMessage(EchoMessage { content }));
+        assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    }
+
+    // The stats request does not report itself.
+    let traffic = client.stats();
+    let response = client.call_message(client_message::Message::MyStatsRequest(MyStatsRequest {}));
+    match response.expect("Failed to receive response for MyStatsRequest").message {
+        Some(server_message::Message::MyStatsResponse(stats)) => {
+            assert_eq!(stats.bytes_received, traffic.bytes_sent, "Bytes received mismatch");
+            assert_eq!(stats.bytes_sent, traffic.bytes_received, "Bytes sent mismatch");
+        }
+        _ => panic!("Expected MyStatsResponse, but received a different message"),
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    assert!(handle.join().is_ok(), "Server thread panicked or failed to join");
+}